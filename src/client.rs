@@ -1,6 +1,14 @@
+use core::cell::Cell;
 use core::future::Future;
 
-use crate::components::CfuComponentTraits;
+use crate::components::{CfuComponentInfo, CfuComponentTraits};
+use crate::protocol_definitions::{
+    CfuCommand, CfuProtocolError, CfuUpdateContentResponseStatus, FwUpdateContentCommand, FwUpdateContentResponse,
+    FwUpdateOffer, FwUpdateOfferExtended, FwUpdateOfferResponse, HostToken, OfferCommandExtendedCodeValues,
+    OfferInformationCodeValues, OfferRejectReason, OfferStatus, DEFAULT_DATA_LENGTH, FW_UPDATE_FLAG_FIRST_BLOCK,
+    FW_UPDATE_FLAG_LAST_BLOCK, PROTOCOL_VER,
+};
+use crate::trace;
 
 /// CfuReceiveContent trait defines behavior needed for a Cfu client (receiver) to process CFU commands
 /// E is an error type that can be defined by the implementor
@@ -18,4 +26,1067 @@ pub trait CfuReceiveContent<T, C, E: Default> {
         args: Option<T>,
         primary_component: impl CfuComponentTraits,
     ) -> impl Future<Output = Result<(), E>>;
+
+    /// Called once an offer has been accepted, before any content for it arrives
+    /// Lets components reserve resources (e.g. pre-erase a bank) ahead of storage_prepare() on the first block
+    /// Default implementation does nothing
+    fn on_offer_accepted(&self, _offer: &FwUpdateOffer) -> impl Future<Output = Result<(), E>> {
+        async { Ok(()) }
+    }
+
+    /// Asks the component whether it takes the offer and builds the response to send back to the Host
+    /// An offer with force_ignore_version set is accepted without the component's version check
+    /// on_offer_accepted() is run before responding to an accepted offer
+    fn evaluate_offer(
+        &self,
+        offer: &FwUpdateOffer,
+        component: &impl CfuComponentInfo,
+    ) -> impl Future<Output = Result<FwUpdateOfferResponse, E>> {
+        async move {
+            let token = offer.component_info.token;
+            let validity = if offer.component_info.byte1.force_ignore_version() {
+                trace!(
+                    "offer for component {} ignores the version",
+                    component.get_component_id()
+                );
+                Ok(OfferStatus::Accept)
+            } else {
+                component.is_offer_valid().await
+            };
+            match validity {
+                Ok(OfferStatus::Accept) => {
+                    self.on_offer_accepted(offer).await?;
+                    Ok(FwUpdateOfferResponse::new_accept(token))
+                }
+                Ok(status) => Ok(FwUpdateOfferResponse::new_with_failure(
+                    token,
+                    OfferRejectReason::default(),
+                    status,
+                )),
+                Err((status, reason)) => Ok(FwUpdateOfferResponse::new_with_failure(token, reason, status)),
+            }
+        }
+    }
+
+    /// Builds the response to an offer command extended sent by the Host
+    /// Default implementation is always ready to accept offers, so answers OfferNotifyOnReady with CommandReady
+    /// right away, and reports any vendor specific command as not supported
+    fn respond_to_extended_command(
+        &self,
+        cmd: &FwUpdateOfferExtended,
+    ) -> impl Future<Output = Result<FwUpdateOfferResponse, E>> {
+        async move {
+            let token = cmd.component_info.token;
+            let status = match cmd.component_info.code {
+                OfferCommandExtendedCodeValues::OfferNotifyOnReady => OfferStatus::CommandReady,
+                OfferCommandExtendedCodeValues::VendorSpecific(_) => OfferStatus::CmdNotSupported,
+            };
+            Ok(FwUpdateOfferResponse::new_with_failure(
+                token,
+                OfferRejectReason::default(),
+                status,
+            ))
+        }
+    }
+}
+
+/// A client's decision on an offer, see `respond_to_offer`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OfferDecision {
+    Accept,
+    Reject(OfferRejectReason),
+    /// The component is busy, the Host must offer it again once it is ready
+    Busy,
+    /// The component skips the offer, the Host must offer it again later
+    Skip,
+}
+
+/// Builds the response to an offer, ready to hand to the transport
+/// The offer's token is copied into the response and reserved fields are zeroed
+pub fn respond_to_offer(
+    offer: &FwUpdateOffer,
+    decision: OfferDecision,
+) -> [u8; FwUpdateOfferResponse::SERIALIZED_SIZE] {
+    let token = offer.component_info.token;
+    let resp = match decision {
+        OfferDecision::Accept => FwUpdateOfferResponse::new_accept(token),
+        OfferDecision::Reject(reason) => FwUpdateOfferResponse::new_with_failure(token, reason, OfferStatus::Reject),
+        OfferDecision::Busy => {
+            FwUpdateOfferResponse::new_with_failure(token, OfferRejectReason::default(), OfferStatus::Busy)
+        }
+        OfferDecision::Skip => {
+            FwUpdateOfferResponse::new_with_failure(token, OfferRejectReason::default(), OfferStatus::Skip)
+        }
+    };
+    (&resp).into()
+}
+
+/// Checks that an offer was sent by a Host speaking this crate's protocol revision, PROTOCOL_VER
+/// Returns the response rejecting the offer with CmdNotSupported if the revisions don't match, so the client can
+/// send it back without evaluating the offer any further
+pub fn check_offer_protocol_version(offer: &FwUpdateOffer) -> Result<(), [u8; FwUpdateOfferResponse::SERIALIZED_SIZE]> {
+    let version = offer.protocol_version();
+    if version == PROTOCOL_VER {
+        return Ok(());
+    }
+    trace!("offer uses protocol version {}, expected {}", version, PROTOCOL_VER);
+    let resp = FwUpdateOfferResponse::new_with_failure(
+        offer.component_info.token,
+        OfferRejectReason::default(),
+        OfferStatus::CmdNotSupported,
+    );
+    Err((&resp).into())
+}
+
+/// Size of every response a StandardClient sends back to the Host
+pub const STANDARD_RESPONSE_SIZE: usize = FwUpdateOfferResponse::SERIALIZED_SIZE;
+
+/// Progress of a StandardClient through an update
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum ContentState {
+    /// No offer has been accepted, content is rejected
+    #[default]
+    Idle,
+    /// An offer was accepted, waiting for the first block
+    OfferAccepted,
+    /// Receiving content, the next block must carry this sequence number
+    Receiving(u16),
+    /// The last block, carrying this sequence number, was handled and answered with this status
+    /// A re-sent last block gets the same answer without the image being committed again
+    Finished(u16, CfuUpdateContentResponseStatus),
+}
+
+/// CfuReceiveContent implementation for the standard CFU command set, updating a single component
+/// Decodes each frame from the Host, dispatches offers to evaluate_offer() and feeds content blocks to the
+/// component's storage, producing the response frame to send back. Content that arrives before an offer was accepted
+/// is answered with ErrorNoOffer, and content that arrives out of order with ErrorInvalid.
+/// The accepted offer belongs to the Host that sent it: an offer from a host with a different token drops it.
+/// Offers from a Host speaking another protocol revision are answered with CmdNotSupported without being evaluated.
+/// An image staged without the last block flag is committed by the crate's ACTIVATE_STAGED_IMAGE extended command.
+pub struct StandardClient<C> {
+    component: C,
+    state: Cell<ContentState>,
+    /// Token of the Host whose offer put the client in its current state, None while Idle
+    offer_token: Cell<Option<HostToken>>,
+    response: Cell<Option<[u8; STANDARD_RESPONSE_SIZE]>>,
+}
+
+impl<C: CfuComponentTraits> StandardClient<C> {
+    pub fn new(component: C) -> Self {
+        Self {
+            component,
+            state: Cell::new(ContentState::Idle),
+            offer_token: Cell::new(None),
+            response: Cell::new(None),
+        }
+    }
+
+    /// The component being updated
+    pub fn component(&self) -> &C {
+        &self.component
+    }
+
+    /// Takes the response to the last command handled by process_command()
+    pub fn take_response(&self) -> Option<[u8; STANDARD_RESPONSE_SIZE]> {
+        self.response.take()
+    }
+
+    /// Handles a single frame from the Host and returns the response frame to send back
+    /// Returns BadResponse if the frame is not a standard command, or DeserializeField if one of its fields is invalid
+    pub async fn handle_frame(&self, frame: &[u8]) -> Result<[u8; STANDARD_RESPONSE_SIZE], CfuProtocolError> {
+        let resp = match CfuCommand::decode(frame)? {
+            CfuCommand::Content(cmd) => return Ok((&self.handle_content(&cmd).await).into()),
+            CfuCommand::Information(info) => {
+                if info.component_info.code == OfferInformationCodeValues::StartEntireTransaction {
+                    self.reset();
+                }
+                FwUpdateOfferResponse::new_accept(info.component_info.token)
+            }
+            CfuCommand::Extended(cmd)
+                if cmd.component_info.code == OfferCommandExtendedCodeValues::ACTIVATE_STAGED_IMAGE =>
+            {
+                self.activate_staged(cmd.component_info.token).await
+            }
+            CfuCommand::Extended(cmd) => self
+                .respond_to_extended_command(&cmd)
+                .await
+                .map_err(|_| CfuProtocolError::BadResponse)?,
+            CfuCommand::Offer(offer) => {
+                if let Err(resp) = check_offer_protocol_version(&offer) {
+                    return Ok(resp);
+                }
+                self.handle_offer(&offer).await
+            }
+        };
+        Ok((&resp).into())
+    }
+
+    /// Forgets any accepted offer, content is refused until another one is accepted
+    fn reset(&self) {
+        self.state.set(ContentState::Idle);
+        self.offer_token.set(None);
+    }
+
+    async fn handle_offer(&self, offer: &FwUpdateOffer) -> FwUpdateOfferResponse {
+        let token = offer.component_info.token;
+        if self.offer_token.get().is_some_and(|accepted| accepted != token) {
+            trace!("offer from another host, dropping the accepted offer");
+            self.reset();
+        }
+        if offer.component_info.component_id != self.component.get_component_id() {
+            return FwUpdateOfferResponse::new_with_failure(
+                token,
+                OfferRejectReason::InvalidComponent,
+                OfferStatus::Reject,
+            );
+        }
+        let resp = match self.evaluate_offer(offer, &self.component).await {
+            Ok(resp) => resp,
+            Err(()) => {
+                FwUpdateOfferResponse::new_with_failure(token, OfferRejectReason::default(), OfferStatus::Reject)
+            }
+        };
+        if resp.status == OfferStatus::Accept {
+            self.state.set(ContentState::OfferAccepted);
+            self.offer_token.set(Some(token));
+        }
+        resp
+    }
+
+    async fn handle_content(&self, cmd: &FwUpdateContentCommand) -> FwUpdateContentResponse {
+        let seq = cmd.header.sequence_num;
+        let status = self.write_content(cmd).await;
+        if status != CfuUpdateContentResponseStatus::Success {
+            trace!("content block {} failed", seq);
+        }
+        FwUpdateContentResponse::new(seq, status)
+    }
+
+    async fn write_content(&self, cmd: &FwUpdateContentCommand) -> CfuUpdateContentResponseStatus {
+        let seq = cmd.header.sequence_num;
+        let first = cmd.header.flags & FW_UPDATE_FLAG_FIRST_BLOCK != 0;
+        let last = cmd.header.flags & FW_UPDATE_FLAG_LAST_BLOCK != 0;
+        let expected = match (self.state.get(), first) {
+            (ContentState::Idle, _) => return CfuUpdateContentResponseStatus::ErrorNoOffer,
+            (ContentState::OfferAccepted, true) => 0,
+            (ContentState::Receiving(next), false) => next,
+            // the Host lost our answer to the last block, repeat it rather than swapping banks twice
+            (ContentState::Finished(finished, status), _) if last && seq == finished => {
+                trace!("last block {} re-sent, not committing again", seq);
+                return status;
+            }
+            _ => return CfuUpdateContentResponseStatus::ErrorInvalid,
+        };
+        if seq != expected {
+            return CfuUpdateContentResponseStatus::ErrorInvalid;
+        }
+
+        // every block carries data, CFU has no empty blocks
+        let Some(data) = cmd.payload().filter(|data| !data.is_empty()) else {
+            return CfuUpdateContentResponseStatus::ErrorInvalid;
+        };
+        let offset = seq as usize * DEFAULT_DATA_LENGTH;
+        if !self.is_writable(offset, data.len()) {
+            trace!("block {} lands outside the writable range", seq);
+            return CfuUpdateContentResponseStatus::ErrorInvalidAddr;
+        }
+        // both failures are retryable, the state is left as is so the Host can re-send the same block
+        if first && self.component.storage_prepare().await.is_err() {
+            return CfuUpdateContentResponseStatus::ErrorPrepare;
+        }
+        if self.component.storage_write_block(offset, data).await.is_err() {
+            return CfuUpdateContentResponseStatus::ErrorWrite;
+        }
+
+        if last {
+            let status = self.commit_image(seq).await;
+            self.state.set(ContentState::Finished(seq, status));
+            return status;
+        }
+        self.state.set(ContentState::Receiving(seq.wrapping_add(1)));
+        CfuUpdateContentResponseStatus::Success
+    }
+
+    /// Commits an image the Host staged without the last block flag, see `CfuUpdater::activate`
+    /// Only the Host whose offer the image was written for can activate it, and only once at least one block was
+    /// received. The image is verified before it is committed, and the activation is rejected if it fails
+    async fn activate_staged(&self, token: HostToken) -> FwUpdateOfferResponse {
+        let staged = match self.state.get() {
+            ContentState::Receiving(next) if next > 0 && self.offer_token.get() == Some(token) => next - 1,
+            _ => {
+                trace!("no staged image to activate");
+                return FwUpdateOfferResponse::new_with_failure(
+                    token,
+                    OfferRejectReason::default(),
+                    OfferStatus::Reject,
+                );
+            }
+        };
+        let status = self.commit_image(staged).await;
+        self.state.set(ContentState::Finished(staged, status));
+        if status != CfuUpdateContentResponseStatus::Success {
+            return FwUpdateOfferResponse::new_with_failure(token, OfferRejectReason::default(), OfferStatus::Reject);
+        }
+        FwUpdateOfferResponse::new_accept(token)
+    }
+
+    /// Whether every byte of a block of `len` bytes written at `offset` lands inside the component's writable range
+    fn is_writable(&self, offset: usize, len: usize) -> bool {
+        let (start, end) = self.component.writable_range();
+        let (Ok(first), Ok(len)) = (u32::try_from(offset), u32::try_from(len)) else {
+            return false;
+        };
+        first
+            .checked_add(len.saturating_sub(1))
+            .is_some_and(|last| start <= first && last <= end)
+    }
+
+    /// Verifies the image ending at block seq and commits it
+    async fn commit_image(&self, seq: u16) -> CfuUpdateContentResponseStatus {
+        // the swap is only committed once the whole image checks out
+        if let Err(e) = self.component.storage_verify().await {
+            trace!("image ending at block {} failed verification", seq);
+            return e.into();
+        }
+        if self.component.storage_finalize().await.is_err() {
+            return CfuUpdateContentResponseStatus::ErrorComplete;
+        }
+        CfuUpdateContentResponseStatus::Success
+    }
+}
+
+impl<C: CfuComponentTraits> CfuReceiveContent<(), &[u8], ()> for StandardClient<C> {
+    /// Handles a frame from the Host, the response is kept for take_response()
+    async fn process_command(&self, _args: Option<()>, cmd: &[u8]) -> Result<(), ()> {
+        let resp = self.handle_frame(cmd).await.map_err(|_| ())?;
+        self.response.set(Some(resp));
+        Ok(())
+    }
+
+    async fn prepare_components(
+        &self,
+        _args: Option<()>,
+        primary_component: impl CfuComponentTraits,
+    ) -> Result<(), ()> {
+        primary_component.storage_prepare().await.map_err(|_| ())
+    }
+}
+
+/// Errors reported by ContentReassembler
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReassemblyError {
+    /// A block with this sequence number was already received
+    Duplicate(u16),
+    /// The block comes after the block flagged as last
+    PastLastBlock(u16),
+    /// The block carries more than DEFAULT_DATA_LENGTH bytes
+    BlockTooLong(u16),
+    /// Every slot for out of order blocks is taken
+    Full,
+}
+
+/// A content block released by ContentReassembler in sequence order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReassembledBlock {
+    pub sequence: u16,
+    pub data: heapless::Vec<u8, DEFAULT_DATA_LENGTH>,
+}
+
+/// Puts content blocks that a transport delivers out of order back in sequence order
+/// Up to N blocks can wait for a gap before them to be filled
+#[derive(Clone, Debug, Default)]
+pub struct ContentReassembler<const N: usize> {
+    /// Sequence number of the next block to release
+    next_sequence: u16,
+    /// Sequence number of the block flagged as last, once it has arrived
+    last_sequence: Option<u16>,
+    /// Blocks received but not yet released, keyed by sequence number
+    pending: heapless::LinearMap<u16, heapless::Vec<u8, DEFAULT_DATA_LENGTH>, N>,
+}
+
+impl<const N: usize> ContentReassembler<N> {
+    pub fn new() -> Self {
+        Self {
+            next_sequence: 0,
+            last_sequence: None,
+            pending: heapless::LinearMap::new(),
+        }
+    }
+
+    /// Accepts the block with the given sequence number, `last` is set if it carries the last block flag
+    pub fn insert(&mut self, sequence: u16, data: &[u8], last: bool) -> Result<(), ReassemblyError> {
+        if sequence < self.next_sequence || self.pending.contains_key(&sequence) {
+            trace!("duplicate content block {}", sequence);
+            return Err(ReassemblyError::Duplicate(sequence));
+        }
+        if self.last_sequence.is_some_and(|last_sequence| sequence > last_sequence)
+            || (last && self.pending.keys().any(|pending| *pending > sequence))
+        {
+            return Err(ReassemblyError::PastLastBlock(sequence));
+        }
+        let data = heapless::Vec::from_slice(data).map_err(|_| ReassemblyError::BlockTooLong(sequence))?;
+        self.pending.insert(sequence, data).map_err(|_| ReassemblyError::Full)?;
+        if last {
+            self.last_sequence = Some(sequence);
+        }
+        Ok(())
+    }
+
+    /// Accepts a content command as received from the Host
+    pub fn insert_command(&mut self, cmd: &FwUpdateContentCommand) -> Result<(), ReassemblyError> {
+        let data = cmd
+            .payload()
+            .ok_or(ReassemblyError::BlockTooLong(cmd.header.sequence_num))?;
+        self.insert(
+            cmd.header.sequence_num,
+            data,
+            cmd.header.flags & FW_UPDATE_FLAG_LAST_BLOCK != 0,
+        )
+    }
+
+    /// Releases the next block in sequence order if it has arrived
+    /// Calling this until it returns None drains the contiguous prefix that can be flushed to storage
+    pub fn pop_ready(&mut self) -> Option<ReassembledBlock> {
+        let sequence = self.next_sequence;
+        let data = self.pending.remove(&sequence)?;
+        self.next_sequence = sequence.wrapping_add(1);
+        Some(ReassembledBlock { sequence, data })
+    }
+
+    /// Number of blocks that can be released in order right now
+    pub fn ready_len(&self) -> usize {
+        (self.next_sequence..=u16::MAX)
+            .take_while(|sequence| self.pending.contains_key(sequence))
+            .count()
+    }
+
+    /// Returns whether the last block and every block before it have arrived
+    pub fn is_complete(&self) -> bool {
+        self.last_sequence
+            .is_some_and(|last| (self.next_sequence..=last).all(|sequence| self.pending.contains_key(&sequence)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use core::cell::{Cell, RefCell};
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::components::{CfuComponentStorage, CfuComponentTraits, ImageVerifyError};
+    use crate::host::{CfuUpdateContent, CfuUpdater, ContentBlockEncoder, NoHooks};
+    use crate::image::SliceImage;
+    use crate::loopback::{LoopbackWriter, RamComponent};
+    use crate::protocol_definitions::{
+        CfuProtocolError, ComponentId, FwUpdateOfferInformation, FwVersion, HostToken, OfferExtendedComponentInfo,
+        OfferInformationComponentInfo, OfferOutcome, SpecialComponentIds, MAX_SUBCMPT_COUNT,
+    };
+    use crate::writer::CfuWriterError;
+
+    #[derive(Default)]
+    struct MockComponent {
+        reject: bool,
+    }
+
+    impl CfuComponentInfo for MockComponent {
+        async fn get_fw_version(&self) -> Result<FwVersion, CfuProtocolError> {
+            Ok(FwVersion::default())
+        }
+
+        fn get_component_id(&self) -> ComponentId {
+            1
+        }
+
+        fn offered_version(&self) -> FwVersion {
+            FwVersion::default()
+        }
+
+        async fn is_offer_valid(&self) -> Result<OfferStatus, (OfferStatus, OfferRejectReason)> {
+            if self.reject {
+                Err((OfferStatus::Reject, OfferRejectReason::OldFw))
+            } else {
+                Ok(OfferStatus::Accept)
+            }
+        }
+
+        fn is_dual_bank(&self) -> bool {
+            false
+        }
+
+        fn get_subcomponents(&self) -> [Option<ComponentId>; MAX_SUBCMPT_COUNT] {
+            [None; MAX_SUBCMPT_COUNT]
+        }
+    }
+
+    impl CfuComponentStorage for MockComponent {
+        async fn storage_prepare(&self) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        async fn storage_write(&self) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        async fn storage_finalize(&self) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+    }
+
+    impl CfuComponentTraits for MockComponent {}
+
+    /// Client that counts how many times on_offer_accepted fired
+    #[derive(Default)]
+    struct MockClient {
+        accepted: Cell<usize>,
+    }
+
+    impl CfuReceiveContent<(), (), ()> for MockClient {
+        async fn process_command(&self, _args: Option<()>, _cmd: ()) -> Result<(), ()> {
+            Ok(())
+        }
+
+        async fn prepare_components(
+            &self,
+            _args: Option<()>,
+            _primary_component: impl CfuComponentTraits,
+        ) -> Result<(), ()> {
+            Ok(())
+        }
+
+        async fn on_offer_accepted(&self, _offer: &FwUpdateOffer) -> Result<(), ()> {
+            self.accepted.set(self.accepted.get() + 1);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_on_offer_accepted_fires_only_on_accept() {
+        let client = MockClient::default();
+        let offer = FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::new(0x01000000), 0, 0);
+
+        let resp = block_on(client.evaluate_offer(&offer, &MockComponent { reject: true })).unwrap();
+        assert_eq!(resp.status, OfferStatus::Reject);
+        assert_eq!(resp.reject_reason, OfferRejectReason::OldFw);
+        assert_eq!(client.accepted.get(), 0);
+
+        let resp = block_on(client.evaluate_offer(&offer, &MockComponent { reject: false })).unwrap();
+        assert_eq!(resp.status, OfferStatus::Accept);
+        assert_eq!(resp.token, HostToken::Driver);
+        assert_eq!(client.accepted.get(), 1);
+    }
+
+    #[test]
+    fn test_evaluate_offer_version_check() {
+        let client = MockClient::default();
+        let evaluate = |current: u32, offered: u32, force: bool| {
+            let component = RamComponent::<0>::new(1, FwVersion::new(current), FwVersion::new(offered));
+            let mut offer = FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::new(offered), 0, 0);
+            offer.component_info = offer.component_info.with_flags(force, false);
+            let resp = block_on(client.evaluate_offer(&offer, &component)).unwrap();
+            (resp.status, resp.reject_reason)
+        };
+
+        assert_eq!(
+            evaluate(0x02000000, 0x01000000, false),
+            (OfferStatus::Reject, OfferRejectReason::OldFw)
+        );
+        assert_eq!(evaluate(0x01000000, 0x01000000, false).0, OfferStatus::Skip);
+        assert_eq!(evaluate(0x01000000, 0x02000000, false).0, OfferStatus::Accept);
+        // the Host can force the same version, or an older one, to be installed
+        assert_eq!(evaluate(0x01000000, 0x01000000, true).0, OfferStatus::Accept);
+        assert_eq!(evaluate(0x02000000, 0x01000000, true).0, OfferStatus::Accept);
+    }
+
+    #[test]
+    fn test_respond_to_extended_command() {
+        let client = MockClient::default();
+        let cmd = FwUpdateOfferExtended::new(OfferExtendedComponentInfo::new(
+            HostToken::Tool,
+            SpecialComponentIds::Command,
+            OfferCommandExtendedCodeValues::OfferNotifyOnReady,
+        ));
+        let resp = block_on(client.respond_to_extended_command(&cmd)).unwrap();
+        assert_eq!(resp.status, OfferStatus::CommandReady);
+        assert_eq!(resp.token, HostToken::Tool);
+
+        let cmd = FwUpdateOfferExtended::new(OfferExtendedComponentInfo::new(
+            HostToken::Tool,
+            SpecialComponentIds::Command,
+            OfferCommandExtendedCodeValues::VendorSpecific(0x80),
+        ));
+        let resp = block_on(client.respond_to_extended_command(&cmd)).unwrap();
+        assert_eq!(resp.status, OfferStatus::CmdNotSupported);
+    }
+
+    /// Component that records the blocks written to it
+    #[derive(Default)]
+    struct RecordingComponent {
+        /// (offset, length) of each block written
+        writes: RefCell<Vec<(usize, usize)>>,
+        finalized: Cell<usize>,
+        /// Result of verifying the written image, None passes
+        verify_error: Option<ImageVerifyError>,
+        /// Writable range reported to the client, None for the default
+        writable: Option<(u32, u32)>,
+    }
+
+    impl CfuComponentInfo for RecordingComponent {
+        async fn get_fw_version(&self) -> Result<FwVersion, CfuProtocolError> {
+            Ok(FwVersion::default())
+        }
+
+        fn get_component_id(&self) -> ComponentId {
+            1
+        }
+
+        fn offered_version(&self) -> FwVersion {
+            FwVersion::new(0x01000000)
+        }
+
+        fn is_dual_bank(&self) -> bool {
+            false
+        }
+
+        fn get_subcomponents(&self) -> [Option<ComponentId>; MAX_SUBCMPT_COUNT] {
+            [None; MAX_SUBCMPT_COUNT]
+        }
+    }
+
+    impl CfuComponentStorage for RecordingComponent {
+        async fn storage_prepare(&self) -> Result<(), CfuWriterError> {
+            self.writes.borrow_mut().clear();
+            Ok(())
+        }
+
+        async fn storage_write(&self) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        async fn storage_finalize(&self) -> Result<(), CfuWriterError> {
+            self.finalized.set(self.finalized.get() + 1);
+            Ok(())
+        }
+
+        async fn storage_verify(&self) -> Result<(), ImageVerifyError> {
+            self.verify_error.map_or(Ok(()), Err)
+        }
+
+        fn writable_range(&self) -> (u32, u32) {
+            self.writable.unwrap_or((0, u32::MAX))
+        }
+
+        async fn storage_write_block(&self, offset: usize, data: &[u8]) -> Result<(), CfuWriterError> {
+            self.writes.borrow_mut().push((offset, data.len()));
+            Ok(())
+        }
+    }
+
+    impl CfuComponentTraits for RecordingComponent {}
+
+    fn content_status(client: &StandardClient<RecordingComponent>, frame: &[u8]) -> CfuUpdateContentResponseStatus {
+        let resp = block_on(client.handle_frame(frame)).unwrap();
+        FwUpdateContentResponse::try_from(resp).unwrap().status
+    }
+
+    fn offer_status(client: &StandardClient<RecordingComponent>, component_id: ComponentId) -> FwUpdateOfferResponse {
+        let offer = FwUpdateOffer::builder(HostToken::Driver, component_id, FwVersion::new(0x01000000)).build();
+        let frame: [u8; FwUpdateOffer::SERIALIZED_SIZE] = (&offer).into();
+        FwUpdateOfferResponse::try_from(block_on(client.handle_frame(&frame)).unwrap()).unwrap()
+    }
+
+    fn activation_status(client: &StandardClient<RecordingComponent>, token: HostToken) -> OfferStatus {
+        let cmd = FwUpdateOfferExtended::new(OfferExtendedComponentInfo::new(
+            token,
+            SpecialComponentIds::Command,
+            OfferCommandExtendedCodeValues::ACTIVATE_STAGED_IMAGE,
+        ));
+        let frame: [u8; FwUpdateOfferExtended::SERIALIZED_SIZE] = (&cmd).into();
+        FwUpdateOfferResponse::try_from(block_on(client.handle_frame(&frame)).unwrap())
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn test_standard_client_content_flow() {
+        let client = StandardClient::new(RecordingComponent::default());
+        let encoder = ContentBlockEncoder;
+        let chunk = [0xA5; DEFAULT_DATA_LENGTH];
+
+        // no content before an accepted offer
+        assert_eq!(
+            content_status(&client, &encoder.first(chunk)),
+            CfuUpdateContentResponseStatus::ErrorNoOffer
+        );
+        let resp = offer_status(&client, 2);
+        assert_eq!(resp.status, OfferStatus::Reject);
+        assert_eq!(resp.reject_reason, OfferRejectReason::InvalidComponent);
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+
+        let success = CfuUpdateContentResponseStatus::Success;
+        assert_eq!(content_status(&client, &encoder.first(chunk)), success);
+        assert_eq!(content_status(&client, &encoder.middle(chunk, 1)), success);
+        // out of order block is rejected without losing our place
+        assert_eq!(
+            content_status(&client, &encoder.middle(chunk, 3)),
+            CfuUpdateContentResponseStatus::ErrorInvalid
+        );
+        assert_eq!(content_status(&client, &encoder.middle(chunk, 2)), success);
+        assert_eq!(client.component().finalized.get(), 0);
+        assert_eq!(content_status(&client, &encoder.last(chunk, 3, 10)), success);
+
+        assert_eq!(client.component().finalized.get(), 1);
+        assert_eq!(
+            *client.component().writes.borrow(),
+            std::vec![(0, 52), (52, 52), (104, 52), (156, 10)]
+        );
+        // the update is over, further content is rejected
+        assert_eq!(
+            content_status(&client, &encoder.middle(chunk, 4)),
+            CfuUpdateContentResponseStatus::ErrorInvalid
+        );
+    }
+
+    #[test]
+    fn test_standard_client_content_needs_offer_from_same_host() {
+        let client = StandardClient::new(RecordingComponent::default());
+        let encoder = ContentBlockEncoder;
+        let chunk = [0xA5; DEFAULT_DATA_LENGTH];
+        let no_offer = CfuUpdateContentResponseStatus::ErrorNoOffer;
+
+        assert_eq!(content_status(&client, &encoder.middle(chunk, 1)), no_offer);
+        assert_eq!(content_status(&client, &encoder.last(chunk, 0, 10)), no_offer);
+        assert!(client.component().writes.borrow().is_empty());
+
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        assert_eq!(
+            content_status(&client, &encoder.first(chunk)),
+            CfuUpdateContentResponseStatus::Success
+        );
+        // an offer from another host drops the accepted offer, whatever the answer to it
+        let offer = FwUpdateOffer::builder(HostToken::Tool, 2, FwVersion::new(0x01000000)).build();
+        let frame: [u8; FwUpdateOffer::SERIALIZED_SIZE] = (&offer).into();
+        block_on(client.handle_frame(&frame)).unwrap();
+        assert_eq!(content_status(&client, &encoder.middle(chunk, 1)), no_offer);
+
+        // another offer from the same host keeps it
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        assert_eq!(offer_status(&client, 2).status, OfferStatus::Reject);
+        assert_eq!(
+            content_status(&client, &encoder.first(chunk)),
+            CfuUpdateContentResponseStatus::Success
+        );
+    }
+
+    #[test]
+    fn test_standard_client_failed_verification_skips_commit() {
+        let client = StandardClient::new(RecordingComponent {
+            verify_error: Some(ImageVerifyError::Crc),
+            ..Default::default()
+        });
+        let encoder = ContentBlockEncoder;
+        let chunk = [0xA5; DEFAULT_DATA_LENGTH];
+        let success = CfuUpdateContentResponseStatus::Success;
+
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        assert_eq!(content_status(&client, &encoder.first(chunk)), success);
+        assert_eq!(content_status(&client, &encoder.middle(chunk, 1)), success);
+        assert_eq!(
+            content_status(&client, &encoder.last(chunk, 2, 10)),
+            CfuUpdateContentResponseStatus::ErrorCrc
+        );
+        // the image was written but never committed
+        assert_eq!(client.component().writes.borrow().len(), 3);
+        assert_eq!(client.component().finalized.get(), 0);
+        assert_eq!(
+            content_status(&client, &encoder.last(chunk, 3, 0)),
+            CfuUpdateContentResponseStatus::ErrorInvalid
+        );
+        assert_eq!(client.component().finalized.get(), 0);
+
+        // a staged image is verified on activation, before it is committed
+        let client = StandardClient::new(RecordingComponent {
+            verify_error: Some(ImageVerifyError::Signature),
+            ..Default::default()
+        });
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        assert_eq!(content_status(&client, &encoder.first(chunk)), success);
+        assert_eq!(content_status(&client, &encoder.middle(chunk, 1)), success);
+        assert_eq!(activation_status(&client, HostToken::Driver), OfferStatus::Reject);
+        assert_eq!(client.component().finalized.get(), 0);
+    }
+
+    #[test]
+    fn test_standard_client_activation() {
+        let client = StandardClient::new(RecordingComponent::default());
+        let encoder = ContentBlockEncoder;
+        let chunk = [0xA5; DEFAULT_DATA_LENGTH];
+        let success = CfuUpdateContentResponseStatus::Success;
+
+        // nothing staged yet
+        assert_eq!(activation_status(&client, HostToken::Driver), OfferStatus::Reject);
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        assert_eq!(activation_status(&client, HostToken::Driver), OfferStatus::Reject);
+
+        assert_eq!(content_status(&client, &encoder.first(chunk)), success);
+        assert_eq!(content_status(&client, &encoder.middle(chunk, 1)), success);
+        // an empty last block is not a standard frame, only the extended command activates
+        assert_eq!(
+            content_status(&client, &encoder.last(chunk, 2, 0)),
+            CfuUpdateContentResponseStatus::ErrorInvalid
+        );
+        assert_eq!(client.component().finalized.get(), 0);
+        // only the Host that staged the image can activate it
+        assert_eq!(activation_status(&client, HostToken::Tool), OfferStatus::Reject);
+        assert_eq!(activation_status(&client, HostToken::Driver), OfferStatus::Accept);
+        assert_eq!(client.component().finalized.get(), 1);
+        // and only once
+        assert_eq!(activation_status(&client, HostToken::Driver), OfferStatus::Reject);
+        assert_eq!(client.component().finalized.get(), 1);
+    }
+
+    #[test]
+    fn test_standard_client_rejects_blocks_outside_writable_range() {
+        let window_end = 3 * DEFAULT_DATA_LENGTH as u32 - 1;
+        let client = StandardClient::new(RecordingComponent {
+            writable: Some((0, window_end)),
+            ..Default::default()
+        });
+        let encoder = ContentBlockEncoder;
+        let chunk = [0xA5; DEFAULT_DATA_LENGTH];
+        let success = CfuUpdateContentResponseStatus::Success;
+
+        // the range is checked against where the block is written, whatever firmware_address says
+        let mut first = encoder.first(chunk);
+        first[4..8].copy_from_slice(&(window_end + 1).to_le_bytes());
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        assert_eq!(content_status(&client, &first), success);
+        assert_eq!(content_status(&client, &encoder.middle(chunk, 1)), success);
+        // a block that starts inside the window but runs past its end is rejected
+        let client = StandardClient::new(RecordingComponent {
+            writable: Some((0, window_end - 1)),
+            ..Default::default()
+        });
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        assert_eq!(content_status(&client, &encoder.first(chunk)), success);
+        assert_eq!(content_status(&client, &encoder.middle(chunk, 1)), success);
+        assert_eq!(
+            content_status(&client, &encoder.last(chunk, 2, 52)),
+            CfuUpdateContentResponseStatus::ErrorInvalidAddr
+        );
+        assert_eq!(content_status(&client, &encoder.last(chunk, 2, 51)), success);
+        assert_eq!(client.component().writes.borrow().len(), 3);
+        assert_eq!(client.component().finalized.get(), 1);
+    }
+
+    #[test]
+    fn test_standard_client_writable_range_through_updater() {
+        let client = StandardClient::new(RecordingComponent {
+            writable: Some((0, 2 * DEFAULT_DATA_LENGTH as u32 - 1)),
+            ..Default::default()
+        });
+        let mut writer = LoopbackWriter::new(&client);
+        let mut updater = CfuUpdater::new();
+        let data = [0x5A; DEFAULT_DATA_LENGTH * 3];
+
+        let offer = FwUpdateOffer::builder(updater.token(), 1, FwVersion::new(0x01000000)).build();
+        assert_eq!(
+            block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks))
+                .unwrap()
+                .status,
+            OfferStatus::Accept
+        );
+        // the third block falls past the window, the first two are written
+        assert_eq!(
+            block_on(updater.write_data_chunks(&mut writer, SliceImage::new(&data), 1, 0)),
+            Err(CfuProtocolError::UpdateError(1))
+        );
+        assert_eq!(
+            *client.component().writes.borrow(),
+            std::vec![(0, DEFAULT_DATA_LENGTH), (DEFAULT_DATA_LENGTH, DEFAULT_DATA_LENGTH)]
+        );
+        assert_eq!(client.component().finalized.get(), 0);
+    }
+
+    #[test]
+    fn test_standard_client_repeated_last_block_commits_once() {
+        let client = StandardClient::new(RecordingComponent::default());
+        let encoder = ContentBlockEncoder;
+        let chunk = [0xA5; DEFAULT_DATA_LENGTH];
+        let success = CfuUpdateContentResponseStatus::Success;
+
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        assert_eq!(content_status(&client, &encoder.first(chunk)), success);
+        assert_eq!(content_status(&client, &encoder.last(chunk, 1, 10)), success);
+        // the ack was lost and the Host sends the last block again
+        assert_eq!(content_status(&client, &encoder.last(chunk, 1, 10)), success);
+        assert_eq!(client.component().finalized.get(), 1);
+        assert_eq!(client.component().writes.borrow().len(), 2);
+        // a last block that was never sent is still rejected
+        assert_eq!(
+            content_status(&client, &encoder.last(chunk, 2, 10)),
+            CfuUpdateContentResponseStatus::ErrorInvalid
+        );
+
+        // the next accepted offer starts over and commits its own image
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        assert_eq!(
+            content_status(&client, &encoder.last(chunk, 1, 10)),
+            CfuUpdateContentResponseStatus::ErrorInvalid
+        );
+        assert_eq!(content_status(&client, &encoder.first(chunk)), success);
+        assert_eq!(content_status(&client, &encoder.last(chunk, 1, 10)), success);
+        assert_eq!(client.component().finalized.get(), 2);
+    }
+
+    #[test]
+    fn test_offer_to_command_id_is_extended_command() {
+        let client = StandardClient::new(RecordingComponent::default());
+        let mut offer = FwUpdateOffer::builder(HostToken::Driver, 0xFE, FwVersion::new(0x01000000)).build();
+        offer.component_info.segment_number = u8::from(OfferCommandExtendedCodeValues::OfferNotifyOnReady);
+        let frame: [u8; FwUpdateOffer::SERIALIZED_SIZE] = (&offer).into();
+        assert!(matches!(CfuCommand::decode(&frame), Ok(CfuCommand::Extended(_))));
+
+        // answered as a command, never accepted or rejected as an update of the component
+        let resp = FwUpdateOfferResponse::try_from(block_on(client.handle_frame(&frame)).unwrap()).unwrap();
+        assert_eq!(resp.outcome(), OfferOutcome::CommandReady);
+        assert_eq!(offer_status(&client, 0xFE).outcome(), OfferOutcome::NotSupported);
+        assert!(client.component().writes.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_standard_client_rejects_oversized_data_length() {
+        let client = StandardClient::new(RecordingComponent::default());
+        assert_eq!(offer_status(&client, 1).status, OfferStatus::Accept);
+        let mut frame = ContentBlockEncoder.first([0xA5; DEFAULT_DATA_LENGTH]);
+        frame[1] = 200;
+        assert_eq!(
+            block_on(client.handle_frame(&frame)),
+            Err(CfuProtocolError::DeserializeField {
+                struct_name: "FwUpdateContentHeader",
+                field_name: "data_length",
+            })
+        );
+        assert!(client.component().writes.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_respond_to_offer() {
+        let offer = FwUpdateOffer::builder(HostToken::Tool, 1, FwVersion::new(0x01000000)).build();
+
+        let bytes = respond_to_offer(&offer, OfferDecision::Accept);
+        let resp = FwUpdateOfferResponse::try_from(bytes).unwrap();
+        assert_eq!(resp.token, HostToken::Tool);
+        assert_eq!(resp.status, OfferStatus::Accept);
+        assert_eq!(bytes[3], 0xB0);
+
+        let bytes = respond_to_offer(&offer, OfferDecision::Reject(OfferRejectReason::SwapPending));
+        let resp = FwUpdateOfferResponse::try_from(bytes).unwrap();
+        assert_eq!(resp.token, HostToken::Tool);
+        assert_eq!(resp.outcome(), OfferOutcome::Rejected(OfferRejectReason::SwapPending));
+        assert_eq!(&bytes[..3], &[0; 3]);
+        assert_eq!(&bytes[13..], &[0; 3]);
+
+        let resp = FwUpdateOfferResponse::try_from(respond_to_offer(&offer, OfferDecision::Busy)).unwrap();
+        assert_eq!(resp.status, OfferStatus::Busy);
+        let resp = FwUpdateOfferResponse::try_from(respond_to_offer(&offer, OfferDecision::Skip)).unwrap();
+        assert_eq!(resp.status, OfferStatus::Skip);
+    }
+
+    #[test]
+    fn test_check_offer_protocol_version() {
+        // upper bits of the misc field don't take part in the check
+        let offer = FwUpdateOffer::new(HostToken::Tool, 1, FwVersion::new(0x01000000), 0, 0xABCD_0003);
+        let bytes = check_offer_protocol_version(&offer).unwrap_err();
+        let resp = FwUpdateOfferResponse::try_from(bytes).unwrap();
+        assert_eq!(resp.token, HostToken::Tool);
+        assert_eq!(resp.status, OfferStatus::CmdNotSupported);
+
+        let offer = FwUpdateOffer::new(HostToken::Tool, 1, FwVersion::new(0x01000000), 0, 0xABCD_0002);
+        assert_eq!(offer.protocol_version(), PROTOCOL_VER);
+        assert_eq!(check_offer_protocol_version(&offer), Ok(()));
+    }
+
+    #[test]
+    fn test_standard_client_process_command() {
+        let client = StandardClient::new(RecordingComponent::default());
+        let info = FwUpdateOfferInformation::new(OfferInformationComponentInfo::new(
+            HostToken::Tool,
+            SpecialComponentIds::Info,
+            OfferInformationCodeValues::StartEntireTransaction,
+        ));
+        let frame: [u8; FwUpdateOfferInformation::SERIALIZED_SIZE] = (&info).into();
+        block_on(client.process_command(None, &frame)).unwrap();
+        let resp = FwUpdateOfferResponse::try_from(client.take_response().unwrap()).unwrap();
+        assert_eq!(resp.status, OfferStatus::Accept);
+        assert_eq!(resp.token, HostToken::Tool);
+        assert_eq!(client.take_response(), None);
+
+        assert_eq!(block_on(client.process_command(None, &[0u8; 7])), Err(()));
+    }
+
+    #[test]
+    fn test_content_reassembler_out_of_order() {
+        let mut reassembler = ContentReassembler::<4>::new();
+        let released = |r: &mut ContentReassembler<4>| -> Vec<(u16, Vec<u8>)> {
+            core::iter::from_fn(|| r.pop_ready())
+                .map(|block| (block.sequence, block.data.to_vec()))
+                .collect()
+        };
+
+        reassembler.insert(0, &[0; 52], false).unwrap();
+        assert_eq!(released(&mut reassembler), std::vec![(0, std::vec![0; 52])]);
+
+        // block 2 waits for the gap at 1
+        reassembler.insert(2, &[2; 52], false).unwrap();
+        assert_eq!(reassembler.ready_len(), 0);
+        assert_eq!(
+            reassembler.insert(0, &[0; 52], false),
+            Err(ReassemblyError::Duplicate(0))
+        );
+        assert_eq!(
+            reassembler.insert(2, &[2; 52], false),
+            Err(ReassemblyError::Duplicate(2))
+        );
+
+        reassembler.insert(1, &[1; 52], false).unwrap();
+        assert_eq!(reassembler.ready_len(), 2);
+        assert!(!reassembler.is_complete());
+
+        reassembler.insert(3, &[3; 10], true).unwrap();
+        assert!(reassembler.is_complete());
+        assert_eq!(
+            reassembler.insert(4, &[4; 52], false),
+            Err(ReassemblyError::PastLastBlock(4))
+        );
+        assert_eq!(
+            released(&mut reassembler),
+            std::vec![(1, std::vec![1; 52]), (2, std::vec![2; 52]), (3, std::vec![3; 10])]
+        );
+        assert!(reassembler.is_complete());
+    }
+
+    #[test]
+    fn test_content_reassembler_last_block_before_gap() {
+        let mut reassembler = ContentReassembler::<2>::new();
+        reassembler
+            .insert_command(&FwUpdateContentCommand::try_from(&ContentBlockEncoder.last([9; 52], 2, 4)).unwrap())
+            .unwrap();
+        assert!(!reassembler.is_complete());
+        reassembler.insert(0, &[0; 52], false).unwrap();
+        assert!(!reassembler.is_complete());
+        assert_eq!(reassembler.insert(1, &[1; 52], false), Err(ReassemblyError::Full));
+        assert_eq!(reassembler.pop_ready().unwrap().sequence, 0);
+        reassembler.insert(1, &[1; 52], false).unwrap();
+        assert!(reassembler.is_complete());
+        assert_eq!(
+            reassembler.insert(3, &[0; 53], false),
+            Err(ReassemblyError::PastLastBlock(3))
+        );
+    }
 }
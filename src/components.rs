@@ -1,8 +1,11 @@
+use core::cmp::Ordering;
 use core::future::Future;
 
 use crate::protocol_definitions::{
-    CfuProtocolError, ComponentId, FwVersion, OfferRejectReason, OfferStatus, MAX_SUBCMPT_COUNT,
+    BankType, CfuProtocolError, CfuUpdateContentResponseStatus, ComponentId, FwVersion, OfferRejectReason, OfferStatus,
+    MAX_CMPT_COUNT, MAX_SUBCMPT_COUNT,
 };
+use crate::trace;
 use crate::writer::CfuWriterError;
 
 pub trait CfuComponentInfo {
@@ -13,9 +16,75 @@ pub trait CfuComponentInfo {
     /// Not async as this should be an element of struct that implements this trait
     fn get_component_id(&self) -> ComponentId;
 
+    /// Gets the fw version currently being offered to the component
+    /// Not async as this should be an element of struct that implements this trait
+    /// `CfuUpdater::run_update` sends this version in the offer, so there is no default: a made up version would be
+    /// offered on the wire
+    fn offered_version(&self) -> FwVersion;
+
     /// Validate the CFU offer for the component
     /// returns an OfferStatus with additional info on Reject Reason in the Err case.
-    fn is_offer_valid(&self) -> impl Future<Output = Result<OfferStatus, (OfferStatus, OfferRejectReason)>>;
+    /// Default implementation answers with offer_validity(), skipping an offer of the version the component already
+    /// runs like any other skipped offer
+    fn is_offer_valid(&self) -> impl Future<Output = Result<OfferStatus, (OfferStatus, OfferRejectReason)>> {
+        async { self.offer_validity().await.map(OfferValidity::status) }
+    }
+
+    /// Validates the CFU offer like is_offer_valid(), telling an offer of the version the component already runs
+    /// apart from offers skipped for other reasons
+    /// `CfuUpdater::run_update` decides on offers with this method and reports an up to date component as
+    /// `CfuProtocolError::UpToDate`, so it isn't queued for a re-offer it would only skip again. A component with its
+    /// own offer policy overrides this method rather than is_offer_valid() so the Host applies it too
+    /// Default implementation accepts the offer if offered_version() is newer than get_fw_version(), reports it up to
+    /// date if the component already runs that version, and rejects it with reject_policy() if it is older. The offer
+    /// is skipped if the current version can't be read
+    /// When ignore_variant() is set only major.minor must not be older, so an offer that only changes the variant
+    /// is accepted
+    fn offer_validity(&self) -> impl Future<Output = Result<OfferValidity, (OfferStatus, OfferRejectReason)>> {
+        async {
+            let Ok(current) = self.get_fw_version().await else {
+                trace!("failed to read fw version of component {}", self.get_component_id());
+                return Ok(OfferValidity::Status(OfferStatus::Skip));
+            };
+            let offered = self.offered_version();
+            let ordering = if self.ignore_variant() {
+                match offered.cmp_ignoring_variant(&current) {
+                    Ordering::Equal if offered.variant != current.variant => Ordering::Greater,
+                    ordering => ordering,
+                }
+            } else {
+                offered.cmp(&current)
+            };
+            match ordering {
+                Ordering::Greater => Ok(OfferValidity::Status(OfferStatus::Accept)),
+                Ordering::Equal => {
+                    trace!("component {} already runs the offered version", self.get_component_id());
+                    Ok(OfferValidity::UpToDate)
+                }
+                Ordering::Less => Err((OfferStatus::Reject, self.reject_policy())),
+            }
+        }
+    }
+
+    /// Returns whether the variant is a build tag that shouldn't gate updates, see is_offer_valid()
+    /// Not async as this should be an element of struct that implements this trait
+    /// Default implementation returns false
+    fn ignore_variant(&self) -> bool {
+        false
+    }
+
+    /// Reason the default is_offer_valid() reports when rejecting an offer that isn't newer than the current fw
+    /// Default implementation returns OldFw
+    fn reject_policy(&self) -> OfferRejectReason {
+        OfferRejectReason::OldFw
+    }
+
+    /// Returns whether the component is ready to be offered an update
+    /// A component that isn't ready is skipped rather than offered, so it can be offered again later
+    /// Default implementation is always ready
+    fn is_ready(&self) -> impl Future<Output = Result<bool, CfuProtocolError>> {
+        async { Ok(true) }
+    }
 
     /// Returns whether or not this component is a primary component
     /// Not async as this should be an element of struct that implements this trait
@@ -28,18 +97,122 @@ pub trait CfuComponentInfo {
     /// Not async as this should be an element of struct that implements this trait
     fn is_dual_bank(&self) -> bool;
 
+    /// Returns the bank the component is currently running from, as reported in its FwVerComponentInfo
+    /// Not async as this should be an element of struct that implements this trait
+    /// Default implementation returns bank 0
+    fn current_bank(&self) -> BankType {
+        BankType::VendorSpecific(0)
+    }
+
+    /// Returns how many banks the component has
+    /// Default implementation returns 2 for a dual-bank component and 1 otherwise
+    fn bank_count(&self) -> u8 {
+        if self.is_dual_bank() {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Returns the bank an update should be written into, see `inactive_bank`
+    fn write_bank(&self) -> BankType {
+        inactive_bank(self.current_bank(), self.bank_count())
+    }
+
     /// Returns sub-component ids if this component has any
     /// Not async as this should be an element of struct that implements this trait
     fn get_subcomponents(&self) -> [Option<ComponentId>; MAX_SUBCMPT_COUNT];
 }
 
+/// Outcome of `CfuComponentInfo::offer_validity` for an offer the component doesn't reject
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OfferValidity {
+    /// The offer is answered with this status
+    Status(OfferStatus),
+    /// The component already runs the offered version, so the offer is skipped for good rather than deferred
+    UpToDate,
+}
+
+impl OfferValidity {
+    /// The status the offer is answered with, Skip for an up to date component
+    pub fn status(self) -> OfferStatus {
+        match self {
+            OfferValidity::Status(status) => status,
+            OfferValidity::UpToDate => OfferStatus::Skip,
+        }
+    }
+}
+
+/// Picks the bank to write an update into, given the active bank and how many banks the component has
+/// The next bank after the active one is used, wrapping around, so updates round-robin through the banks of a
+/// triple or quad-bank component. A single bank component is written in place
+/// The layout is given as a bank count rather than a `BankType`: BankType only carries a bank index, and none of
+/// its values describes a dual, triple or quad-bank layout
+pub fn inactive_bank(active: BankType, bank_count: u8) -> BankType {
+    let active = u8::from(active);
+    if bank_count <= 1 {
+        return BankType::VendorSpecific(active);
+    }
+    BankType::VendorSpecific(active.wrapping_add(1) % bank_count)
+}
+
+/// Reason an image written to the inactive bank failed verification
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ImageVerifyError {
+    /// The image's CRC did not match
+    Crc,
+    /// The image's signature did not check out
+    Signature,
+}
+
+impl From<ImageVerifyError> for CfuUpdateContentResponseStatus {
+    fn from(error: ImageVerifyError) -> Self {
+        match error {
+            ImageVerifyError::Crc => CfuUpdateContentResponseStatus::ErrorCrc,
+            ImageVerifyError::Signature => CfuUpdateContentResponseStatus::ErrorSignature,
+        }
+    }
+}
+
 pub trait CfuComponentStorage {
     fn storage_prepare(&self) -> impl Future<Output = Result<(), CfuWriterError>>;
     fn storage_write(&self) -> impl Future<Output = Result<(), CfuWriterError>>;
     fn storage_finalize(&self) -> impl Future<Output = Result<(), CfuWriterError>>;
+    /// Writes the data carried by one content block, `offset` is the position of the data in the image
+    /// Default implementation ignores the data and calls storage_write()
+    fn storage_write_block(&self, _offset: usize, _data: &[u8]) -> impl Future<Output = Result<(), CfuWriterError>> {
+        self.storage_write()
+    }
+    /// Checks the whole image written to the inactive bank, e.g. its CRC and signature, before it is committed
+    /// Called once the last block has been written and before storage_finalize(), which is skipped on failure so
+    /// the active bank is left untouched
+    /// Default implementation accepts every image
+    fn storage_verify(&self) -> impl Future<Output = Result<(), ImageVerifyError>> {
+        async { Ok(()) }
+    }
+    /// Sequence number of the last content block the component wrote and kept, so an interrupted update can be
+    /// resumed from the block after it even if the Host has lost track of the stream
+    /// Default implementation doesn't track it and returns None, making a resume start over
+    fn last_written_sequence(&self) -> impl Future<Output = Result<Option<u16>, CfuWriterError>> {
+        async { Ok(None) }
+    }
     fn get_storage_offset(&self) -> usize {
         0
     }
+    /// Inclusive (start, end) window of storage offsets content blocks may be written to
+    /// A block is written at its sequence number times DEFAULT_DATA_LENGTH, whatever its firmware_address says, and
+    /// one whose bytes fall outside the window is answered with ErrorInvalidAddr
+    /// Default implementation accepts every address
+    fn writable_range(&self) -> (u32, u32) {
+        (0, u32::MAX)
+    }
+    /// Largest image in bytes the component can store, checked before an update is offered
+    /// Default implementation accepts images of any size
+    fn max_image_size(&self) -> usize {
+        usize::MAX
+    }
 }
 
 pub trait CfuAccessoryComponent {
@@ -68,6 +241,258 @@ pub trait CfuComponentFinalize {
             Ok(RT::default())
         }
     }
+
+    /// Handles cleanup after an update failed partway, like erasing the partially written bank or clearing a dirty flag
+    /// at_sequence is the sequence number of the block at which the update failed
+    /// Default implementation is do nothing
+    fn on_update_failed(&self, _at_sequence: u16) -> impl Future<Output = Result<(), CfuWriterError>> {
+        async { Ok(()) }
+    }
 }
 
 pub trait CfuComponentTraits: CfuComponentInfo + CfuComponentStorage + Default {}
+
+/// The components a Host updates, at most MAX_CMPT_COUNT of them, each with a distinct component id
+/// Components are kept in the order they were registered, which is the order `CfuUpdater::run_update_registry`
+/// offers them in
+pub struct ComponentRegistry<C> {
+    components: heapless::Vec<C, MAX_CMPT_COUNT>,
+}
+
+impl<C: CfuComponentInfo> ComponentRegistry<C> {
+    pub const fn new() -> Self {
+        Self {
+            components: heapless::Vec::new(),
+        }
+    }
+
+    /// Adds a component after those already registered
+    /// Returns DuplicateComponent if a component with the same id is registered, or InvalidComponentCount if the
+    /// registry is full
+    pub fn register(&mut self, component: C) -> Result<(), CfuProtocolError> {
+        let cmpt_id = component.get_component_id();
+        if self.by_id(cmpt_id).is_some() {
+            return Err(CfuProtocolError::DuplicateComponent(cmpt_id));
+        }
+        self.components
+            .push(component)
+            .map_err(|_| CfuProtocolError::InvalidComponentCount)
+    }
+
+    /// The registered component with the given id
+    pub fn by_id(&self, cmpt_id: ComponentId) -> Option<&C> {
+        self.components.iter().find(|c| c.get_component_id() == cmpt_id)
+    }
+
+    /// The registered components, in registration order
+    pub fn iter(&self) -> core::slice::Iter<'_, C> {
+        self.components.iter()
+    }
+
+    pub fn as_slice(&self) -> &[C] {
+        &self.components
+    }
+
+    pub fn len(&self) -> usize {
+        self.components.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.components.is_empty()
+    }
+}
+
+impl<C: CfuComponentInfo> Default for ComponentRegistry<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, C: CfuComponentInfo> IntoIterator for &'a ComponentRegistry<C> {
+    type Item = &'a C;
+    type IntoIter = core::slice::Iter<'a, C>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    struct VersionedComponent {
+        current: FwVersion,
+        offered: FwVersion,
+        reject_reason: Option<OfferRejectReason>,
+        ignore_variant: bool,
+    }
+
+    impl CfuComponentInfo for VersionedComponent {
+        async fn get_fw_version(&self) -> Result<FwVersion, CfuProtocolError> {
+            Ok(self.current)
+        }
+
+        fn get_component_id(&self) -> ComponentId {
+            1
+        }
+
+        fn offered_version(&self) -> FwVersion {
+            self.offered
+        }
+
+        fn reject_policy(&self) -> OfferRejectReason {
+            self.reject_reason.unwrap_or(OfferRejectReason::OldFw)
+        }
+
+        fn ignore_variant(&self) -> bool {
+            self.ignore_variant
+        }
+
+        fn is_dual_bank(&self) -> bool {
+            false
+        }
+
+        fn get_subcomponents(&self) -> [Option<ComponentId>; MAX_SUBCMPT_COUNT] {
+            [None; MAX_SUBCMPT_COUNT]
+        }
+    }
+
+    fn check(current: u32, offered: u32) -> Result<OfferStatus, (OfferStatus, OfferRejectReason)> {
+        let component = VersionedComponent {
+            current: FwVersion::new(current),
+            offered: FwVersion::new(offered),
+            reject_reason: None,
+            ignore_variant: false,
+        };
+        block_on(component.is_offer_valid())
+    }
+
+    #[test]
+    fn test_default_is_offer_valid() {
+        // newer
+        assert_eq!(check(0x01000200, 0x01000300), Ok(OfferStatus::Accept));
+        assert_eq!(check(0x01FFFF00, 0x02000000), Ok(OfferStatus::Accept));
+        // older
+        assert_eq!(
+            check(0x02000000, 0x01FFFF00),
+            Err((OfferStatus::Reject, OfferRejectReason::OldFw))
+        );
+        // equal is skipped, not rejected as old, and offer_validity tells it apart as up to date
+        assert_eq!(check(0x01000200, 0x01000200), Ok(OfferStatus::Skip));
+        let component = VersionedComponent {
+            current: FwVersion::new(0x01000200),
+            offered: FwVersion::new(0x01000200),
+            reject_reason: None,
+            ignore_variant: false,
+        };
+        assert_eq!(block_on(component.offer_validity()), Ok(OfferValidity::UpToDate));
+        // an older variant of the same major.minor is still older
+        assert_eq!(
+            check(0x01000205, 0x01000204),
+            Err((OfferStatus::Reject, OfferRejectReason::OldFw))
+        );
+    }
+
+    #[test]
+    fn test_is_offer_valid_ignoring_variant() {
+        let mut component = VersionedComponent {
+            current: FwVersion::new(0x01000205),
+            offered: FwVersion::new(0x01000203),
+            reject_reason: None,
+            ignore_variant: false,
+        };
+        // an older build tag is rejected when the variant counts
+        assert_eq!(
+            block_on(component.is_offer_valid()),
+            Err((OfferStatus::Reject, OfferRejectReason::OldFw))
+        );
+
+        component.ignore_variant = true;
+        assert_eq!(block_on(component.is_offer_valid()), Ok(OfferStatus::Accept));
+        // the same build is still not reinstalled, and an older major.minor is still rejected
+        component.offered = component.current;
+        assert_eq!(block_on(component.is_offer_valid()), Ok(OfferStatus::Skip));
+        component.offered = FwVersion::new(0x010001FF);
+        assert_eq!(
+            block_on(component.is_offer_valid()),
+            Err((OfferStatus::Reject, OfferRejectReason::OldFw))
+        );
+        component.offered = FwVersion::new(0x01000300);
+        assert_eq!(block_on(component.is_offer_valid()), Ok(OfferStatus::Accept));
+    }
+
+    #[test]
+    fn test_inactive_bank() {
+        let bank = BankType::VendorSpecific;
+        // dual bank flips between the two
+        assert_eq!(inactive_bank(bank(0), 2), bank(1));
+        assert_eq!(inactive_bank(bank(1), 2), bank(0));
+        // quad bank moves on to the next bank
+        assert_eq!(inactive_bank(bank(0), 4), bank(1));
+        assert_eq!(inactive_bank(bank(2), 4), bank(3));
+        assert_eq!(inactive_bank(bank(3), 4), bank(0));
+        // single bank is written in place
+        assert_eq!(inactive_bank(bank(0), 1), bank(0));
+
+        let component = VersionedComponent {
+            current: FwVersion::default(),
+            offered: FwVersion::default(),
+            reject_reason: None,
+            ignore_variant: false,
+        };
+        assert_eq!(component.bank_count(), 1);
+        assert_eq!(component.write_bank(), bank(0));
+    }
+
+    #[test]
+    fn test_custom_reject_policy() {
+        let mut component = VersionedComponent {
+            current: FwVersion::new(0x02000000),
+            offered: FwVersion::new(0x01000000),
+            reject_reason: Some(OfferRejectReason::VendorSpecific(0xE5)),
+            ignore_variant: false,
+        };
+        assert_eq!(
+            block_on(component.is_offer_valid()),
+            Err((OfferStatus::Reject, OfferRejectReason::VendorSpecific(0xE5)))
+        );
+
+        // the policy only applies to rejections, newer fw is still accepted
+        component.offered = FwVersion::new(0x03000000);
+        assert_eq!(block_on(component.is_offer_valid()), Ok(OfferStatus::Accept));
+    }
+
+    #[test]
+    fn test_component_registry() {
+        use crate::loopback::RamComponent;
+
+        let component = |id| RamComponent::<16>::new(id, FwVersion::default(), FwVersion::new(id as u32));
+        let mut registry = ComponentRegistry::new();
+        assert!(registry.is_empty());
+        registry.register(component(3)).unwrap();
+        registry.register(component(1)).unwrap();
+
+        assert_eq!(registry.by_id(1).map(|c| c.offered_version()), Some(FwVersion::new(1)));
+        assert!(registry.by_id(2).is_none());
+        // registration order is kept rather than sorting by id
+        assert!(registry.iter().map(|c| c.get_component_id()).eq([3, 1]));
+
+        assert_eq!(
+            registry.register(component(3)),
+            Err(CfuProtocolError::DuplicateComponent(3))
+        );
+        assert_eq!(registry.len(), 2);
+
+        for id in 4..4 + (MAX_CMPT_COUNT as u8 - 2) {
+            registry.register(component(id)).unwrap();
+        }
+        assert_eq!(
+            registry.register(component(0x20)),
+            Err(CfuProtocolError::InvalidComponentCount)
+        );
+        assert_eq!(registry.as_slice().len(), MAX_CMPT_COUNT);
+    }
+}
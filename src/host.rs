@@ -1,12 +1,22 @@
-use core::future::Future;
+use core::cell::Cell;
+use core::future::{poll_fn, Future};
+use core::task::{Poll, Waker};
+use core::time::Duration;
 
+use embassy_futures::select::{select, Either};
+
+use crate::components::{
+    CfuComponentFinalize, CfuComponentInfo, CfuComponentStorage, CfuComponentTraits, ComponentRegistry, OfferValidity,
+};
 use crate::protocol_definitions::{
-    CfuProtocolError, CfuUpdateContentResponseStatus, ComponentId, FwUpdateContentCommand, FwUpdateContentHeader,
-    FwUpdateContentResponse, FwUpdateOfferResponse, DEFAULT_DATA_LENGTH, FW_UPDATE_FLAG_FIRST_BLOCK,
-    FW_UPDATE_FLAG_LAST_BLOCK,
+    CfuProtocolError, CfuUpdateContentResponseStatus, ComponentId, ExtendedContentResponse, FwUpdateContentCommand,
+    FwUpdateContentHeader, FwUpdateContentResponse, FwUpdateOffer, FwUpdateOfferExtended, FwUpdateOfferInformation,
+    FwUpdateOfferResponse, HostToken, OfferCommandExtendedCodeValues, OfferExtendedComponentInfo,
+    OfferInformationCodeValues, OfferInformationComponentInfo, OfferRejectReason, OfferStatus, SpecialComponentIds,
+    DEFAULT_DATA_LENGTH, FW_UPDATE_FLAG_FIRST_BLOCK, FW_UPDATE_FLAG_LAST_BLOCK, MAX_CMPT_COUNT,
 };
 use crate::writer::{CfuWriterAsync, CfuWriterError};
-use crate::{trace, CfuImage, DataChunk};
+use crate::{block_count, trace, CfuImage, DataChunk};
 
 /// CfuHostStates trait defines behavior needed for a Cfu Host to process available Cfu Offers
 /// and send the appropriate commands to the Cfu Client to update the components
@@ -27,7 +37,33 @@ pub trait CfuHostStates<W> {
     /// For a slice of responses, determine if any components have not finished updating
     fn verify_all_updates_completed(
         offer_responses: &[FwUpdateOfferResponse],
-    ) -> impl Future<Output = Result<bool, CfuProtocolError>>;
+    ) -> impl Future<Output = Result<bool, CfuProtocolError>> {
+        async move {
+            Ok(!offer_responses
+                .iter()
+                .any(|resp| matches!(resp.status, OfferStatus::Busy | OfferStatus::Skip)))
+        }
+    }
+
+    /// For a slice of (component, response) pairs, list the components that have not finished updating
+    /// (those that responded Busy or Skip) so exactly those can be offered again
+    /// At most MAX_CMPT_COUNT components are listed
+    fn updates_remaining(
+        offer_responses: &[(ComponentId, FwUpdateOfferResponse)],
+    ) -> Result<(), heapless::Vec<ComponentId, MAX_CMPT_COUNT>> {
+        let mut remaining = heapless::Vec::new();
+        for (cmpt_id, resp) in offer_responses {
+            if matches!(resp.status, OfferStatus::Busy | OfferStatus::Skip) && remaining.push(*cmpt_id).is_err() {
+                trace!("more than MAX_CMPT_COUNT components remaining");
+                break;
+            }
+        }
+        if remaining.is_empty() {
+            Ok(())
+        } else {
+            Err(remaining)
+        }
+    }
 }
 
 /// CfuUpdateContent trait defines behavior needed for a Cfu Host to send the contents of an accepted offer to a component via sending commands to a Cfu Client
@@ -42,10 +78,12 @@ pub trait CfuUpdateContent<W> {
     ) -> impl Future<Output = Result<FwUpdateContentResponse, CfuProtocolError>>;
 
     /// Build and send UpdateOfferContent command with first block flag
+    /// Blocks are written at `base_offset` plus their position in the image
     fn process_first_data_block(
         &mut self,
         w: &mut W,
         chunk: DataChunk,
+        base_offset: usize,
     ) -> impl Future<Output = Result<FwUpdateContentResponse, CfuWriterError>>;
 
     /// Build and send UpdateOfferContent command, no special flags
@@ -54,173 +92,3579 @@ pub trait CfuUpdateContent<W> {
         w: &mut W,
         chunk: DataChunk,
         seq_num: usize,
+        base_offset: usize,
     ) -> impl Future<Output = Result<FwUpdateContentResponse, CfuWriterError>>;
 
-    /// Build and send UpdateOfferContent command with last block flag
+    /// Build and send UpdateOfferContent command with last block flag, carrying the first `data_length` bytes of
+    /// `chunk` so the padding of a partial final block isn't counted as image data
     fn process_last_data_block(
         &mut self,
         w: &mut W,
         chunk: DataChunk,
+        data_length: usize,
         seq_num: usize,
+        base_offset: usize,
     ) -> impl Future<Output = Result<FwUpdateContentResponse, CfuWriterError>>;
 }
 
+/// CfuUpdateHooks lets the caller inject platform behavior into the content stream,
+/// keeping the updater independent of any particular executor or timer implementation
+pub trait CfuUpdateHooks {
+    /// Returns a future that completes once a single `cfu_write_read` should be considered timed out
+    /// A fresh future is requested for every transaction
+    /// Default implementation never times out
+    fn timeout(&mut self) -> impl Future<Output = ()> {
+        core::future::pending()
+    }
+
+    /// Returns a future that completes once the host should re-send offers to a busy component, or re-send a block
+    /// the component failed with ErrorWrite or ErrorPrepare
+    /// Default implementation retries immediately
+    fn retry_delay(&mut self) -> impl Future<Output = ()> {
+        core::future::ready(())
+    }
+
+    /// Returns a future that completes once the next content block may be sent
+    /// Awaited between consecutive blocks of the content stream, e.g. to space out bus traffic on a device with
+    /// EMI constraints. Not awaited before the first block
+    /// Default implementation sends the next block immediately
+    fn block_delay(&mut self) -> impl Future<Output = ()> {
+        core::future::ready(())
+    }
+
+    /// Returns the current time in milliseconds from a monotonic clock
+    /// Used to hold the content stream of a component to the updater's content budget
+    /// Default implementation has no clock, so the content budget is never enforced
+    fn now_ms(&mut self) -> Option<u64> {
+        None
+    }
+
+    /// Returns a future that completes once the next content block may be sent, resolving to false if the content
+    /// stream should be abandoned instead
+    /// Awaited before every block of the content stream, after block_delay
+    /// Default implementation never pauses
+    fn proceed(&mut self) -> impl Future<Output = bool> {
+        core::future::ready(true)
+    }
+}
+
+/// CfuUpdateHooks implementation that uses all of the default behavior
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NoHooks;
+
+impl CfuUpdateHooks for NoHooks {}
+
+/// Receives the image bytes as they are streamed, e.g. to compute a digest for a signature check
+/// Only the data_length bytes each block carries are fed, never the padding of the final block
+pub trait DigestSink {
+    fn update(&mut self, data: &[u8]);
+}
+
+/// DigestSink that discards everything it is fed
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NoDigest;
+
+impl DigestSink for NoDigest {
+    fn update(&mut self, _data: &[u8]) {}
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
-pub struct CfuUpdater;
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+enum PauseState {
+    Running,
+    Paused,
+    Cancelled,
+}
 
-impl<W: CfuWriterAsync> CfuUpdateContent<W> for CfuUpdater {
-    /// Write all chunks of an image
-    async fn write_data_chunks(
-        &mut self,
-        writer: &mut W,
-        image: impl CfuImage,
-        cmpt_id: ComponentId,
-        base_offset: usize,
-    ) -> Result<FwUpdateContentResponse, CfuProtocolError> {
-        // Build update offer command
-        let updateoffercmd_bytes = [0u8; 16];
-        let mut offer_resp = [0u8; 16];
-        writer
-            .cfu_write_read(Some(base_offset), &updateoffercmd_bytes, &mut offer_resp)
-            .await
-            .map_err(CfuProtocolError::WriterError)?;
+/// Lets another task pause, resume or cancel a content stream between blocks, e.g. to yield the bus to a
+/// higher-priority transfer
+/// Shared by reference between tasks on the same executor. `&PauseHandle` is itself a CfuUpdateHooks that only
+/// pauses; hooks that need more call `wait_resumed` from their `proceed`
+/// A paused stream is parked on a waker rather than polling, and only one stream may wait on a handle at a time
+pub struct PauseHandle {
+    state: Cell<PauseState>,
+    waker: Cell<Option<Waker>>,
+}
 
-        let deser = FwUpdateContentResponse::try_from(offer_resp)
-            .map_err(|_| CfuProtocolError::WriterError(CfuWriterError::ByteConversionError))?;
+impl PauseHandle {
+    pub const fn new() -> Self {
+        Self {
+            state: Cell::new(PauseState::Running),
+            waker: Cell::new(None),
+        }
+    }
+
+    /// Holds the stream before its next block
+    /// Has no effect once the handle is cancelled
+    pub fn pause(&self) {
+        if self.state.get() == PauseState::Running {
+            self.state.set(PauseState::Paused);
+        }
+    }
 
-        let status = deser.status;
-        if status != CfuUpdateContentResponseStatus::Success {
-            return Err(CfuProtocolError::CfuContentUpdateResponseError(status));
+    /// Lets a paused stream continue from the block it stopped at
+    /// Has no effect once the handle is cancelled
+    pub fn resume(&self) {
+        if self.state.get() == PauseState::Paused {
+            self.state.set(PauseState::Running);
+            self.wake();
         }
+    }
 
-        let total_bytes: usize = image.get_total_size();
-        let chunk_size = DEFAULT_DATA_LENGTH;
-        let num_chunks = total_bytes / chunk_size;
-        let remainder = total_bytes % chunk_size;
+    /// Abandons the stream before its next block, whether or not it is paused
+    pub fn cancel(&self) {
+        self.state.set(PauseState::Cancelled);
+        self.wake();
+    }
 
-        // Read and process data in chunks so as to not over-burden memory resources
-        let mut resp: FwUpdateContentResponse =
-            FwUpdateContentResponse::new(0, CfuUpdateContentResponseStatus::ErrorInvalid);
-        for i in 0..num_chunks {
-            let mut chunk = [0u8; DEFAULT_DATA_LENGTH];
-            let address_offset = i * DEFAULT_DATA_LENGTH + base_offset;
-            let r = match i {
-                0 => {
-                    image
-                        .get_bytes_for_chunk(&mut chunk, address_offset)
-                        .await
-                        .map_err(|_| CfuProtocolError::WriterError(CfuWriterError::StorageError))?;
-                    self.process_first_data_block(writer, chunk).await
-                }
-                num if (num < num_chunks) => {
-                    image
-                        .get_bytes_for_chunk(&mut chunk, address_offset)
-                        .await
-                        .map_err(|_| CfuProtocolError::WriterError(CfuWriterError::StorageError))?;
-                    self.process_middle_data_block(writer, chunk, i).await
-                }
-                _ => {
-                    image
-                        .get_bytes_for_chunk(
-                            chunk
-                                .get_mut(0..remainder)
-                                .ok_or(CfuProtocolError::WriterError(CfuWriterError::Other))?,
-                            address_offset,
-                        )
-                        .await
-                        .map_err(|_| CfuProtocolError::WriterError(CfuWriterError::StorageError))?;
-                    self.process_last_data_block(writer, chunk, i).await
-                }
-            }
-            .map_err(CfuProtocolError::WriterError)?;
-            // if no errors in processing the data block, check the response
-            if r.status != CfuUpdateContentResponseStatus::Success {
-                return Err(CfuProtocolError::UpdateError(cmpt_id));
+    pub fn is_paused(&self) -> bool {
+        self.state.get() == PauseState::Paused
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.get() == PauseState::Cancelled
+    }
+
+    /// Completes once the handle is not paused, with false if it has been cancelled
+    pub async fn wait_resumed(&self) -> bool {
+        poll_fn(|cx| match self.state.get() {
+            PauseState::Running => Poll::Ready(true),
+            PauseState::Cancelled => Poll::Ready(false),
+            PauseState::Paused => {
+                self.waker.set(Some(cx.waker().clone()));
+                Poll::Pending
             }
-            resp = r;
+        })
+        .await
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for PauseHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CfuUpdateHooks for &PauseHandle {
+    async fn proceed(&mut self) -> bool {
+        self.wait_resumed().await
+    }
+}
+
+/// Races `fut` against `timeout`, returning `CfuProtocolError::TimeoutError(cmpt_id)` if the timeout completes first
+pub async fn with_timeout<T>(
+    cmpt_id: ComponentId,
+    fut: impl Future<Output = T>,
+    timeout: impl Future<Output = ()>,
+) -> Result<T, CfuProtocolError> {
+    match select(fut, timeout).await {
+        Either::First(output) => Ok(output),
+        Either::Second(()) => {
+            trace!("timed out waiting on component {}", cmpt_id);
+            Err(CfuProtocolError::TimeoutError(cmpt_id))
         }
+    }
+}
 
-        if resp.sequence != num_chunks as u16 {
-            trace!("final sequence number does not match expected number of chunks");
-            return Err(CfuProtocolError::InvalidBlockTransition);
+/// Maps an error reported by a writer while talking to `cmpt_id`
+/// A writer reported timeout surfaces as `CfuProtocolError::TimeoutError` so callers can tell it is retryable
+fn writer_error(cmpt_id: ComponentId) -> impl Fn(CfuWriterError) -> CfuProtocolError {
+    move |e| match e {
+        CfuWriterError::Timeout => CfuProtocolError::TimeoutError(cmpt_id),
+        e => CfuProtocolError::WriterError(e),
+    }
+}
+
+/// Maps the status of an offer `cmpt_id` didn't accept
+/// CmdNotSupported surfaces as `CfuProtocolError::CommandNotSupported` so callers can skip the component rather than
+/// treat it as rejecting the update
+fn offer_status_error(cmpt_id: ComponentId, status: OfferStatus) -> CfuProtocolError {
+    match status {
+        OfferStatus::CmdNotSupported => {
+            trace!("component {} does not support the offer", cmpt_id);
+            CfuProtocolError::CommandNotSupported(cmpt_id)
         }
+        status => CfuProtocolError::CfuOfferStatusError(status),
+    }
+}
 
-        Ok(resp)
+/// Checks that a component acked the content block that was sent
+fn check_sequence(expected: u16, actual: u16) -> Result<(), CfuProtocolError> {
+    if actual != expected {
+        trace!("expected ack for sequence {}, got {}", expected, actual);
+        return Err(CfuProtocolError::UnexpectedSequence { expected, actual });
     }
+    Ok(())
+}
 
-    /// Build and send UpdateOfferContent command with first block flag
-    async fn process_first_data_block(
-        &mut self,
-        w: &mut W,
+/// Checks the response of `cmpt_id` to the content block with sequence number `seq`
+fn check_content_response(
+    cmpt_id: ComponentId,
+    seq: usize,
+    resp: FwUpdateContentResponse,
+) -> Result<FwUpdateContentResponse, CfuProtocolError> {
+    if resp.status != CfuUpdateContentResponseStatus::Success {
+        return Err(CfuProtocolError::UpdateError(cmpt_id));
+    }
+    // catch a desynced component as soon as it happens rather than after the whole image
+    check_sequence(seq as u16, resp.sequence)?;
+    Ok(resp)
+}
+
+/// Produces a rolling sequence of vendor specific host tokens so a component can tell transactions apart
+/// Tokens reserved by the spec for the standard hosts are never produced
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TokenGenerator {
+    start: u8,
+    end: u8,
+    next: u8,
+}
+
+impl TokenGenerator {
+    /// Creates a generator that rolls through the full 0x00..=0xFF range
+    pub fn new() -> Self {
+        Self {
+            start: 0x00,
+            end: 0xFF,
+            next: 0x00,
+        }
+    }
+
+    /// Creates a generator that rolls through `start..=end`
+    /// Returns None if the range is empty or only contains reserved tokens
+    pub fn with_range(start: u8, end: u8) -> Option<Self> {
+        if start > end || (start..=end).all(HostToken::is_reserved) {
+            return None;
+        }
+        Some(Self {
+            start,
+            end,
+            next: start,
+        })
+    }
+
+    /// Returns the next token, wrapping back to the start of the range after the end
+    /// Stamp the returned token onto every command of a transaction with `CfuUpdater::set_token`
+    pub fn next_token(&mut self) -> HostToken {
+        loop {
+            let value = self.next;
+            self.next = if value == self.end { self.start } else { value + 1 };
+            if !HostToken::is_reserved(value) {
+                return HostToken::VendorSpecific(value);
+            }
+        }
+    }
+}
+
+impl Default for TokenGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Encodes UpdateOfferContent commands into their wire bytes without sending them,
+/// for use over transports the CfuWriterAsync traits don't fit
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContentBlockEncoder;
+
+impl ContentBlockEncoder {
+    /// Encodes a full block with the first block flag
+    pub fn first(&self, chunk: DataChunk) -> [u8; FwUpdateContentCommand::SERIALIZED_SIZE] {
+        self.encode(FW_UPDATE_FLAG_FIRST_BLOCK, chunk, DEFAULT_DATA_LENGTH as u8, 0)
+    }
+
+    /// Encodes a full block with no special flags
+    pub fn middle(&self, chunk: DataChunk, seq_num: u16) -> [u8; FwUpdateContentCommand::SERIALIZED_SIZE] {
+        self.encode(0, chunk, DEFAULT_DATA_LENGTH as u8, seq_num)
+    }
+
+    /// Encodes a block with the last block flag, carrying the first `data_length` bytes of `chunk`
+    pub fn last(
+        &self,
         chunk: DataChunk,
-    ) -> Result<FwUpdateContentResponse, CfuWriterError> {
-        let cmd = FwUpdateContentCommand {
-            header: FwUpdateContentHeader {
-                flags: FW_UPDATE_FLAG_FIRST_BLOCK,
-                data_length: DEFAULT_DATA_LENGTH as u8,
-                sequence_num: 0,
-                firmware_address: 0,
-            },
-            data: chunk,
+        seq_num: u16,
+        data_length: u8,
+    ) -> [u8; FwUpdateContentCommand::SERIALIZED_SIZE] {
+        self.encode(FW_UPDATE_FLAG_LAST_BLOCK, chunk, data_length, seq_num)
+    }
+
+    /// Encodes a block with arbitrary flags
+    pub fn encode(
+        &self,
+        flags: u8,
+        chunk: DataChunk,
+        data_length: u8,
+        seq_num: u16,
+    ) -> [u8; FwUpdateContentCommand::SERIALIZED_SIZE] {
+        let mut frame = [0u8; FwUpdateContentCommand::SERIALIZED_SIZE];
+        Self::data_mut(&mut frame).copy_from_slice(&chunk);
+        self.encode_header_into(&mut frame, flags, data_length, seq_num);
+        frame
+    }
+
+    /// Encodes only the header of a block into the front of `frame`, leaving the data region untouched
+    /// Together with `data_mut` this lets the data be filled in place, e.g. straight from the image,
+    /// instead of going through a DataChunk that is then copied into the frame
+    pub fn encode_header_into(
+        &self,
+        frame: &mut [u8; FwUpdateContentCommand::SERIALIZED_SIZE],
+        flags: u8,
+        data_length: u8,
+        seq_num: u16,
+    ) {
+        let header = FwUpdateContentHeader {
+            flags,
+            data_length,
+            sequence_num: seq_num,
+            firmware_address: 0,
         };
-        let cmd_bytes: [u8; 60] = (&cmd).into();
-        let offset = 0;
-        let mut resp_buf = [0u8; core::mem::size_of::<FwUpdateContentResponse>()];
-        w.cfu_write_read(Some(offset), &cmd_bytes, &mut resp_buf)
-            .await
-            .map_err(|_| CfuWriterError::StorageError)?;
+        let header: [u8; FwUpdateContentHeader::SERIALIZED_SIZE] = (&header).into();
+        frame[..FwUpdateContentHeader::SERIALIZED_SIZE].copy_from_slice(&header);
+    }
 
-        FwUpdateContentResponse::try_from(resp_buf).map_err(|_| CfuWriterError::ByteConversionError)
+    /// The data_length bytes of data carried by an encoded content command frame
+    pub fn payload(frame: &[u8; FwUpdateContentCommand::SERIALIZED_SIZE]) -> &[u8] {
+        let data_length = frame[1] as usize;
+        frame
+            .get(FwUpdateContentHeader::SERIALIZED_SIZE..FwUpdateContentHeader::SERIALIZED_SIZE + data_length)
+            .unwrap_or_default()
     }
 
-    /// Build and send UpdateOfferContent command, no special flags
-    async fn process_middle_data_block(
-        &mut self,
+    /// The data region of a content command frame
+    pub fn data_mut(frame: &mut [u8; FwUpdateContentCommand::SERIALIZED_SIZE]) -> &mut [u8] {
+        &mut frame[FwUpdateContentHeader::SERIALIZED_SIZE..]
+    }
+}
+
+/// Controls how `CfuUpdater::run_offer_list` reacts to a component responding Busy
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ListRetryMode {
+    /// Only the busy component is offered again
+    #[default]
+    PerComponent,
+    /// The whole offer list is presented again if any component is busy
+    WholeList,
+}
+
+/// Throughput of the last successful `write_data_chunks`, as reported by `CfuUpdater::last_update_stats`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct UpdateStats {
+    /// Size of the image that was written
+    pub bytes: usize,
+    /// Time from the start of the content stream to the ack of its last block
+    pub duration: Duration,
+    /// Number of content blocks the image was sent in
+    pub blocks: usize,
+}
+
+impl UpdateStats {
+    /// Image bytes written per second, or None if the stream took no measurable time
+    pub fn bytes_per_second(&self) -> Option<u64> {
+        let millis = u64::try_from(self.duration.as_millis()).ok().filter(|ms| *ms > 0)?;
+        Some((self.bytes as u64).saturating_mul(1000) / millis)
+    }
+}
+
+/// Progress of a CfuUpdater through an update, as reported by `CfuUpdater::phase`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UpdatePhase {
+    /// Nothing has been sent yet
+    #[default]
+    Idle,
+    /// Offers are being sent to the components
+    Offering,
+    /// Content is being streamed, `seq` is the block currently being sent
+    WritingContent { seq: u16 },
+    /// The last operation completed
+    Done,
+    /// The last operation failed, see `CfuUpdater::failed_sequence` for where the content stream stopped
+    Failed,
+}
+
+/// Responses to an offer list, paired with the component each offer was sent to
+pub type OfferListResponses = heapless::Vec<(ComponentId, FwUpdateOfferResponse), MAX_CMPT_COUNT>;
+
+/// Components that rejected their offer and the reason they gave, e.g. from `OfferListResponses`
+/// Accepted, busy and skipped offers are left out
+pub fn rejected_offers(
+    responses: &[(ComponentId, FwUpdateOfferResponse)],
+) -> impl Iterator<Item = (ComponentId, OfferRejectReason)> + '_ {
+    responses
+        .iter()
+        .filter(|(_, resp)| resp.status == OfferStatus::Reject)
+        .map(|(cmpt_id, resp)| (*cmpt_id, resp.reject_reason))
+}
+
+/// Per-component outcome of `CfuUpdater::run_update`, in the order the components were given
+/// Unused entries are None
+pub type UpdateResults = [Option<(ComponentId, Result<FwUpdateContentResponse, CfuProtocolError>)>; MAX_CMPT_COUNT];
+
+/// Update order of a set of components, as indices into the components given to `update_order`
+pub type UpdateOrder = heapless::Vec<usize, MAX_CMPT_COUNT>;
+
+/// Orders components so that every subcomponent is updated before the component listing it in get_subcomponents()
+/// Components not tied by a dependency keep the order they were given in, and subcomponents that aren't among
+/// `components` are ignored. The components and their images can be reordered with the result before `run_update`
+/// Fails with DependencyCycle naming a component that depends on itself, or InvalidComponentCount if more than
+/// MAX_CMPT_COUNT components are given
+pub fn update_order(components: &[impl CfuComponentInfo]) -> Result<UpdateOrder, CfuProtocolError> {
+    if components.len() > MAX_CMPT_COUNT {
+        return Err(CfuProtocolError::InvalidComponentCount);
+    }
+    let mut placed = [false; MAX_CMPT_COUNT];
+    let mut order = UpdateOrder::new();
+    while order.len() < components.len() {
+        let before = order.len();
+        for (i, component) in components.iter().enumerate() {
+            if placed.get(i).copied().unwrap_or(true) {
+                continue;
+            }
+            let ready = component.get_subcomponents().iter().flatten().all(|sub| {
+                components
+                    .iter()
+                    .zip(placed.iter())
+                    .all(|(c, placed)| *placed || c.get_component_id() != *sub)
+            });
+            if ready {
+                order.push(i).map_err(|_| CfuProtocolError::InvalidComponentCount)?;
+                if let Some(p) = placed.get_mut(i) {
+                    *p = true;
+                }
+            }
+        }
+        if order.len() == before {
+            // nothing could be placed, every remaining component waits on another remaining component
+            let stuck = components
+                .iter()
+                .zip(placed.iter())
+                .find(|(_, placed)| !**placed)
+                .map_or(0, |(c, _)| c.get_component_id());
+            trace!("dependency cycle through component {}", stuck);
+            return Err(CfuProtocolError::DependencyCycle(stuck));
+        }
+    }
+    Ok(order)
+}
+
+/// Components that skipped their offer during an update pass and should be offered again later
+/// Skipped components are not failures, they are queued for `CfuUpdater::retry_skipped`
+/// A component that already runs the offered version is reported as `CfuProtocolError::UpToDate` and is never
+/// queued, as offering it again would only be skipped again
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SkipList {
+    ids: heapless::Vec<ComponentId, MAX_CMPT_COUNT>,
+}
+
+impl SkipList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collects the components whose offer was skipped in the results of an update pass
+    pub fn from_results(results: &UpdateResults) -> Self {
+        let mut list = Self::new();
+        for (cmpt_id, _) in results
+            .iter()
+            .flatten()
+            .filter(|(_, result)| *result == Err(CfuProtocolError::CfuOfferStatusError(OfferStatus::Skip)))
+        {
+            // results holds at most MAX_CMPT_COUNT entries so this can't overflow
+            let _ = list.push(*cmpt_id);
+        }
+        list
+    }
+
+    /// Queues a component for a later re-offer, ignoring components that are already queued
+    /// Fails if MAX_CMPT_COUNT components are already queued
+    pub fn push(&mut self, cmpt_id: ComponentId) -> Result<(), ComponentId> {
+        if self.contains(cmpt_id) {
+            return Ok(());
+        }
+        self.ids.push(cmpt_id)
+    }
+
+    /// Returns whether a component is queued
+    pub fn contains(&self, cmpt_id: ComponentId) -> bool {
+        self.ids.contains(&cmpt_id)
+    }
+
+    /// Returns the queued components in the order they skipped
+    pub fn as_slice(&self) -> &[ComponentId] {
+        &self.ids
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CfuUpdater {
+    /// Token stamped onto every offer this updater sends
+    token: HostToken,
+    /// When set, the final block is sent without the last block flag so the component does not swap
+    stage_only: bool,
+    /// Sequence number of the final block of an image that has been staged but not yet activated
+    staged_sequence: Option<u16>,
+    /// How run_offer_list reacts to a busy component
+    list_retry_mode: ListRetryMode,
+    /// How many more times a busy offer (or offer list) is re-sent before giving up
+    offer_retries: u8,
+    /// How many more times a content block is re-sent after a write or prepare failure before giving up
+    block_retries: u8,
+    /// Byte that pads the final block of an image past its data_length
+    fill_byte: u8,
+    /// Sequence number of the block at which the last write_data_chunks failed
+    failed_sequence: Option<u16>,
+    /// Sequence number of the last block the component acked in the current or last content stream
+    last_completed_sequence: Option<u16>,
+    /// Total time in milliseconds the content stream of a single component may take
+    content_budget_ms: Option<u64>,
+    /// Where the updater is in the update
+    phase: UpdatePhase,
+    /// Last offer sent with send_offer, offered again ahead of the content written for its component if the
+    /// component didn't accept it
+    last_offer: Option<FwUpdateOffer>,
+    /// Whether the component accepted last_offer
+    offer_accepted: bool,
+    /// Throughput of the last successful content stream, if the hooks provided a clock
+    last_update_stats: Option<UpdateStats>,
+}
+
+impl CfuUpdater {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Selects whether `write_data_chunks` only stages the image (download phase)
+    /// A staged image must later be committed with `activate`
+    pub fn set_stage_only(&mut self, stage_only: bool) {
+        self.stage_only = stage_only;
+    }
+
+    /// Returns whether `write_data_chunks` only stages the image
+    pub fn is_stage_only(&self) -> bool {
+        self.stage_only
+    }
+
+    /// Sets the token stamped onto every offer this updater sends
+    pub fn set_token(&mut self, token: HostToken) {
+        self.token = token;
+    }
+
+    /// Gets the token stamped onto every offer this updater sends
+    pub fn token(&self) -> HostToken {
+        self.token
+    }
+
+    /// Selects how `run_offer_list` retries when a component responds Busy, and how many times
+    pub fn set_offer_retry(&mut self, mode: ListRetryMode, retries: u8) {
+        self.list_retry_mode = mode;
+        self.offer_retries = retries;
+    }
+
+    /// Sets how many times `write_data_chunks` re-sends a block the component failed with ErrorWrite or
+    /// ErrorPrepare, e.g. after a transient flash glitch, before giving up with
+    /// `CfuProtocolError::BlockRetriesExhausted`
+    /// The block is re-sent as is, with the same sequence number, after awaiting `CfuUpdateHooks::retry_delay`
+    /// Other failures are never retried
+    pub fn set_block_retries(&mut self, retries: u8) {
+        self.block_retries = retries;
+    }
+
+    /// Sets the byte used to pad the final block of an image past its data_length
+    pub fn set_fill_byte(&mut self, fill_byte: u8) {
+        self.fill_byte = fill_byte;
+    }
+
+    /// Caps the total time the content stream of a single component may take, measured with `CfuUpdateHooks::now_ms`
+    /// The stream is aborted with `CfuProtocolError::TimeoutError` once the budget is exceeded, even if every
+    /// individual transaction completes within its own timeout. None removes the cap
+    pub fn set_content_budget_ms(&mut self, budget_ms: Option<u64>) {
+        self.content_budget_ms = budget_ms;
+    }
+
+    /// Returns where the updater is in the update
+    /// If an operation is cancelled partway, e.g. by an outer timeout, the phase it reached is kept
+    pub fn phase(&self) -> UpdatePhase {
+        self.phase
+    }
+
+    /// Moves to Done or Failed depending on the outcome of an operation
+    fn finish<T>(&mut self, result: Result<T, CfuProtocolError>) -> Result<T, CfuProtocolError> {
+        self.phase = if result.is_ok() {
+            UpdatePhase::Done
+        } else {
+            UpdatePhase::Failed
+        };
+        result
+    }
+
+    /// Returns the sequence number of the block at which the last `write_data_chunks` failed,
+    /// or None if it succeeded or failed before sending any block
+    pub fn failed_sequence(&self) -> Option<u16> {
+        self.failed_sequence
+    }
+
+    /// Returns the sequence number of the last block the component acked in the current or last content stream
+    /// It is recorded as each ack arrives, so it is still accurate if the `write_data_chunks` future is dropped
+    /// mid-stream, e.g. when it loses a select against a timeout, and the stream can be picked up with
+    /// `resume_data_chunks` from the block after it
+    pub fn last_completed_sequence(&self) -> Option<u16> {
+        self.last_completed_sequence
+    }
+
+    /// Returns the throughput of the last successful `write_data_chunks`, timed with `CfuUpdateHooks::now_ms`
+    /// None if it failed, or if the hooks it was given have no clock
+    pub fn last_update_stats(&self) -> Option<UpdateStats> {
+        self.last_update_stats
+    }
+
+    /// Build and send an UpdateOfferContent command carrying the first `data_length` bytes of `chunk`
+    /// The block is written at `base_offset` plus its position in the image
+    async fn send_content_block<W: CfuWriterAsync>(
+        &self,
         w: &mut W,
+        flags: u8,
         chunk: DataChunk,
+        data_length: usize,
         seq_num: usize,
+        base_offset: usize,
     ) -> Result<FwUpdateContentResponse, CfuWriterError> {
-        let cmd = FwUpdateContentCommand {
-            header: FwUpdateContentHeader {
-                flags: 0,
-                data_length: DEFAULT_DATA_LENGTH as u8,
-                sequence_num: seq_num as u16,
-                firmware_address: 0,
-            },
-            data: chunk,
-        };
-        let cmd_bytes: [u8; 60] = (&cmd).into();
-        let offset = seq_num * DEFAULT_DATA_LENGTH;
-        let mut resp_buf = [0u8; core::mem::size_of::<FwUpdateContentResponse>()];
-        w.cfu_write_read(Some(offset), &cmd_bytes, &mut resp_buf)
-            .await
-            .map_err(|_| CfuWriterError::StorageError)?;
-
-        FwUpdateContentResponse::try_from(resp_buf).map_err(|_| CfuWriterError::ByteConversionError)
+        let frame = ContentBlockEncoder.encode(flags, chunk, data_length as u8, seq_num as u16);
+        self.send_content_frame(w, &frame, seq_num, base_offset).await
     }
-    /// Build and send UpdateOfferContent command with last block flag
-    async fn process_last_data_block(
-        &mut self,
+
+    /// Sends an already encoded UpdateOfferContent command frame
+    /// The frame is written at `base_offset` plus the position of block `seq_num` in the image
+    async fn send_content_frame<W: CfuWriterAsync>(
+        &self,
         w: &mut W,
-        chunk: DataChunk,
+        frame: &[u8; FwUpdateContentCommand::SERIALIZED_SIZE],
         seq_num: usize,
+        base_offset: usize,
     ) -> Result<FwUpdateContentResponse, CfuWriterError> {
-        let cmd = FwUpdateContentCommand {
-            header: FwUpdateContentHeader {
-                flags: FW_UPDATE_FLAG_LAST_BLOCK,
-                sequence_num: seq_num as u16,
-                data_length: DEFAULT_DATA_LENGTH as u8,
-                firmware_address: 0,
-            },
-            data: chunk,
-        };
-        let cmd_bytes: [u8; 60] = (&cmd).into();
-        let offset = seq_num * DEFAULT_DATA_LENGTH;
-        let mut resp_buf = [0u8; core::mem::size_of::<FwUpdateContentResponse>()];
-        w.cfu_write_read(Some(offset), &cmd_bytes, &mut resp_buf)
+        self.send_content_frame_extended::<W, { FwUpdateContentResponse::SERIALIZED_SIZE }>(
+            w,
+            frame,
+            seq_num,
+            base_offset,
+        )
+        .await
+        .map(|resp| resp.response)
+    }
+
+    /// Sends an already encoded UpdateOfferContent command frame to a component whose response is N bytes long
+    /// The standard fields are decoded from the front of the response and the rest is returned as extension data
+    pub async fn send_content_frame_extended<W: CfuWriterAsync, const N: usize>(
+        &self,
+        w: &mut W,
+        frame: &[u8; FwUpdateContentCommand::SERIALIZED_SIZE],
+        seq_num: usize,
+        base_offset: usize,
+    ) -> Result<ExtendedContentResponse<N>, CfuWriterError> {
+        let offset = base_offset + seq_num * DEFAULT_DATA_LENGTH;
+        let mut resp_buf = [0u8; N];
+        w.cfu_write_read_frame(
+            Some(offset),
+            frame,
+            FwUpdateContentCommand::SERIALIZED_SIZE,
+            &mut resp_buf,
+        )
+        .await?;
+
+        ExtendedContentResponse::deserialize(resp_buf).map_err(|_| CfuWriterError::ByteConversionError)
+    }
+
+    /// Notifies that the host is now initialized and has identified the offers to send
+    /// `hooks` bounds the command with a timeout, as for the content stream
+    pub async fn start_transaction_with_hooks<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        trace!("starting transaction, token {:#x}", u8::from(self.token));
+        self.send_offer_information(
+            writer,
+            self.token,
+            OfferInformationCodeValues::StartEntireTransaction,
+            hooks,
+        )
+        .await
+        .inspect(|resp| trace!("start transaction answered: {}", resp.status))
+        .inspect_err(|e| trace!("start transaction failed: {}", e))
+    }
+
+    /// Notifies the primary component that the host is ready to start sending offers
+    /// `hooks` bounds the command with a timeout, as for the content stream
+    pub async fn notify_start_offer_list_with_hooks<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        trace!("starting offer list, token {:#x}", u8::from(self.token));
+        self.send_offer_information(writer, self.token, OfferInformationCodeValues::StartOfferList, hooks)
+            .await
+            .inspect(|resp| trace!("start offer list answered: {}", resp.status))
+            .inspect_err(|e| trace!("start offer list failed: {}", e))
+    }
+
+    /// Notifies the primary component that the host has sent all offers
+    /// `hooks` bounds the command with a timeout, as for the content stream
+    pub async fn notify_end_offer_list_with_hooks<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        trace!("ending offer list, token {:#x}", u8::from(self.token));
+        self.send_offer_information(writer, self.token, OfferInformationCodeValues::EndOfferList, hooks)
+            .await
+            .inspect(|resp| trace!("end offer list answered: {}", resp.status))
+            .inspect_err(|e| trace!("end offer list failed: {}", e))
+    }
+
+    /// Sends an offer information command with the given code and token to the primary component
+    async fn send_offer_information<W: CfuWriterAsync>(
+        &self,
+        writer: &mut W,
+        token: HostToken,
+        code: OfferInformationCodeValues,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        let cmpt_id = SpecialComponentIds::Info as ComponentId;
+        let cmd = FwUpdateOfferInformation::new(OfferInformationComponentInfo::new(
+            token,
+            SpecialComponentIds::Info,
+            code,
+        ));
+        let cmd_bytes: [u8; FwUpdateOfferInformation::SERIALIZED_SIZE] = (&cmd).into();
+        let mut resp_buf = [0u8; FwUpdateOfferResponse::SERIALIZED_SIZE];
+        with_timeout(
+            cmpt_id,
+            writer.cfu_write_read_frame(
+                None,
+                &cmd_bytes,
+                FwUpdateOfferInformation::SERIALIZED_SIZE,
+                &mut resp_buf,
+            ),
+            hooks.timeout(),
+        )
+        .await?
+        .map_err(writer_error(cmpt_id))?;
+        FwUpdateOfferResponse::try_from(resp_buf)
+            .map_err(|_| CfuProtocolError::WriterError(CfuWriterError::ByteConversionError))
+    }
+
+    /// Sends an offer command extended with the given code to the primary component and returns its response
+    /// For OfferNotifyOnReady the component answers CommandReady once it is ready to accept offers
+    pub async fn send_extended_command<W: CfuWriterAsync>(
+        &self,
+        writer: &mut W,
+        code: OfferCommandExtendedCodeValues,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        self.send_extended_command_for(writer, code, SpecialComponentIds::Command as ComponentId, hooks)
             .await
-            .map_err(|_| CfuWriterError::StorageError)?;
+    }
+
+    /// Sends an offer command extended, reporting a timeout or writer failure against `cmpt_id`
+    async fn send_extended_command_for<W: CfuWriterAsync>(
+        &self,
+        writer: &mut W,
+        code: OfferCommandExtendedCodeValues,
+        cmpt_id: ComponentId,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        let cmd = FwUpdateOfferExtended::new(OfferExtendedComponentInfo::new(
+            self.token,
+            SpecialComponentIds::Command,
+            code,
+        ));
+        let cmd_bytes: [u8; FwUpdateOfferExtended::SERIALIZED_SIZE] = (&cmd).into();
+        let mut resp_buf = [0u8; FwUpdateOfferResponse::SERIALIZED_SIZE];
+        with_timeout(
+            cmpt_id,
+            writer.cfu_write_read_frame(None, &cmd_bytes, FwUpdateOfferExtended::SERIALIZED_SIZE, &mut resp_buf),
+            hooks.timeout(),
+        )
+        .await?
+        .map_err(writer_error(cmpt_id))?;
+        FwUpdateOfferResponse::try_from(resp_buf)
+            .map_err(|_| CfuProtocolError::WriterError(CfuWriterError::ByteConversionError))
+    }
+
+    /// Sends an offer to a component and returns its response, using `hooks` to bound the transaction with a timeout
+    pub async fn send_offer<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        offer: &FwUpdateOffer,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        let cmpt_id = offer.component_info.component_id;
+        self.phase = UpdatePhase::Offering;
+        self.last_offer = Some(*offer);
+        self.offer_accepted = false;
+        #[cfg(feature = "defmt")]
+        trace!("sending {}", offer.summary());
+        let cmd_bytes: [u8; FwUpdateOffer::SERIALIZED_SIZE] = offer.into();
+        let mut resp_buf = [0u8; FwUpdateOfferResponse::SERIALIZED_SIZE];
+        with_timeout(
+            cmpt_id,
+            writer.cfu_write_read_frame(None, &cmd_bytes, FwUpdateOffer::SERIALIZED_SIZE, &mut resp_buf),
+            hooks.timeout(),
+        )
+        .await?
+        .map_err(writer_error(cmpt_id))?;
+        let resp = FwUpdateOfferResponse::try_from(resp_buf)
+            .map_err(|_| CfuProtocolError::WriterError(CfuWriterError::ByteConversionError))?;
+        self.offer_accepted = resp.status == OfferStatus::Accept;
+        Ok(resp)
+    }
+
+    /// Sends an offer, sending it again after `hooks.retry_delay()` while the component answers Busy, up to the number
+    /// of offer retries set with `set_offer_retry`
+    /// Returns RetriesExhausted carrying the last status if the component is still busy once the retries are used up
+    pub async fn send_offer_with_retry<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        offer: &FwUpdateOffer,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        let resp = self.offer_with_retries(writer, offer, hooks).await?;
+        if resp.status.is_retryable() {
+            let component = offer.component_info.component_id;
+            trace!(
+                "component {} still busy after {} retries",
+                component,
+                self.offer_retries
+            );
+            return Err(CfuProtocolError::RetriesExhausted {
+                component,
+                last_status: resp.status,
+            });
+        }
+        Ok(resp)
+    }
+
+    /// Offer retry loop of send_offer_with_retry, returning the last response whatever its status
+    async fn offer_with_retries<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        offer: &FwUpdateOffer,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        let mut resp = self.send_offer(writer, offer, hooks).await?;
+        let mut attempts = 0;
+        while resp.status.is_retryable() && attempts < self.offer_retries {
+            attempts += 1;
+            hooks.retry_delay().await;
+            resp = self.send_offer(writer, offer, hooks).await?;
+        }
+        Ok(resp)
+    }
+
+    /// Presents a list of offers to the primary component, bracketed by start and end offer list commands
+    ///
+    /// A component responding Busy is offered again after `hooks.retry_delay()`, up to the configured number of
+    /// retries. In `ListRetryMode::PerComponent` only the busy offer is re-sent; in `ListRetryMode::WholeList` the
+    /// whole list is presented again. The responses of the final pass are returned.
+    pub async fn run_offer_list<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        offers: &[FwUpdateOffer],
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<OfferListResponses, CfuProtocolError> {
+        if offers.len() > MAX_CMPT_COUNT {
+            return Err(CfuProtocolError::InvalidComponentCount);
+        }
+
+        let mut responses = OfferListResponses::new();
+        let mut list_attempts = 0;
+        loop {
+            responses.clear();
+            let resp = self.notify_start_offer_list_with_hooks(writer, hooks).await?;
+            if resp.status != OfferStatus::Accept {
+                return Err(CfuProtocolError::CfuOfferStatusError(resp.status));
+            }
+
+            for offer in offers {
+                let resp = if self.list_retry_mode == ListRetryMode::PerComponent {
+                    self.offer_with_retries(writer, offer, hooks).await?
+                } else {
+                    self.send_offer(writer, offer, hooks).await?
+                };
+                responses
+                    .push((offer.component_info.component_id, resp))
+                    .map_err(|_| CfuProtocolError::InvalidComponentCount)?;
+            }
+
+            let resp = self.notify_end_offer_list_with_hooks(writer, hooks).await?;
+            if resp.status != OfferStatus::Accept {
+                return Err(CfuProtocolError::CfuOfferStatusError(resp.status));
+            }
+
+            let any_busy = responses.iter().any(|(_, resp)| resp.status.is_retryable());
+            if self.list_retry_mode == ListRetryMode::WholeList && any_busy && list_attempts < self.offer_retries {
+                list_attempts += 1;
+                trace!("component busy, presenting the offer list again");
+                hooks.retry_delay().await;
+                continue;
+            }
+            return Ok(responses);
+        }
+    }
+
+    /// Drives a complete update of several components
+    ///
+    /// Starts the transaction and offer list, then offers each component its image (paired by index in `images`)
+    /// at the component's `offered_version`, writes content only for accepted offers, and ends the offer list.
+    /// A component that rejects the offer (locally via offer_validity or over the wire) is recorded with
+    /// `CfuOfferStatusError(Reject)` and no content is sent to it; a component that skips or is busy is recorded
+    /// with that status so it can be offered again later. A component that answers CmdNotSupported is recorded
+    /// with `CommandNotSupported` and is not offered again. Either way the remaining components are still offered.
+    /// A component whose `is_ready` reports false is recorded as skipped without being offered.
+    /// Offers are checked locally with `offer_validity`, and a component it reports up to date is recorded with
+    /// `UpToDate` instead, and is not offered again either.
+    /// Use `SkipList::from_results` and `retry_skipped` to run the re-offer pass for skipped components.
+    /// If writing content to a component fails partway, its `on_update_failed` is called with the failing sequence.
+    /// `hooks` is used for every command of the update, offers and content alike, as in
+    /// `write_data_chunks_with_hooks`.
+    pub async fn run_update<W: CfuWriterAsync, C: CfuComponentTraits + CfuComponentFinalize, I: CfuImage>(
+        &mut self,
+        writer: &mut W,
+        components: &[C],
+        images: &[I],
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<UpdateResults, CfuProtocolError> {
+        let result = self.run_update_for(writer, components, images, hooks, |_| true).await;
+        self.finish(result)
+    }
+
+    /// Runs another update pass that offers only the components in `skipped`
+    /// `components` and `images` are the same slices given to the original `run_update`
+    /// Results are reported in component order for the retried components only
+    pub async fn retry_skipped<W: CfuWriterAsync, C: CfuComponentTraits + CfuComponentFinalize, I: CfuImage>(
+        &mut self,
+        writer: &mut W,
+        skipped: &SkipList,
+        components: &[C],
+        images: &[I],
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<UpdateResults, CfuProtocolError> {
+        let result = self
+            .run_update_for(writer, components, images, hooks, |cmpt_id| skipped.contains(cmpt_id))
+            .await;
+        self.finish(result)
+    }
+
+    /// Drives a complete update of the components in `registry`, in registration order, as in `run_update`
+    /// `images` are paired by index with the registered components
+    pub async fn run_update_registry<W: CfuWriterAsync, C: CfuComponentTraits + CfuComponentFinalize, I: CfuImage>(
+        &mut self,
+        writer: &mut W,
+        registry: &ComponentRegistry<C>,
+        images: &[I],
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<UpdateResults, CfuProtocolError> {
+        self.run_update(writer, registry.as_slice(), images, hooks).await
+    }
+
+    /// Runs the re-offer pass of `run_update_registry` for the components in `skipped`, as in `retry_skipped`
+    pub async fn retry_skipped_registry<
+        W: CfuWriterAsync,
+        C: CfuComponentTraits + CfuComponentFinalize,
+        I: CfuImage,
+    >(
+        &mut self,
+        writer: &mut W,
+        skipped: &SkipList,
+        registry: &ComponentRegistry<C>,
+        images: &[I],
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<UpdateResults, CfuProtocolError> {
+        self.retry_skipped(writer, skipped, registry.as_slice(), images, hooks)
+            .await
+    }
+
+    /// Update flow shared by run_update and retry_skipped, offering only the components selected by `include`
+    async fn run_update_for<W: CfuWriterAsync, C: CfuComponentTraits + CfuComponentFinalize, I: CfuImage>(
+        &mut self,
+        writer: &mut W,
+        components: &[C],
+        images: &[I],
+        hooks: &mut impl CfuUpdateHooks,
+        include: impl Fn(ComponentId) -> bool,
+    ) -> Result<UpdateResults, CfuProtocolError> {
+        if components.len() > MAX_CMPT_COUNT || components.len() != images.len() {
+            return Err(CfuProtocolError::InvalidComponentCount);
+        }
+
+        let mut results: UpdateResults = [None; MAX_CMPT_COUNT];
+
+        self.phase = UpdatePhase::Offering;
+        let resp = self.start_transaction_with_hooks(writer, hooks).await?;
+        if resp.status != OfferStatus::Accept {
+            return Err(CfuProtocolError::CfuOfferStatusError(resp.status));
+        }
+        let resp = self.notify_start_offer_list_with_hooks(writer, hooks).await?;
+        if resp.status != OfferStatus::Accept {
+            return Err(CfuProtocolError::CfuOfferStatusError(resp.status));
+        }
+
+        let selected = components
+            .iter()
+            .zip(images)
+            .filter(|(component, _)| include(component.get_component_id()));
+        for ((component, image), result) in selected.zip(results.iter_mut()) {
+            let cmpt_id = component.get_component_id();
+            // fail before the component prepares its bank for an image that can't fit
+            if image.get_total_size() > component.max_image_size() {
+                trace!("image for component {} is larger than its storage", cmpt_id);
+                *result = Some((cmpt_id, Err(CfuProtocolError::ImageTooLarge)));
+                continue;
+            }
+            // a component that isn't ready would only answer Busy, defer it to the re-offer pass instead
+            match component.is_ready().await {
+                Ok(true) => {}
+                Ok(false) => {
+                    trace!("component {} not ready, skipping its offer", cmpt_id);
+                    *result = Some((cmpt_id, Err(CfuProtocolError::CfuOfferStatusError(OfferStatus::Skip))));
+                    continue;
+                }
+                Err(e) => {
+                    *result = Some((cmpt_id, Err(e)));
+                    continue;
+                }
+            }
+            let outcome = match component.offer_validity().await {
+                // an equal version is skipped too, but unlike a component that can't take the offer yet there is no
+                // point offering it again later
+                Ok(OfferValidity::UpToDate) => {
+                    trace!("component {} is up to date", cmpt_id);
+                    Err(CfuProtocolError::UpToDate(cmpt_id))
+                }
+                Ok(OfferValidity::Status(OfferStatus::Accept)) => {
+                    let offer = FwUpdateOffer::builder(self.token, cmpt_id, component.offered_version()).build();
+                    match self.send_offer(writer, &offer, hooks).await {
+                        Ok(resp) if resp.status == OfferStatus::Accept => {
+                            let result = self
+                                .write_data_chunks_with_hooks(
+                                    writer,
+                                    *image,
+                                    cmpt_id,
+                                    component.get_storage_offset(),
+                                    hooks,
+                                )
+                                .await;
+                            if let Some(seq) = self.failed_sequence {
+                                // cleanup is best effort, the write failure is what gets reported
+                                if component.on_update_failed(seq).await.is_err() {
+                                    trace!("cleanup after failed update of component {} failed", cmpt_id);
+                                }
+                            }
+                            result
+                        }
+                        Ok(resp) => Err(offer_status_error(cmpt_id, resp.status)),
+                        Err(e) => Err(e),
+                    }
+                }
+                Ok(OfferValidity::Status(status)) | Err((status, _)) => Err(offer_status_error(cmpt_id, status)),
+            };
+            *result = Some((cmpt_id, outcome));
+        }
+
+        let resp = self.notify_end_offer_list_with_hooks(writer, hooks).await?;
+        if resp.status != OfferStatus::Accept {
+            return Err(CfuProtocolError::CfuOfferStatusError(resp.status));
+        }
+
+        Ok(results)
+    }
+
+    /// Write all chunks of an image, using `hooks` to bound each transaction with a timeout
+    /// and the whole stream with the content budget
+    pub async fn write_data_chunks_with_hooks<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        image: impl CfuImage,
+        cmpt_id: ComponentId,
+        base_offset: usize,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateContentResponse, CfuProtocolError> {
+        self.write_data_chunks_with_digest(writer, image, cmpt_id, base_offset, hooks, &mut NoDigest)
+            .await
+    }
+
+    /// Same as `write_data_chunks_with_hooks`, also feeding the image bytes of every block into `digest` as it is sent
+    pub async fn write_data_chunks_with_digest<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        image: impl CfuImage,
+        cmpt_id: ComponentId,
+        base_offset: usize,
+        hooks: &mut impl CfuUpdateHooks,
+        digest: &mut impl DigestSink,
+    ) -> Result<FwUpdateContentResponse, CfuProtocolError> {
+        trace!(
+            "streaming {} blocks to component {} at offset {}, token {:#x}",
+            image.block_count(),
+            cmpt_id,
+            base_offset,
+            u8::from(self.token)
+        );
+        let result = self
+            .stream_content(writer, image, cmpt_id, base_offset, 0, hooks, digest)
+            .await
+            .inspect(|resp| {
+                trace!(
+                    "content stream to component {} finished at block {}",
+                    cmpt_id,
+                    resp.sequence
+                )
+            })
+            .inspect_err(|e| trace!("content stream to component {} failed: {}", cmpt_id, e));
+        self.finish(result)
+    }
+
+    /// Writes the rest of an image to a component whose content stream was interrupted, from block `start` on
+    /// If no start is given the component is asked where it left off with `last_written_sequence`, and the stream
+    /// picks up at the block after it, or starts over if the component doesn't know, so a Host that lost its own
+    /// record of the stream, e.g. across a reboot, can still resume
+    /// Returns InvalidBlockTransition if the image has no blocks left to send from that point
+    pub async fn resume_data_chunks<W: CfuWriterAsync, C: CfuComponentInfo + CfuComponentStorage>(
+        &mut self,
+        writer: &mut W,
+        component: &C,
+        image: impl CfuImage,
+        base_offset: usize,
+        start: Option<u16>,
+    ) -> Result<FwUpdateContentResponse, CfuProtocolError> {
+        self.resume_data_chunks_with_hooks(writer, component, image, base_offset, start, &mut NoHooks)
+            .await
+    }
+
+    /// Same as `resume_data_chunks`, using `hooks` to bound each transaction with a timeout and the resumed stream
+    /// with the content budget, as in `write_data_chunks_with_hooks`
+    pub async fn resume_data_chunks_with_hooks<W: CfuWriterAsync, C: CfuComponentInfo + CfuComponentStorage>(
+        &mut self,
+        writer: &mut W,
+        component: &C,
+        image: impl CfuImage,
+        base_offset: usize,
+        start: Option<u16>,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateContentResponse, CfuProtocolError> {
+        let cmpt_id = component.get_component_id();
+        let start = match start {
+            Some(start) => start,
+            None => component
+                .last_written_sequence()
+                .await
+                .map_err(writer_error(cmpt_id))?
+                .map_or(0, |seq| seq.wrapping_add(1)),
+        };
+        if usize::from(start) >= image.block_count() {
+            trace!("nothing left to resume for component {} at block {}", cmpt_id, start);
+            return Err(CfuProtocolError::InvalidBlockTransition);
+        }
+        trace!("resuming content stream to component {} at block {}", cmpt_id, start);
+        let result = self
+            .stream_content(
+                writer,
+                image,
+                cmpt_id,
+                base_offset,
+                usize::from(start),
+                hooks,
+                &mut NoDigest,
+            )
+            .await;
+        self.finish(result)
+    }
+
+    /// Content stream of write_data_chunks_with_hooks, sending the blocks of the image from first_block on
+    #[allow(clippy::too_many_arguments)] // private, every caller passes the public method's arguments straight through
+    async fn stream_content<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        image: impl CfuImage,
+        cmpt_id: ComponentId,
+        base_offset: usize,
+        first_block: usize,
+        hooks: &mut impl CfuUpdateHooks,
+        digest: &mut impl DigestSink,
+    ) -> Result<FwUpdateContentResponse, CfuProtocolError> {
+        self.staged_sequence = None;
+        self.failed_sequence = None;
+        self.last_completed_sequence = first_block.checked_sub(1).map(|seq| seq as u16);
+        self.last_update_stats = None;
+
+        // content may only follow an accepted offer, so an offer the component didn't accept, e.g. because it was
+        // busy, is made again first. An accepted offer is not repeated, and no offer is made up if the component
+        // was offered its image some other way
+        if let Some(offer) = self
+            .last_offer
+            .filter(|offer| offer.component_info.component_id == cmpt_id && !self.offer_accepted)
+        {
+            trace!("offering component {} again ahead of its content", cmpt_id);
+            let resp = self.send_offer(writer, &offer, hooks).await?;
+            if resp.status != OfferStatus::Accept {
+                return Err(offer_status_error(cmpt_id, resp.status));
+            }
+        }
+
+        self.phase = UpdatePhase::WritingContent {
+            seq: first_block as u16,
+        };
+        let start = hooks.now_ms();
+        let deadline = self
+            .content_budget_ms
+            .zip(start)
+            .map(|(budget, start)| start.saturating_add(budget));
+
+        let num_chunks = image.block_count();
+
+        // Read and process data in chunks so as to not over-burden memory resources
+        let mut seq = first_block;
+        let result: Result<FwUpdateContentResponse, CfuProtocolError> = async {
+            let mut resp: FwUpdateContentResponse =
+                FwUpdateContentResponse::new(0, CfuUpdateContentResponseStatus::ErrorInvalid);
+            for i in first_block..num_chunks {
+                seq = i;
+                self.phase = UpdatePhase::WritingContent { seq: i as u16 };
+                Self::wait_for_block(cmpt_id, i, i > first_block, deadline, hooks).await?;
+                let frame = self.read_content_frame(image, i).await?;
+                digest.update(ContentBlockEncoder::payload(&frame));
+                resp = self
+                    .send_content_block_with_retries(writer, &frame, cmpt_id, i, base_offset, hooks)
+                    .await?;
+                self.last_completed_sequence = Some(i as u16);
+            }
+            Ok(resp)
+        }
+        .await;
+        let resp = result.inspect_err(|_| self.failed_sequence = Some(seq as u16))?;
+
+        if self.stage_only && num_chunks > first_block {
+            self.staged_sequence = Some(resp.sequence);
+        }
+        self.last_update_stats = start.zip(hooks.now_ms()).map(|(start, end)| UpdateStats {
+            bytes: image.get_total_size().saturating_sub(first_block * DEFAULT_DATA_LENGTH),
+            duration: Duration::from_millis(end.saturating_sub(start)),
+            blocks: num_chunks.saturating_sub(first_block),
+        });
+
+        Ok(resp)
+    }
+
+    /// Waits until block `seq` of a content stream to `cmpt_id` may be sent
+    /// Awaits the hooks' block_delay unless it is the first block of the stream, then their proceed, and fails if the
+    /// stream was cancelled or has run past `deadline`
+    async fn wait_for_block(
+        cmpt_id: ComponentId,
+        seq: usize,
+        delay: bool,
+        deadline: Option<u64>,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<(), CfuProtocolError> {
+        if delay {
+            hooks.block_delay().await;
+        }
+        if !hooks.proceed().await {
+            trace!("content stream to component {} cancelled at block {}", cmpt_id, seq);
+            return Err(CfuProtocolError::Cancelled(cmpt_id));
+        }
+        if let Some(deadline) = deadline {
+            if hooks.now_ms().is_some_and(|now| now > deadline) {
+                trace!("content budget exceeded for component {} at block {}", cmpt_id, seq);
+                return Err(CfuProtocolError::TimeoutError(cmpt_id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends an encoded content block to a component and checks its response, bounding each attempt with the hooks'
+    /// timeout and re-sending the block while the component fails it with a retryable status, up to the configured
+    /// number of block retries
+    async fn send_content_block_with_retries<W: CfuWriterAsync>(
+        &self,
+        writer: &mut W,
+        frame: &[u8; FwUpdateContentCommand::SERIALIZED_SIZE],
+        cmpt_id: ComponentId,
+        seq: usize,
+        base_offset: usize,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateContentResponse, CfuProtocolError> {
+        let mut attempts = 0;
+        let r = loop {
+            let r = with_timeout(
+                cmpt_id,
+                self.send_content_frame(writer, frame, seq, base_offset),
+                hooks.timeout(),
+            )
+            .await?
+            .map_err(writer_error(cmpt_id))?;
+            if !r.status.is_retryable() || attempts >= self.block_retries {
+                break r;
+            }
+            attempts += 1;
+            trace!("retrying block {} of component {}", seq, cmpt_id);
+            hooks.retry_delay().await;
+        };
+        if attempts > 0 && r.status.is_retryable() {
+            return Err(CfuProtocolError::BlockRetriesExhausted {
+                component: cmpt_id,
+                sequence: seq as u16,
+                last_status: r.status,
+            });
+        }
+        check_content_response(cmpt_id, seq, r)
+    }
+
+    /// Reads block `seq` of an image and encodes it into a content command frame
+    async fn read_content_frame(
+        &self,
+        image: impl CfuImage,
+        seq: usize,
+    ) -> Result<[u8; FwUpdateContentCommand::SERIALIZED_SIZE], CfuProtocolError> {
+        let total_bytes = image.get_total_size();
+        let num_chunks = block_count(total_bytes, DEFAULT_DATA_LENGTH);
+        let remainder = total_bytes % DEFAULT_DATA_LENGTH;
+        // bytes past the end of the image are padding, fill them deterministically
+        // the image is read straight into the frame so the block data is never copied
+        let mut frame = [self.fill_byte; FwUpdateContentCommand::SERIALIZED_SIZE];
+        let is_final = seq + 1 == num_chunks;
+        let read_len = if is_final && remainder != 0 {
+            remainder
+        } else {
+            DEFAULT_DATA_LENGTH
+        };
+        image
+            .get_bytes_for_chunk(
+                ContentBlockEncoder::data_mut(&mut frame)
+                    .get_mut(0..read_len)
+                    .ok_or(CfuProtocolError::WriterError(CfuWriterError::Other))?,
+                seq * DEFAULT_DATA_LENGTH,
+            )
+            .await
+            .map_err(|_| CfuProtocolError::WriterError(CfuWriterError::ImageReadError))?;
+        let mut flags = 0;
+        if seq == 0 {
+            flags |= FW_UPDATE_FLAG_FIRST_BLOCK;
+        }
+        // in stage only mode the final block is sent without the last block flag
+        // an image that fits in one block is sent as a single block flagged both first and last
+        if is_final && !self.stage_only {
+            flags |= FW_UPDATE_FLAG_LAST_BLOCK;
+        }
+        ContentBlockEncoder.encode_header_into(&mut frame, flags, read_len as u8, seq as u16);
+        Ok(frame)
+    }
+
+    /// Reads back an image written at base_offset and compares it to the source, block by block
+    /// Each block is read with cfu_read at the offset it was written to, and the first block that differs is
+    /// reported as `CfuProtocolError::VerifyMismatch` with its sequence number
+    pub async fn verify_written<W: CfuWriterAsync>(
+        &self,
+        writer: &mut W,
+        image: impl CfuImage,
+        base_offset: usize,
+    ) -> Result<(), CfuProtocolError> {
+        let total_bytes = image.get_total_size();
+        let mut expected = [0u8; DEFAULT_DATA_LENGTH];
+        let mut actual = [0u8; DEFAULT_DATA_LENGTH];
+        for seq in 0..image.block_count() {
+            let offset = seq * DEFAULT_DATA_LENGTH;
+            let len = DEFAULT_DATA_LENGTH.min(total_bytes - offset);
+            let expected = expected
+                .get_mut(..len)
+                .ok_or(CfuProtocolError::WriterError(CfuWriterError::Other))?;
+            let actual = actual
+                .get_mut(..len)
+                .ok_or(CfuProtocolError::WriterError(CfuWriterError::Other))?;
+            image
+                .get_bytes_for_chunk(expected, offset)
+                .await
+                .map_err(|_| CfuProtocolError::WriterError(CfuWriterError::ImageReadError))?;
+            writer.cfu_read(Some(base_offset + offset), actual).await?;
+            if expected != actual {
+                trace!("block {} read back differs from the image", seq);
+                return Err(CfuProtocolError::VerifyMismatch { sequence: seq as u16 });
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes one image to several components that take the same firmware, e.g. identical peripherals
+    /// Each block is read from the image once and sent to every component, at that component's base offset,
+    /// before moving on to the next block. Offers must already have been accepted by every component
+    /// A component that fails a block is dropped from the rest of the stream and its error is reported in its
+    /// result, the whole operation only fails if every remaining component fails the same block
+    /// `hooks` paces and bounds the stream as in `write_data_chunks_with_hooks`, with the content budget covering
+    /// the whole fan-out. Returns InvalidBlockTransition for an image without any blocks
+    /// The staged image of stage only mode can't be activated with `activate`, as it tracks a single component
+    pub async fn write_data_chunks_fanout<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        image: impl CfuImage,
+        cmpt_ids: &[ComponentId],
+        base_offsets: &[usize],
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<UpdateResults, CfuProtocolError> {
+        let result = self
+            .stream_content_fanout(writer, image, cmpt_ids, base_offsets, hooks)
+            .await;
+        self.finish(result)
+    }
+
+    /// Content stream of write_data_chunks_fanout
+    async fn stream_content_fanout<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        image: impl CfuImage,
+        cmpt_ids: &[ComponentId],
+        base_offsets: &[usize],
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<UpdateResults, CfuProtocolError> {
+        if cmpt_ids.is_empty() || cmpt_ids.len() > MAX_CMPT_COUNT || cmpt_ids.len() != base_offsets.len() {
+            return Err(CfuProtocolError::InvalidComponentCount);
+        }
+        let num_chunks = image.block_count();
+        if num_chunks == 0 {
+            trace!("no blocks to fan out");
+            return Err(CfuProtocolError::InvalidBlockTransition);
+        }
+        self.staged_sequence = None;
+        self.failed_sequence = None;
+
+        // every component's result is replaced by the response to the first block
+        let mut results: UpdateResults = [None; MAX_CMPT_COUNT];
+        for (result, cmpt_id) in results.iter_mut().zip(cmpt_ids) {
+            *result = Some((*cmpt_id, Ok(FwUpdateContentResponse::invalid_error(0))));
+        }
+
+        let deadline = self
+            .content_budget_ms
+            .zip(hooks.now_ms())
+            .map(|(budget, start)| start.saturating_add(budget));
+        for i in 0..num_chunks {
+            self.phase = UpdatePhase::WritingContent { seq: i as u16 };
+            // cancellation and the content budget are reported against the first component still being written
+            let live = results
+                .iter()
+                .flatten()
+                .find(|(_, outcome)| outcome.is_ok())
+                .map_or(0, |(cmpt_id, _)| *cmpt_id);
+            Self::wait_for_block(live, i, i > 0, deadline, hooks)
+                .await
+                .inspect_err(|_| self.failed_sequence = Some(i as u16))?;
+            let frame = self.read_content_frame(image, i).await?;
+            let mut block_error = None;
+            let mut any_ok = false;
+            for (result, base_offset) in results.iter_mut().flatten().zip(base_offsets) {
+                let (cmpt_id, outcome) = result;
+                if outcome.is_err() {
+                    continue;
+                }
+                *outcome = self
+                    .send_content_block_with_retries(writer, &frame, *cmpt_id, i, *base_offset, hooks)
+                    .await;
+                match outcome {
+                    Ok(_) => any_ok = true,
+                    Err(e) => {
+                        trace!("component {} failed block {} of fanout", *cmpt_id, i);
+                        block_error.get_or_insert(*e);
+                    }
+                }
+            }
+            if let (false, Some(e)) = (any_ok, block_error) {
+                self.failed_sequence = Some(i as u16);
+                return Err(e);
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Cancels the update in progress, e.g. after a content stream future was dropped between blocks
+    ///
+    /// CFU has no dedicated abort command. A new OFFER_INFO_START_ENTIRE_TRANSACTION tells the component to drop any
+    /// transaction in progress, so that is what gets sent, with `token`. The 16 bytes sent are
+    /// `[0x00, 0x00, 0xFF, token, 0x00 * 12]`: the StartEntireTransaction code, a reserved byte, the Info special
+    /// component id, and the host token, followed by reserved bytes.
+    /// The updater returns to Idle with no staged, failed or completed sequence and no record of the last offer,
+    /// whether or not the component accepts the command, so the next write_data_chunks starts from sequence 0 and
+    /// must follow a new offer.
+    pub async fn abort<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        token: HostToken,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        trace!("aborting update");
+        self.phase = UpdatePhase::Idle;
+        self.staged_sequence = None;
+        self.failed_sequence = None;
+        self.last_completed_sequence = None;
+        self.last_update_stats = None;
+        // the component drops the transaction, so the offer it accepted no longer covers any content
+        self.last_offer = None;
+        self.offer_accepted = false;
+        let resp = self
+            .send_offer_information(writer, token, OfferInformationCodeValues::StartEntireTransaction, hooks)
+            .await?;
+        if resp.status != OfferStatus::Accept {
+            return Err(CfuProtocolError::CfuOfferStatusError(resp.status));
+        }
+        Ok(resp)
+    }
+
+    /// Commits an image previously written in stage only mode (activation phase)
+    /// CFU defines no activation frame, so this is a crate extension: an offer command extended to the Command
+    /// special component with the vendor specific code `OfferCommandExtendedCodeValues::ACTIVATE_STAGED_IMAGE`.
+    /// The component answers Accept once it has verified and swapped to the staged image, and a component that doesn't
+    /// know the extension answers CmdNotSupported, reported as `CfuProtocolError::CommandNotSupported`
+    /// `hooks` bounds the command with a timeout
+    pub async fn activate<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        cmpt_id: ComponentId,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        let result = self.send_activation(writer, cmpt_id, hooks).await;
+        self.finish(result)
+    }
+
+    /// Activation command of activate
+    async fn send_activation<W: CfuWriterAsync>(
+        &mut self,
+        writer: &mut W,
+        cmpt_id: ComponentId,
+        hooks: &mut impl CfuUpdateHooks,
+    ) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        if self.staged_sequence.is_none() {
+            trace!("no staged image to activate for component {}", cmpt_id);
+            return Err(CfuProtocolError::InvalidBlockTransition);
+        }
+        self.phase = UpdatePhase::Offering;
+        let resp = self
+            .send_extended_command_for(
+                writer,
+                OfferCommandExtendedCodeValues::ACTIVATE_STAGED_IMAGE,
+                cmpt_id,
+                hooks,
+            )
+            .await?;
+        match resp.status {
+            OfferStatus::Accept => {}
+            OfferStatus::CmdNotSupported => return Err(offer_status_error(cmpt_id, resp.status)),
+            _ => return Err(CfuProtocolError::UpdateError(cmpt_id)),
+        }
+
+        self.staged_sequence = None;
+        Ok(resp)
+    }
+}
+
+/// The trait methods consume the updater, use the `_with_hooks` inherent methods to keep its state across commands
+impl<W: CfuWriterAsync> CfuHostStates<W> for CfuUpdater {
+    /// Notifies that the host is now initialized and has identified the offers to send
+    async fn start_transaction(mut self, writer: &mut W) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        self.start_transaction_with_hooks(writer, &mut NoHooks).await
+    }
+
+    /// Notifies the primary component that the host is ready to start sending offers
+    async fn notify_start_offer_list(mut self, writer: &mut W) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        self.notify_start_offer_list_with_hooks(writer, &mut NoHooks).await
+    }
+
+    /// Notifies the primary component that the host has sent all offers
+    async fn notify_end_offer_list(mut self, writer: &mut W) -> Result<FwUpdateOfferResponse, CfuProtocolError> {
+        self.notify_end_offer_list_with_hooks(writer, &mut NoHooks).await
+    }
+}
+
+impl<W: CfuWriterAsync> CfuUpdateContent<W> for CfuUpdater {
+    /// Write all chunks of an image
+    async fn write_data_chunks(
+        &mut self,
+        writer: &mut W,
+        image: impl CfuImage,
+        cmpt_id: ComponentId,
+        base_offset: usize,
+    ) -> Result<FwUpdateContentResponse, CfuProtocolError> {
+        self.write_data_chunks_with_hooks(writer, image, cmpt_id, base_offset, &mut NoHooks)
+            .await
+    }
+
+    /// Build and send UpdateOfferContent command with first block flag
+    async fn process_first_data_block(
+        &mut self,
+        w: &mut W,
+        chunk: DataChunk,
+        base_offset: usize,
+    ) -> Result<FwUpdateContentResponse, CfuWriterError> {
+        self.send_content_block(
+            w,
+            FW_UPDATE_FLAG_FIRST_BLOCK,
+            chunk,
+            DEFAULT_DATA_LENGTH,
+            0,
+            base_offset,
+        )
+        .await
+    }
+
+    /// Build and send UpdateOfferContent command, no special flags
+    async fn process_middle_data_block(
+        &mut self,
+        w: &mut W,
+        chunk: DataChunk,
+        seq_num: usize,
+        base_offset: usize,
+    ) -> Result<FwUpdateContentResponse, CfuWriterError> {
+        self.send_content_block(w, 0, chunk, DEFAULT_DATA_LENGTH, seq_num, base_offset)
+            .await
+    }
+
+    /// Build and send UpdateOfferContent command with last block flag
+    /// Fails with ByteConversionError unless data_length is in 1..=DEFAULT_DATA_LENGTH
+    async fn process_last_data_block(
+        &mut self,
+        w: &mut W,
+        chunk: DataChunk,
+        data_length: usize,
+        seq_num: usize,
+        base_offset: usize,
+    ) -> Result<FwUpdateContentResponse, CfuWriterError> {
+        if !(1..=DEFAULT_DATA_LENGTH).contains(&data_length) {
+            trace!("last block {} can't carry {} bytes", seq_num, data_length);
+            return Err(CfuWriterError::ByteConversionError);
+        }
+        self.send_content_block(w, FW_UPDATE_FLAG_LAST_BLOCK, chunk, data_length, seq_num, base_offset)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use core::cell::Cell;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+    use embedded_io_async::{ErrorType, Read, Seek, SeekFrom};
+
+    use super::*;
+    use crate::components::CfuComponentInfo;
+    use crate::protocol_definitions::{FwVersion, MAX_SUBCMPT_COUNT};
+
+    /// Image that yields an incrementing byte pattern
+    #[derive(Copy, Clone, Debug)]
+    struct MockImage {
+        size: usize,
+        pos: usize,
+        /// Reads past this point come up short, as if the backing storage failed
+        readable: usize,
+    }
+
+    impl MockImage {
+        fn new(size: usize) -> Self {
+            Self {
+                size,
+                pos: 0,
+                readable: usize::MAX,
+            }
+        }
+    }
+
+    impl ErrorType for MockImage {
+        type Error = core::convert::Infallible;
+    }
+
+    impl Read for MockImage {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            let len = buf.len().min(self.size.min(self.readable).saturating_sub(self.pos));
+            for (i, b) in buf.iter_mut().take(len).enumerate() {
+                *b = (self.pos + i) as u8;
+            }
+            self.pos += len;
+            Ok(len)
+        }
+    }
+
+    impl Seek for MockImage {
+        async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+            if let SeekFrom::Start(offset) = pos {
+                self.pos = offset as usize;
+            }
+            Ok(self.pos as u64)
+        }
+    }
+
+    impl CfuImage for MockImage {
+        fn get_total_size(self) -> usize {
+            self.size
+        }
+
+        async fn get_bytes_for_chunk(
+            mut self,
+            buf: &mut [u8],
+            offset: usize,
+        ) -> Result<(), embedded_io_async::ReadExactError<Self::Error>> {
+            self.seek(SeekFrom::Start(offset as u64)).await?;
+            self.read_exact(buf).await
+        }
+    }
+
+    /// Writer whose transactions never complete
+    struct PendingWriter;
+
+    impl CfuWriterAsync for PendingWriter {
+        async fn cfu_write_read(
+            &mut self,
+            _mem_offset: Option<usize>,
+            _data: &[u8],
+            _read: &mut [u8],
+        ) -> Result<(), CfuWriterError> {
+            core::future::pending().await
+        }
+
+        async fn cfu_read(&mut self, _mem_offset: Option<usize>, _read: &mut [u8]) -> Result<(), CfuWriterError> {
+            core::future::pending().await
+        }
+
+        async fn cfu_write(&mut self, _mem_offset: Option<usize>, _data: &[u8]) -> Result<(), CfuWriterError> {
+            core::future::pending().await
+        }
+
+        async fn cfu_storage(&mut self, _mem_offset: usize, _data: &[u8]) -> Result<(), CfuWriterError> {
+            core::future::pending().await
+        }
+    }
+
+    /// Writer that records every frame it is sent
+    /// Content commands are acked with their own sequence number, anything else is treated as an offer
+    /// and answered with the status configured for its component id (Accept by default)
+    #[derive(Default)]
+    struct RecordingWriter {
+        frames: Vec<Vec<u8>>,
+        offer_statuses: Vec<(ComponentId, OfferStatus)>,
+        /// Components that respond Busy to this many offers before using their configured status
+        busy_offers: Vec<(ComponentId, usize)>,
+        /// Error reported by the writer for every content command, instead of acking it
+        content_error: Option<CfuWriterError>,
+        /// Content command sequence number answered with ErrorWrite
+        nak_sequence: Option<u16>,
+        /// Content command sequence number answered with ErrorWrite the first time it is sent only
+        transient_nak_sequence: Option<u16>,
+        /// Offset at which a content command is answered with ErrorWrite
+        nak_offset: Option<usize>,
+        /// Content command sequence number that is never answered
+        stall_sequence: Option<u16>,
+        /// Content command sequence number acked with another sequence number
+        misack_sequence: Option<(u16, u16)>,
+        /// Offsets each frame was written at
+        offsets: Vec<Option<usize>>,
+    }
+
+    impl RecordingWriter {
+        /// Content command frames that were sent, decoded
+        fn content_commands(&self) -> Vec<FwUpdateContentCommand> {
+            self.frames
+                .iter()
+                .filter_map(|f| <&[u8; FwUpdateContentCommand::SERIALIZED_SIZE]>::try_from(f.as_slice()).ok())
+                .map(|f| FwUpdateContentCommand::try_from(f).unwrap())
+                .collect()
+        }
+
+        /// Component ids of the offers that were sent, in order
+        fn offered_components(&self) -> Vec<ComponentId> {
+            self.frames
+                .iter()
+                .filter(|f| f.len() == FwUpdateOffer::SERIALIZED_SIZE)
+                .map(|f| f[2])
+                .filter(|id| !SpecialComponentIds::is_reserved(*id))
+                .collect()
+        }
+    }
+
+    impl CfuWriterAsync for RecordingWriter {
+        async fn cfu_write_read(
+            &mut self,
+            mem_offset: Option<usize>,
+            data: &[u8],
+            read: &mut [u8],
+        ) -> Result<(), CfuWriterError> {
+            self.frames.push(data.to_vec());
+            self.offsets.push(mem_offset);
+            let resp: [u8; FwUpdateOfferResponse::SERIALIZED_SIZE] =
+                match <&[u8; FwUpdateContentCommand::SERIALIZED_SIZE]>::try_from(data) {
+                    Ok(_) if self.content_error.is_some() => return Err(self.content_error.unwrap()),
+                    Ok(frame) => {
+                        let sequence = FwUpdateContentCommand::try_from(frame).unwrap().header.sequence_num;
+                        if self.stall_sequence == Some(sequence) {
+                            core::future::pending::<()>().await;
+                        }
+                        let transient_nak = self.transient_nak_sequence == Some(sequence);
+                        if transient_nak {
+                            self.transient_nak_sequence = None;
+                        }
+                        let status = if transient_nak
+                            || self.nak_sequence == Some(sequence)
+                            || (mem_offset.is_some() && self.nak_offset == mem_offset)
+                        {
+                            CfuUpdateContentResponseStatus::ErrorWrite
+                        } else {
+                            CfuUpdateContentResponseStatus::Success
+                        };
+                        let sequence = match self.misack_sequence {
+                            Some((sent, acked)) if sent == sequence => acked,
+                            _ => sequence,
+                        };
+                        (&FwUpdateContentResponse::new(sequence, status)).into()
+                    }
+                    Err(_) => {
+                        let busy = self
+                            .busy_offers
+                            .iter_mut()
+                            .find(|(id, count)| *id == data[2] && *count > 0);
+                        let status = if let Some((_, count)) = busy {
+                            *count -= 1;
+                            OfferStatus::Busy
+                        } else {
+                            self.offer_statuses
+                                .iter()
+                                .find(|(id, _)| *id == data[2])
+                                .map_or(OfferStatus::Accept, |(_, status)| *status)
+                        };
+                        let token = HostToken::try_from(data[3]).unwrap();
+                        if status == OfferStatus::Accept {
+                            (&FwUpdateOfferResponse::new_accept(token)).into()
+                        } else {
+                            (&FwUpdateOfferResponse::new_with_failure(token, OfferRejectReason::OldFw, status)).into()
+                        }
+                    }
+                };
+            read.copy_from_slice(&resp);
+            Ok(())
+        }
+
+        async fn cfu_read(&mut self, _mem_offset: Option<usize>, _read: &mut [u8]) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        async fn cfu_write(&mut self, mem_offset: Option<usize>, data: &[u8]) -> Result<(), CfuWriterError> {
+            self.frames.push(data.to_vec());
+            self.offsets.push(mem_offset);
+            Ok(())
+        }
+
+        async fn cfu_storage(&mut self, _mem_offset: usize, _data: &[u8]) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct MockComponent {
+        id: ComponentId,
+        /// Sequence number on_update_failed was called with
+        failed_at: Cell<Option<u16>>,
+        max_image_size: usize,
+        not_ready: Cell<bool>,
+        subcomponents: [Option<ComponentId>; MAX_SUBCMPT_COUNT],
+        /// Sequence number reported by last_written_sequence
+        last_written: Option<u16>,
+        /// Version reported by get_fw_version
+        current: FwVersion,
+        /// Number of times get_fw_version was called
+        version_reads: Cell<usize>,
+        /// Skip every offer, as a component with its own offer policy might
+        policy_skip: bool,
+    }
+
+    impl MockComponent {
+        fn new(id: ComponentId) -> Self {
+            Self {
+                id,
+                failed_at: Cell::new(None),
+                max_image_size: usize::MAX,
+                not_ready: Cell::new(false),
+                subcomponents: [None; MAX_SUBCMPT_COUNT],
+                last_written: None,
+                current: FwVersion::default(),
+                version_reads: Cell::new(0),
+                policy_skip: false,
+            }
+        }
+
+        /// Component depending on the given subcomponents
+        fn with_subcomponents(id: ComponentId, subcomponents: &[ComponentId]) -> Self {
+            let mut component = Self::new(id);
+            for (slot, sub) in component.subcomponents.iter_mut().zip(subcomponents) {
+                *slot = Some(*sub);
+            }
+            component
+        }
+    }
+
+    impl CfuComponentInfo for MockComponent {
+        async fn get_fw_version(&self) -> Result<FwVersion, CfuProtocolError> {
+            self.version_reads.set(self.version_reads.get() + 1);
+            Ok(self.current)
+        }
+
+        async fn offer_validity(&self) -> Result<OfferValidity, (OfferStatus, OfferRejectReason)> {
+            let current = self
+                .get_fw_version()
+                .await
+                .map_err(|_| (OfferStatus::Reject, OfferRejectReason::OldFw))?;
+            match self.offered_version().cmp(&current) {
+                _ if self.policy_skip => Ok(OfferValidity::Status(OfferStatus::Skip)),
+                core::cmp::Ordering::Greater => Ok(OfferValidity::Status(OfferStatus::Accept)),
+                core::cmp::Ordering::Equal => Ok(OfferValidity::UpToDate),
+                core::cmp::Ordering::Less => Err((OfferStatus::Reject, OfferRejectReason::OldFw)),
+            }
+        }
+
+        fn get_component_id(&self) -> ComponentId {
+            self.id
+        }
+
+        fn offered_version(&self) -> FwVersion {
+            FwVersion::new(0x01000000)
+        }
+
+        async fn is_ready(&self) -> Result<bool, CfuProtocolError> {
+            Ok(!self.not_ready.get())
+        }
+
+        fn is_dual_bank(&self) -> bool {
+            false
+        }
+
+        fn get_subcomponents(&self) -> [Option<ComponentId>; MAX_SUBCMPT_COUNT] {
+            self.subcomponents
+        }
+    }
+
+    impl CfuComponentStorage for MockComponent {
+        async fn storage_prepare(&self) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        async fn storage_write(&self) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        async fn storage_finalize(&self) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        fn max_image_size(&self) -> usize {
+            self.max_image_size
+        }
+
+        async fn last_written_sequence(&self) -> Result<Option<u16>, CfuWriterError> {
+            Ok(self.last_written)
+        }
+    }
+
+    impl CfuComponentFinalize for MockComponent {
+        async fn on_update_failed(&self, at_sequence: u16) -> Result<(), CfuWriterError> {
+            self.failed_at.set(Some(at_sequence));
+            Ok(())
+        }
+    }
+
+    impl CfuComponentTraits for MockComponent {}
+
+    /// Hooks that count how many times the host waited before retrying
+    #[derive(Default)]
+    struct CountingDelay {
+        delays: usize,
+    }
+
+    impl CfuUpdateHooks for CountingDelay {
+        async fn retry_delay(&mut self) {
+            self.delays += 1;
+        }
+    }
+
+    /// Hooks that count how many times the host waited between content blocks
+    #[derive(Default)]
+    struct CountingBlockDelay {
+        delays: usize,
+    }
+
+    impl CfuUpdateHooks for CountingBlockDelay {
+        async fn block_delay(&mut self) {
+            self.delays += 1;
+        }
+    }
+
+    #[test]
+    fn test_write_data_chunks_block_delay() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let mut hooks = CountingBlockDelay::default();
+        block_on(updater.write_data_chunks_with_hooks(&mut writer, MockImage::new(130), 1, 0, &mut hooks)).unwrap();
+        assert_eq!(writer.content_commands().len(), 3);
+        assert_eq!(hooks.delays, 2);
+
+        // a single block image is never delayed
+        let mut hooks = CountingBlockDelay::default();
+        block_on(updater.write_data_chunks_with_hooks(&mut writer, MockImage::new(40), 1, 0, &mut hooks)).unwrap();
+        assert_eq!(hooks.delays, 0);
+    }
+
+    /// Hooks that yield to other tasks between blocks and count the blocks a PauseHandle let through
+    struct YieldingPause<'a> {
+        handle: &'a PauseHandle,
+        released: &'a Cell<usize>,
+    }
+
+    impl CfuUpdateHooks for YieldingPause<'_> {
+        async fn block_delay(&mut self) {
+            embassy_futures::yield_now().await;
+        }
+
+        async fn proceed(&mut self) -> bool {
+            let proceed = self.handle.wait_resumed().await;
+            if proceed {
+                self.released.set(self.released.get() + 1);
+            }
+            proceed
+        }
+    }
+
+    #[test]
+    fn test_pause_and_resume_content_stream() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let handle = PauseHandle::new();
+        let released = Cell::new(0);
+        let mut hooks = YieldingPause {
+            handle: &handle,
+            released: &released,
+        };
+
+        let control = async {
+            while released.get() < 2 {
+                embassy_futures::yield_now().await;
+            }
+            handle.pause();
+            // the stream parks before block 2 however long it is left paused
+            for _ in 0..10 {
+                embassy_futures::yield_now().await;
+            }
+            assert_eq!(released.get(), 2);
+            handle.resume();
+        };
+        let (result, ()) = block_on(embassy_futures::join::join(
+            updater.write_data_chunks_with_hooks(&mut writer, MockImage::new(260), 1, 0, &mut hooks),
+            control,
+        ));
+        assert_eq!(result.unwrap().sequence, 4);
+        let sequences: Vec<u16> = writer
+            .content_commands()
+            .iter()
+            .map(|c| c.header.sequence_num)
+            .collect();
+        assert_eq!(sequences, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_cancel_paused_content_stream() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let handle = PauseHandle::new();
+        let released = Cell::new(0);
+        let mut hooks = YieldingPause {
+            handle: &handle,
+            released: &released,
+        };
+
+        let control = async {
+            while released.get() < 3 {
+                embassy_futures::yield_now().await;
+            }
+            handle.pause();
+            embassy_futures::yield_now().await;
+            handle.cancel();
+            // a cancelled handle can't be resumed
+            handle.resume();
+            assert!(handle.is_cancelled());
+        };
+        let (result, ()) = block_on(embassy_futures::join::join(
+            updater.write_data_chunks_with_hooks(&mut writer, MockImage::new(260), 1, 0, &mut hooks),
+            control,
+        ));
+        assert_eq!(result, Err(CfuProtocolError::Cancelled(1)));
+        assert_eq!(updater.failed_sequence(), Some(3));
+        assert_eq!(writer.content_commands().len(), 3);
+    }
+
+    /// Hooks whose timeout fires as soon as it is polled
+    struct ImmediateTimeout;
+
+    impl CfuUpdateHooks for ImmediateTimeout {
+        fn timeout(&mut self) -> impl Future<Output = ()> {
+            core::future::ready(())
+        }
+    }
+
+    #[test]
+    fn test_write_data_chunks_times_out() {
+        let mut updater = CfuUpdater::new();
+        let result = block_on(updater.write_data_chunks_with_hooks(
+            &mut PendingWriter,
+            MockImage::new(DEFAULT_DATA_LENGTH * 3),
+            2,
+            0,
+            &mut ImmediateTimeout,
+        ));
+        assert_eq!(result, Err(CfuProtocolError::TimeoutError(2)));
+    }
+
+    #[test]
+    fn test_offer_path_times_out() {
+        let mut updater = CfuUpdater::new();
+        let offer = FwUpdateOffer::new(HostToken::Driver, 3, FwVersion::new(0x01000000), 0, 0);
+        assert_eq!(
+            block_on(updater.send_offer(&mut PendingWriter, &offer, &mut ImmediateTimeout)),
+            Err(CfuProtocolError::TimeoutError(3))
+        );
+        // offer information commands are addressed to the Info special component
+        assert_eq!(
+            block_on(updater.start_transaction_with_hooks(&mut PendingWriter, &mut ImmediateTimeout)),
+            Err(CfuProtocolError::TimeoutError(SpecialComponentIds::Info as ComponentId))
+        );
+    }
+
+    /// Hooks whose clock advances by a fixed step every time it is read
+    struct SteppingClock {
+        now: u64,
+        step: u64,
+    }
+
+    impl CfuUpdateHooks for SteppingClock {
+        fn now_ms(&mut self) -> Option<u64> {
+            let now = self.now;
+            self.now += self.step;
+            Some(now)
+        }
+    }
+
+    #[test]
+    fn test_write_data_chunks_content_budget() {
+        let mut updater = CfuUpdater::new();
+        updater.set_content_budget_ms(Some(250));
+
+        // every block is fast, but the stream as a whole overruns the budget
+        let mut writer = RecordingWriter::default();
+        let mut clock = SteppingClock { now: 1000, step: 100 };
+        let result = block_on(updater.write_data_chunks_with_hooks(
+            &mut writer,
+            MockImage::new(DEFAULT_DATA_LENGTH * 5),
+            2,
+            0,
+            &mut clock,
+        ));
+        assert_eq!(result, Err(CfuProtocolError::TimeoutError(2)));
+        // the blocks sent at 1100 and 1200, the check at 1300 aborts
+        assert_eq!(writer.frames.len(), 2);
+        assert_eq!(updater.failed_sequence(), Some(2));
+
+        // within budget
+        let mut writer = RecordingWriter::default();
+        let mut clock = SteppingClock { now: 1000, step: 10 };
+        let result = block_on(updater.write_data_chunks_with_hooks(
+            &mut writer,
+            MockImage::new(DEFAULT_DATA_LENGTH * 5),
+            2,
+            0,
+            &mut clock,
+        ));
+        assert!(result.is_ok());
+
+        // without a clock the budget can't be enforced
+        updater.set_content_budget_ms(Some(0));
+        let result = block_on(updater.write_data_chunks_with_hooks(
+            &mut RecordingWriter::default(),
+            MockImage::new(DEFAULT_DATA_LENGTH * 5),
+            2,
+            0,
+            &mut NoHooks,
+        ));
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_last_update_stats() {
+        let mut updater = CfuUpdater::new();
+        let mut clock = SteppingClock { now: 1000, step: 250 };
+        block_on(updater.write_data_chunks_with_hooks(
+            &mut RecordingWriter::default(),
+            MockImage::new(DEFAULT_DATA_LENGTH * 4 + 20),
+            2,
+            0,
+            &mut clock,
+        ))
+        .unwrap();
+        // the clock is read once as the stream starts and once after the last block
+        let stats = updater.last_update_stats().unwrap();
+        assert_eq!(stats.bytes, DEFAULT_DATA_LENGTH * 4 + 20);
+        assert_eq!(stats.blocks, 5);
+        assert_eq!(stats.duration, Duration::from_millis(250));
+        assert_eq!(stats.bytes_per_second(), Some(912));
+
+        // a failed stream leaves no stats behind
+        let mut writer = RecordingWriter {
+            nak_sequence: Some(1),
+            ..Default::default()
+        };
+        assert!(block_on(updater.write_data_chunks_with_hooks(
+            &mut writer,
+            MockImage::new(DEFAULT_DATA_LENGTH * 4),
+            2,
+            0,
+            &mut clock
+        ))
+        .is_err());
+        assert_eq!(updater.last_update_stats(), None);
+
+        // and neither does a stream without a clock
+        block_on(updater.write_data_chunks(&mut RecordingWriter::default(), MockImage::new(104), 2, 0)).unwrap();
+        assert_eq!(updater.last_update_stats(), None);
+        assert_eq!(UpdateStats::default().bytes_per_second(), None);
+    }
+
+    #[test]
+    fn test_with_timeout_passes_through_output() {
+        let result = block_on(with_timeout(1, core::future::ready(5u8), core::future::pending()));
+        assert_eq!(result, Ok(5));
+    }
+
+    /// Hooks whose delay before block `stall_at` never completes
+    struct StallBeforeBlock {
+        stall_at: usize,
+        delays: usize,
+    }
+
+    impl CfuUpdateHooks for StallBeforeBlock {
+        async fn block_delay(&mut self) {
+            self.delays += 1;
+            if self.delays == self.stall_at {
+                core::future::pending::<()>().await;
+            }
+        }
+    }
+
+    #[test]
+    fn test_last_completed_sequence_survives_dropped_stream() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let mut hooks = StallBeforeBlock { stall_at: 2, delays: 0 };
+        let image = MockImage::new(DEFAULT_DATA_LENGTH * 4);
+
+        let stream = updater.write_data_chunks_with_hooks(&mut writer, image, 1, 0, &mut hooks);
+        assert!(embassy_futures::poll_once(stream).is_pending());
+        // the future was dropped after blocks 0 and 1 were acked
+        assert_eq!(updater.last_completed_sequence(), Some(1));
+        assert_eq!(writer.content_commands().len(), 2);
+
+        let start = updater.last_completed_sequence().map(|seq| seq + 1);
+        let resp = block_on(updater.resume_data_chunks(&mut writer, &MockComponent::new(1), image, 0, start)).unwrap();
+        assert_eq!(resp.sequence, 3);
+        assert_eq!(updater.last_completed_sequence(), Some(3));
+        assert!(writer
+            .content_commands()
+            .iter()
+            .map(|c| c.header.sequence_num)
+            .eq([0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn test_resume_from_component_sequence() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let component = MockComponent {
+            last_written: Some(3),
+            ..MockComponent::new(1)
+        };
+        let image = MockImage::new(DEFAULT_DATA_LENGTH * 6);
+
+        let resp = block_on(updater.resume_data_chunks(&mut writer, &component, image, 0, None)).unwrap();
+        assert_eq!(resp.sequence, 5);
+        let commands = writer.content_commands();
+        assert!(commands.iter().map(|c| c.header.sequence_num).eq([4, 5]));
+        // resumed blocks carry the data at their own offset, and never restart the image with the first block flag
+        assert_eq!(commands[0].data[0], (DEFAULT_DATA_LENGTH * 4) as u8);
+        assert_eq!(commands[0].header.flags & FW_UPDATE_FLAG_FIRST_BLOCK, 0);
+        assert_eq!(commands[1].header.flags, FW_UPDATE_FLAG_LAST_BLOCK);
+        assert_eq!(writer.offsets.last(), Some(&Some(DEFAULT_DATA_LENGTH * 5)));
+
+        // an explicit start wins over the component
+        let mut writer = RecordingWriter::default();
+        block_on(updater.resume_data_chunks(&mut writer, &component, image, 0, Some(2))).unwrap();
+        assert_eq!(writer.content_commands()[0].header.sequence_num, 2);
+
+        // a component that doesn't track its progress starts over
+        let mut writer = RecordingWriter::default();
+        block_on(updater.resume_data_chunks(&mut writer, &MockComponent::new(1), image, 0, None)).unwrap();
+        assert_eq!(writer.content_commands().len(), 6);
+
+        let done = MockComponent {
+            last_written: Some(5),
+            ..MockComponent::new(1)
+        };
+        assert_eq!(
+            block_on(updater.resume_data_chunks(&mut writer, &done, image, 0, None)),
+            Err(CfuProtocolError::InvalidBlockTransition)
+        );
+
+        // a resumed stream is bounded by the hooks like any other
+        let mut writer = RecordingWriter {
+            stall_sequence: Some(4),
+            ..Default::default()
+        };
+        assert_eq!(
+            block_on(updater.resume_data_chunks_with_hooks(
+                &mut writer,
+                &component,
+                image,
+                0,
+                None,
+                &mut ImmediateTimeout
+            )),
+            Err(CfuProtocolError::TimeoutError(1))
+        );
+        assert_eq!(updater.failed_sequence(), Some(4));
+    }
+
+    #[test]
+    fn test_stage_then_activate() {
+        let mut updater = CfuUpdater::new();
+        updater.set_stage_only(true);
+        let mut writer = RecordingWriter::default();
+
+        // download phase: no block may carry the last block flag
+        let resp = block_on(updater.write_data_chunks(&mut writer, MockImage::new(130), 1, 0)).unwrap();
+        assert_eq!(resp.sequence, 2);
+        let staged = writer.content_commands();
+        assert_eq!(staged.len(), 3);
+        assert_eq!(staged[0].header.flags, FW_UPDATE_FLAG_FIRST_BLOCK);
+        assert!(staged
+            .iter()
+            .all(|cmd| cmd.header.flags & FW_UPDATE_FLAG_LAST_BLOCK == 0));
+
+        // an activation the component never answers times out and leaves the image staged
+        assert_eq!(
+            block_on(updater.activate(&mut PendingWriter, 1, &mut ImmediateTimeout)),
+            Err(CfuProtocolError::TimeoutError(1))
+        );
+
+        // a component that doesn't know the extension leaves the image staged too
+        let mut unsupported = RecordingWriter {
+            offer_statuses: std::vec![(
+                SpecialComponentIds::Command as ComponentId,
+                OfferStatus::CmdNotSupported
+            )],
+            ..Default::default()
+        };
+        assert_eq!(
+            block_on(updater.activate(&mut unsupported, 1, &mut NoHooks)),
+            Err(CfuProtocolError::CommandNotSupported(1))
+        );
+
+        // activation phase: a vendor extended command commits the staged image, no content is sent
+        writer.frames.clear();
+        let resp = block_on(updater.activate(&mut writer, 1, &mut NoHooks)).unwrap();
+        assert_eq!(resp.status, OfferStatus::Accept);
+        assert!(writer.content_commands().is_empty());
+        let cmd = FwUpdateOfferExtended::try_from(
+            <&[u8; FwUpdateOfferExtended::SERIALIZED_SIZE]>::try_from(writer.frames[0].as_slice()).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            cmd.component_info.code,
+            OfferCommandExtendedCodeValues::ACTIVATE_STAGED_IMAGE
+        );
+
+        // nothing left to activate
+        assert_eq!(
+            block_on(updater.activate(&mut writer, 1, &mut NoHooks)),
+            Err(CfuProtocolError::InvalidBlockTransition)
+        );
+    }
+
+    #[test]
+    fn test_write_data_chunks_repeats_unaccepted_offer() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter {
+            busy_offers: std::vec![(1, 1)],
+            ..Default::default()
+        };
+        let offer = FwUpdateOffer::new(HostToken::Tool, 1, FwVersion::new(0x01020304), 0x55, 2);
+        let resp = block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks)).unwrap();
+        assert_eq!(resp.status, OfferStatus::Busy);
+        block_on(updater.write_data_chunks(&mut writer, MockImage::new(52), 1, 0x100)).unwrap();
+
+        // the busy offer is made again, as the serialized offer, before any content
+        let offer_bytes: [u8; FwUpdateOffer::SERIALIZED_SIZE] = (&offer).into();
+        assert_eq!(writer.frames[1], offer_bytes);
+        assert_eq!(writer.offsets[1], None);
+        assert_eq!(writer.content_commands().len(), 1);
+
+        // now that it is accepted it isn't repeated
+        let mut writer = RecordingWriter::default();
+        block_on(updater.write_data_chunks(&mut writer, MockImage::new(52), 1, 0x100)).unwrap();
+        assert_eq!(writer.offered_components(), Vec::<ComponentId>::new());
+        assert_eq!(writer.frames.len(), 1);
+    }
+
+    #[test]
+    fn test_write_data_chunks_stops_on_rejected_offer() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter {
+            offer_statuses: std::vec![(1, OfferStatus::Reject)],
+            ..Default::default()
+        };
+        let offer = FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::new(0x01020304), 0, 0);
+        block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks)).unwrap();
+
+        // the component turns the offer down again, so none of the image is sent
+        assert_eq!(
+            block_on(updater.write_data_chunks(&mut writer, MockImage::new(52 * 3), 1, 0)),
+            Err(CfuProtocolError::CfuOfferStatusError(OfferStatus::Reject))
+        );
+        assert_eq!(writer.offered_components(), std::vec![1, 1]);
+        assert!(writer.content_commands().is_empty());
+    }
+
+    #[test]
+    fn test_write_data_chunks_retries_failed_block() {
+        let mut updater = CfuUpdater::new();
+        updater.set_block_retries(2);
+        let mut writer = RecordingWriter {
+            transient_nak_sequence: Some(2),
+            ..Default::default()
+        };
+        let mut hooks = CountingDelay::default();
+        let resp =
+            block_on(updater.write_data_chunks_with_hooks(&mut writer, MockImage::new(52 * 4), 1, 0, &mut hooks))
+                .unwrap();
+        assert_eq!(resp.sequence, 3);
+        // the host backs off before re-sending, the same as for a busy offer
+        assert_eq!(hooks.delays, 1);
+
+        // block 2 is re-sent as is, the sequence number only advances once it is acked
+        let sequences: Vec<u16> = writer
+            .content_commands()
+            .iter()
+            .map(|cmd| cmd.header.sequence_num)
+            .collect();
+        assert_eq!(sequences, std::vec![0, 1, 2, 2, 3]);
+
+        // a block that keeps failing gives up once the retries are used up
+        let mut writer = RecordingWriter {
+            nak_sequence: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            block_on(updater.write_data_chunks(&mut writer, MockImage::new(52 * 4), 1, 0)),
+            Err(CfuProtocolError::BlockRetriesExhausted {
+                component: 1,
+                sequence: 1,
+                last_status: CfuUpdateContentResponseStatus::ErrorWrite,
+            })
+        );
+        assert_eq!(writer.content_commands().len(), 4);
+        assert_eq!(updater.failed_sequence(), Some(1));
+
+        // without retries the block fails as any other
+        updater.set_block_retries(0);
+        let mut writer = RecordingWriter {
+            nak_sequence: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            block_on(updater.write_data_chunks(&mut writer, MockImage::new(52 * 4), 1, 0)),
+            Err(CfuProtocolError::UpdateError(1))
+        );
+    }
+
+    #[test]
+    fn test_write_data_chunks_single_block_image() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let resp = block_on(updater.write_data_chunks(&mut writer, MockImage::new(40), 1, 0)).unwrap();
+        assert_eq!(resp.sequence, 0);
+        let cmds = writer.content_commands();
+        assert_eq!(cmds.len(), 1);
+        assert_eq!(cmds[0].header.flags, 0xC0);
+        assert_eq!(cmds[0].header.data_length, 40);
+
+        // the ack of the single block is still checked
+        let mut writer = RecordingWriter {
+            misack_sequence: Some((0, 1)),
+            ..Default::default()
+        };
+        assert_eq!(
+            block_on(updater.write_data_chunks(&mut writer, MockImage::new(40), 1, 0)),
+            Err(CfuProtocolError::UnexpectedSequence { expected: 0, actual: 1 })
+        );
+    }
+
+    #[test]
+    fn test_write_data_chunks_sends_last_block() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        block_on(updater.write_data_chunks(&mut writer, MockImage::new(130), 1, 0)).unwrap();
+        let cmds = writer.content_commands();
+        assert_eq!(cmds.len(), 3);
+        assert_eq!(cmds[2].header.flags, FW_UPDATE_FLAG_LAST_BLOCK);
+        assert_eq!(
+            block_on(updater.activate(&mut writer, 1, &mut NoHooks)),
+            Err(CfuProtocolError::InvalidBlockTransition)
+        );
+    }
+
+    #[test]
+    fn test_process_last_data_block_data_length() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let chunk = [0x5A; DEFAULT_DATA_LENGTH];
+        // the final 26 bytes of a 130 byte image, driven through the trait rather than write_data_chunks
+        let resp = block_on(updater.process_last_data_block(&mut writer, chunk, 26, 2, 0)).unwrap();
+        assert_eq!(resp.sequence, 2);
+        let cmds = writer.content_commands();
+        assert_eq!(cmds[0].header.flags, FW_UPDATE_FLAG_LAST_BLOCK);
+        assert_eq!(cmds[0].header.data_length, 26);
+
+        for data_length in [0, DEFAULT_DATA_LENGTH + 1] {
+            assert_eq!(
+                block_on(updater.process_last_data_block(&mut writer, chunk, data_length, 2, 0)),
+                Err(CfuWriterError::ByteConversionError)
+            );
+        }
+        assert_eq!(writer.content_commands().len(), 1);
+    }
+
+    #[test]
+    fn test_run_update_offers_every_component() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter {
+            offer_statuses: std::vec![(2, OfferStatus::Reject), (3, OfferStatus::Skip)],
+            ..Default::default()
+        };
+        let components = [MockComponent::new(1), MockComponent::new(2), MockComponent::new(3)];
+        let images = [MockImage::new(130); 3];
+
+        let results = block_on(updater.run_update(&mut writer, &components, &images, &mut NoHooks)).unwrap();
+
+        let (id, result) = results[0].unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(result.unwrap().sequence, 2);
+        assert_eq!(
+            results[1],
+            Some((2, Err(CfuProtocolError::CfuOfferStatusError(OfferStatus::Reject))))
+        );
+        assert_eq!(
+            results[2],
+            Some((3, Err(CfuProtocolError::CfuOfferStatusError(OfferStatus::Skip))))
+        );
+        assert!(results[3..].iter().all(Option::is_none));
+
+        // every component is offered, but content only flows to the accepted one
+        assert_eq!(writer.offered_components(), std::vec![1, 2, 3]);
+        assert_eq!(writer.content_commands().len(), 3);
+        // and each offer carries the version the component reports through offered_version
+        let offered_versions: Vec<FwVersion> = writer
+            .frames
+            .iter()
+            .filter(|f| f.len() == FwUpdateOffer::SERIALIZED_SIZE && !SpecialComponentIds::is_reserved(f[2]))
+            .map(|f| {
+                FwUpdateOffer::try_from(<&[u8; 16]>::try_from(f.as_slice()).unwrap())
+                    .unwrap()
+                    .firmware_version
+            })
+            .collect();
+        assert_eq!(offered_versions, std::vec![components[0].offered_version(); 3]);
+
+        // the offer list is opened and closed around the offers
+        let info_codes: Vec<u8> = writer
+            .frames
+            .iter()
+            .filter(|f| f.len() == 16 && f[2] == SpecialComponentIds::Info as u8)
+            .map(|f| f[0])
+            .collect();
+        assert_eq!(info_codes, std::vec![0x00, 0x01, 0x02]);
+    }
+
+    #[test]
+    fn test_write_data_chunks_fanout() {
+        let mut updater = CfuUpdater::new();
+        let image = MockImage::new(130);
+        let mut writer = RecordingWriter::default();
+
+        let mut hooks = CountingBlockDelay::default();
+        let results =
+            block_on(updater.write_data_chunks_fanout(&mut writer, image, &[1, 2], &[0, 0x1000], &mut hooks)).unwrap();
+        assert_eq!(results[0], Some((1, Ok(FwUpdateContentResponse::success(2)))));
+        assert_eq!(results[1], Some((2, Ok(FwUpdateContentResponse::success(2)))));
+        assert!(results[2..].iter().all(Option::is_none));
+        // the stream is paced per block, not per component
+        assert_eq!(hooks.delays, 2);
+
+        // every block goes to both components before the next one is read
+        let sequences: Vec<u16> = writer
+            .content_commands()
+            .iter()
+            .map(|c| c.header.sequence_num)
+            .collect();
+        assert_eq!(sequences, std::vec![0, 0, 1, 1, 2, 2]);
+        assert_eq!(
+            writer.offsets,
+            std::vec![Some(0), Some(0x1000), Some(52), Some(0x1034), Some(104), Some(0x1068)]
+        );
+        let commands = writer.content_commands();
+        assert!(commands.chunks(2).all(|pair| pair[0] == pair[1]));
+        assert_eq!(commands[5].header.flags, FW_UPDATE_FLAG_LAST_BLOCK);
+
+        // one component failing a block is dropped, the other carries on
+        let mut writer = RecordingWriter {
+            nak_offset: Some(0x1034),
+            ..Default::default()
+        };
+        let results =
+            block_on(updater.write_data_chunks_fanout(&mut writer, image, &[1, 2], &[0, 0x1000], &mut NoHooks))
+                .unwrap();
+        assert_eq!(results[0], Some((1, Ok(FwUpdateContentResponse::success(2)))));
+        assert_eq!(results[1], Some((2, Err(CfuProtocolError::UpdateError(2)))));
+        assert_eq!(writer.content_commands().len(), 5);
+
+        // every component failing the same block fails the whole operation
+        let mut writer = RecordingWriter {
+            nak_sequence: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            block_on(updater.write_data_chunks_fanout(&mut writer, image, &[1, 2], &[0, 0x1000], &mut NoHooks)),
+            Err(CfuProtocolError::UpdateError(1))
+        );
+        assert_eq!(updater.failed_sequence(), Some(1));
+        assert_eq!(
+            block_on(updater.write_data_chunks_fanout(&mut writer, image, &[1, 2], &[0], &mut NoHooks)),
+            Err(CfuProtocolError::InvalidComponentCount)
+        );
+
+        // an image without blocks has nothing to report for any component
+        assert_eq!(
+            block_on(updater.write_data_chunks_fanout(
+                &mut writer,
+                MockImage::new(0),
+                &[1, 2],
+                &[0, 0x1000],
+                &mut NoHooks
+            )),
+            Err(CfuProtocolError::InvalidBlockTransition)
+        );
+
+        // blocks are bounded by the timeout and re-sent after a transient failure like any other content
+        let mut writer = RecordingWriter {
+            stall_sequence: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            block_on(updater.write_data_chunks_fanout(
+                &mut writer,
+                image,
+                &[1, 2],
+                &[0, 0x1000],
+                &mut ImmediateTimeout
+            )),
+            Err(CfuProtocolError::TimeoutError(1))
+        );
+        updater.set_block_retries(1);
+        let mut writer = RecordingWriter {
+            transient_nak_sequence: Some(1),
+            ..Default::default()
+        };
+        let results =
+            block_on(updater.write_data_chunks_fanout(&mut writer, image, &[1, 2], &[0, 0x1000], &mut NoHooks))
+                .unwrap();
+        assert_eq!(results[0], Some((1, Ok(FwUpdateContentResponse::success(2)))));
+        assert_eq!(results[1], Some((2, Ok(FwUpdateContentResponse::success(2)))));
+        assert_eq!(writer.content_commands().len(), 7);
+    }
+
+    #[test]
+    fn test_update_phase() {
+        let mut updater = CfuUpdater::new();
+        assert_eq!(updater.phase(), UpdatePhase::Idle);
+
+        let mut writer = RecordingWriter::default();
+        let offer = FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::new(0x01000000), 0, 0);
+        block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks)).unwrap();
+        assert_eq!(updater.phase(), UpdatePhase::Offering);
+
+        // an update cancelled mid-stream shows the block it was sending
+        let mut writer = RecordingWriter {
+            stall_sequence: Some(2),
+            ..Default::default()
+        };
+        let pending = embassy_futures::poll_once(updater.write_data_chunks(&mut writer, MockImage::new(200), 1, 0));
+        assert!(pending.is_pending());
+        assert_eq!(updater.phase(), UpdatePhase::WritingContent { seq: 2 });
+
+        let mut writer = RecordingWriter {
+            nak_sequence: Some(1),
+            ..Default::default()
+        };
+        assert!(block_on(updater.write_data_chunks(&mut writer, MockImage::new(200), 1, 0)).is_err());
+        assert_eq!(updater.phase(), UpdatePhase::Failed);
+        assert_eq!(updater.failed_sequence(), Some(1));
+
+        // a fresh write starts over after the failure
+        let mut writer = RecordingWriter::default();
+        block_on(updater.write_data_chunks(&mut writer, MockImage::new(200), 1, 0)).unwrap();
+        assert_eq!(updater.phase(), UpdatePhase::Done);
+        assert_eq!(updater.failed_sequence(), None);
+
+        let components = [MockComponent::new(1)];
+        let images = [MockImage::new(200)];
+        block_on(updater.run_update(&mut RecordingWriter::default(), &components, &images, &mut NoHooks)).unwrap();
+        assert_eq!(updater.phase(), UpdatePhase::Done);
+    }
+
+    #[test]
+    fn test_run_update_uses_hooks() {
+        let mut updater = CfuUpdater::new();
+        let components = [MockComponent::new(1), MockComponent::new(2)];
+        let images = [MockImage::new(130); 2];
+
+        // three blocks per component, so two waits between blocks for each
+        let mut hooks = CountingBlockDelay::default();
+        block_on(updater.run_update(&mut RecordingWriter::default(), &components, &images, &mut hooks)).unwrap();
+        assert_eq!(hooks.delays, 4);
+
+        // a component that stops answering mid-image times out instead of hanging the update
+        let mut writer = RecordingWriter {
+            stall_sequence: Some(1),
+            ..Default::default()
+        };
+        let results = block_on(updater.run_update(&mut writer, &components, &images, &mut ImmediateTimeout)).unwrap();
+        assert_eq!(results[0], Some((1, Err(CfuProtocolError::TimeoutError(1)))));
+        assert_eq!(components[0].failed_at.get(), Some(1));
+    }
+
+    #[test]
+    fn test_run_update_defers_unready_component() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let components = [MockComponent::new(1), MockComponent::new(2)];
+        components[0].not_ready.set(true);
+        let images = [MockImage::new(100); 2];
+
+        let results = block_on(updater.run_update(&mut writer, &components, &images, &mut NoHooks)).unwrap();
+        assert_eq!(
+            results[0],
+            Some((1, Err(CfuProtocolError::CfuOfferStatusError(OfferStatus::Skip))))
+        );
+        assert!(results[1].unwrap().1.is_ok());
+        assert_eq!(writer.offered_components(), std::vec![2]);
+
+        // once ready, the deferred component is picked up by the re-offer pass
+        let skipped = SkipList::from_results(&results);
+        assert_eq!(skipped.as_slice(), &[1]);
+        components[0].not_ready.set(false);
+        let mut writer = RecordingWriter::default();
+        let results =
+            block_on(updater.retry_skipped(&mut writer, &skipped, &components, &images, &mut NoHooks)).unwrap();
+        assert!(results[0].unwrap().1.is_ok());
+        assert_eq!(writer.offered_components(), std::vec![1]);
+    }
+
+    /// Hooks that let a number of blocks through and then hold the stream forever
+    struct StallAfter(usize);
+
+    impl CfuUpdateHooks for StallAfter {
+        async fn proceed(&mut self) -> bool {
+            if self.0 == 0 {
+                core::future::pending::<()>().await;
+            }
+            self.0 -= 1;
+            true
+        }
+    }
+
+    #[test]
+    fn test_abort_between_blocks() {
+        use crate::client::StandardClient;
+        use crate::image::SliceImage;
+        use crate::loopback::{LoopbackWriter, RamComponent};
+
+        let client = StandardClient::new(RamComponent::<256>::new(
+            1,
+            FwVersion::new(0x01000000),
+            FwVersion::new(0x02000000),
+        ));
+        let mut writer = LoopbackWriter::new(&client);
+        let mut updater = CfuUpdater::new();
+        let data = [0x3C; 200];
+        let offer = FwUpdateOffer::builder(updater.token(), 1, FwVersion::new(0x02000000)).build();
+        block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks)).unwrap();
+
+        let pending = embassy_futures::poll_once(updater.write_data_chunks_with_hooks(
+            &mut writer,
+            SliceImage::new(&data),
+            1,
+            0,
+            &mut StallAfter(2),
+        ));
+        assert!(pending.is_pending());
+        assert_eq!(updater.phase(), UpdatePhase::WritingContent { seq: 2 });
+        assert_eq!(updater.last_completed_sequence(), Some(1));
+
+        let mut recorder = RecordingWriter::default();
+        let resp = block_on(updater.abort(&mut recorder, HostToken::Driver, &mut NoHooks)).unwrap();
+        assert_eq!(resp.status, OfferStatus::Accept);
+        assert_eq!(
+            recorder.frames,
+            std::vec![crate::wire_fixtures::OFFER_INFO_START_TRANSACTION.to_vec()]
+        );
+        let resp = block_on(updater.abort(&mut writer, HostToken::Driver, &mut NoHooks)).unwrap();
+        assert_eq!(resp.status, OfferStatus::Accept);
+        assert_eq!(updater.phase(), UpdatePhase::Idle);
+        // nothing of the aborted stream is left to resume
+        assert_eq!(updater.last_completed_sequence(), None);
+        assert_eq!(updater.last_update_stats(), None);
+
+        // the component dropped the offer along with the transaction, so content alone is refused
+        assert_eq!(
+            block_on(updater.write_data_chunks(&mut writer, SliceImage::new(&data), 1, 0)),
+            Err(CfuProtocolError::UpdateError(1))
+        );
+
+        // the next update starts over from a new offer and the first block
+        block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks)).unwrap();
+        let resp = block_on(updater.write_data_chunks(&mut writer, SliceImage::new(&data), 1, 0)).unwrap();
+        assert_eq!(resp.sequence, 3);
+        assert!(client.component().is_finalized());
+        assert_eq!(client.component().image(), data);
+    }
+
+    #[test]
+    fn test_image_read_failure_is_not_a_transport_error() {
+        let mut updater = CfuUpdater::new();
+        let image = MockImage {
+            readable: 60,
+            ..MockImage::new(130)
+        };
+        let mut writer = RecordingWriter::default();
+        assert_eq!(
+            block_on(updater.write_data_chunks(&mut writer, image, 1, 0)),
+            Err(CfuProtocolError::WriterError(CfuWriterError::ImageReadError))
+        );
+        assert_eq!(updater.failed_sequence(), Some(1));
+
+        // a bus failure reported by the writer is passed through as is
+        let mut writer = RecordingWriter {
+            content_error: Some(CfuWriterError::TransportError),
+            ..Default::default()
+        };
+        assert_eq!(
+            block_on(updater.write_data_chunks(&mut writer, MockImage::new(130), 1, 0)),
+            Err(CfuProtocolError::WriterError(CfuWriterError::TransportError))
+        );
+    }
+
+    /// DigestSink that counts and checksums the bytes it is fed
+    #[derive(Default)]
+    struct CountingDigest {
+        len: usize,
+        sum: u32,
+    }
+
+    impl DigestSink for CountingDigest {
+        fn update(&mut self, data: &[u8]) {
+            self.len += data.len();
+            self.sum += data.iter().map(|b| *b as u32).sum::<u32>();
+        }
+    }
+
+    #[test]
+    fn test_write_data_chunks_feeds_digest() {
+        let mut updater = CfuUpdater::new();
+        updater.set_fill_byte(0xFF);
+        let image = MockImage::new(130);
+        let mut digest = CountingDigest::default();
+        block_on(updater.write_data_chunks_with_digest(
+            &mut RecordingWriter::default(),
+            image,
+            1,
+            0,
+            &mut NoHooks,
+            &mut digest,
+        ))
+        .unwrap();
+
+        // the padding of the final block is never fed
+        assert_eq!(digest.len, image.get_total_size());
+        assert_eq!(digest.sum, (0..130u32).sum::<u32>());
+    }
+
+    #[test]
+    fn test_update_order_puts_subcomponents_first() {
+        let components = [
+            MockComponent::with_subcomponents(1, &[2, 3]),
+            MockComponent::new(2),
+            MockComponent::new(3),
+            MockComponent::new(4),
+        ];
+        let order = update_order(&components).unwrap();
+        assert_eq!(order.as_slice(), &[1, 2, 3, 0]);
+
+        // nested subcomponents are ordered too, and ids that aren't among the components are ignored
+        let components = [
+            MockComponent::with_subcomponents(1, &[2]),
+            MockComponent::with_subcomponents(2, &[3, 9]),
+            MockComponent::new(3),
+        ];
+        assert_eq!(update_order(&components).unwrap().as_slice(), &[2, 1, 0]);
+    }
+
+    #[test]
+    fn test_update_order_detects_cycle() {
+        let components = [
+            MockComponent::new(4),
+            MockComponent::with_subcomponents(1, &[2]),
+            MockComponent::with_subcomponents(2, &[1]),
+        ];
+        assert_eq!(update_order(&components), Err(CfuProtocolError::DependencyCycle(1)));
+        assert_eq!(
+            update_order(&[MockComponent::with_subcomponents(5, &[5])]),
+            Err(CfuProtocolError::DependencyCycle(5))
+        );
+    }
+
+    #[test]
+    fn test_rejected_offers() {
+        let token = HostToken::Driver;
+        let responses = [
+            (1, FwUpdateOfferResponse::new_accept(token)),
+            (
+                2,
+                FwUpdateOfferResponse::new_with_failure(token, OfferRejectReason::OldFw, OfferStatus::Reject),
+            ),
+            (
+                3,
+                FwUpdateOfferResponse::new_with_failure(token, OfferRejectReason::OldFw, OfferStatus::Busy),
+            ),
+            (
+                4,
+                FwUpdateOfferResponse::new_with_failure(token, OfferRejectReason::SwapPending, OfferStatus::Reject),
+            ),
+            (
+                5,
+                FwUpdateOfferResponse::new_with_failure(token, OfferRejectReason::OldFw, OfferStatus::Skip),
+            ),
+        ];
+        assert!(rejected_offers(&responses).eq([(2, OfferRejectReason::OldFw), (4, OfferRejectReason::SwapPending)]));
+        assert_eq!(rejected_offers(&responses[..1]).count(), 0);
+    }
+
+    /// Writer backed by a flat memory that cfu_read reads back from
+    struct ReadbackWriter {
+        memory: Vec<u8>,
+    }
+
+    impl CfuWriterAsync for ReadbackWriter {
+        async fn cfu_write_read(
+            &mut self,
+            _mem_offset: Option<usize>,
+            _data: &[u8],
+            _read: &mut [u8],
+        ) -> Result<(), CfuWriterError> {
+            Err(CfuWriterError::Other)
+        }
+
+        async fn cfu_read(&mut self, mem_offset: Option<usize>, read: &mut [u8]) -> Result<(), CfuWriterError> {
+            let offset = mem_offset.ok_or(CfuWriterError::Other)?;
+            let bytes = self
+                .memory
+                .get(offset..offset + read.len())
+                .ok_or(CfuWriterError::StorageError)?;
+            read.copy_from_slice(bytes);
+            Ok(())
+        }
+
+        async fn cfu_write(&mut self, _mem_offset: Option<usize>, _data: &[u8]) -> Result<(), CfuWriterError> {
+            Err(CfuWriterError::Other)
+        }
+
+        async fn cfu_storage(&mut self, _mem_offset: usize, _data: &[u8]) -> Result<(), CfuWriterError> {
+            Err(CfuWriterError::Other)
+        }
+    }
+
+    #[test]
+    fn test_verify_written() {
+        let updater = CfuUpdater::new();
+        let image = MockImage::new(DEFAULT_DATA_LENGTH * 3 + 10);
+        let base_offset = 0x20;
+        let mut memory = std::vec![0u8; base_offset];
+        memory.extend((0..image.get_total_size()).map(|i| i as u8));
+        let mut writer = ReadbackWriter { memory };
+
+        block_on(updater.verify_written(&mut writer, image, base_offset)).unwrap();
+
+        // a single flipped byte in the third block is reported against that block
+        writer.memory[base_offset + DEFAULT_DATA_LENGTH * 2 + 5] ^= 0x01;
+        assert_eq!(
+            block_on(updater.verify_written(&mut writer, image, base_offset)),
+            Err(CfuProtocolError::VerifyMismatch { sequence: 2 })
+        );
+
+        // so is a mismatch in the short final block
+        writer.memory[base_offset + DEFAULT_DATA_LENGTH * 2 + 5] ^= 0x01;
+        *writer.memory.last_mut().unwrap() ^= 0x01;
+        assert_eq!(
+            block_on(updater.verify_written(&mut writer, image, base_offset)),
+            Err(CfuProtocolError::VerifyMismatch { sequence: 3 })
+        );
+
+        // a component that can't read back as much as was written is a writer error
+        writer.memory.pop();
+        assert_eq!(
+            block_on(updater.verify_written(&mut writer, image, base_offset)),
+            Err(CfuProtocolError::WriterError(CfuWriterError::StorageError))
+        );
+    }
+
+    #[test]
+    fn test_run_update_rejects_oversized_image() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let small = MockComponent {
+            max_image_size: 128,
+            ..MockComponent::new(1)
+        };
+        let components = [small, MockComponent::new(2)];
+        let images = [MockImage::new(130); 2];
+
+        let results = block_on(updater.run_update(&mut writer, &components, &images, &mut NoHooks)).unwrap();
+        assert_eq!(results[0], Some((1, Err(CfuProtocolError::ImageTooLarge))));
+        assert!(results[1].unwrap().1.is_ok());
+        // the oversized component is never offered
+        assert_eq!(writer.offered_components(), std::vec![2]);
+    }
+
+    #[test]
+    fn test_run_update_rejects_mismatched_images() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let components = [MockComponent::new(1), MockComponent::new(2)];
+        let images = [MockImage::new(52)];
+        assert_eq!(
+            block_on(updater.run_update(&mut writer, &components, &images, &mut NoHooks)),
+            Err(CfuProtocolError::InvalidComponentCount)
+        );
+        assert!(writer.frames.is_empty());
+    }
+
+    #[test]
+    fn test_updates_remaining_lists_busy_and_skipped() {
+        let responses = [
+            (1, FwUpdateOfferResponse::new_accept(HostToken::Driver)),
+            (
+                2,
+                FwUpdateOfferResponse::new_with_failure(HostToken::Driver, OfferRejectReason::OldFw, OfferStatus::Busy),
+            ),
+            (
+                3,
+                FwUpdateOfferResponse::new_with_failure(
+                    HostToken::Driver,
+                    OfferRejectReason::OldFw,
+                    OfferStatus::Reject,
+                ),
+            ),
+            (
+                4,
+                FwUpdateOfferResponse::new_with_failure(HostToken::Driver, OfferRejectReason::OldFw, OfferStatus::Skip),
+            ),
+        ];
+        let remaining = <CfuUpdater as CfuHostStates<RecordingWriter>>::updates_remaining(&responses).unwrap_err();
+        assert_eq!(remaining.as_slice(), &[2, 4]);
+        assert!(!block_on(
+            <CfuUpdater as CfuHostStates<RecordingWriter>>::verify_all_updates_completed(
+                &responses.map(|(_, resp)| resp)
+            )
+        )
+        .unwrap());
+
+        assert_eq!(
+            <CfuUpdater as CfuHostStates<RecordingWriter>>::updates_remaining(&responses[..1]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_token_generator_wraps_and_skips_reserved() {
+        let mut tokens = TokenGenerator::with_range(0x9E, 0xA1).unwrap();
+        let produced: Vec<HostToken> = (0..4).map(|_| tokens.next_token()).collect();
+        assert_eq!(
+            produced,
+            std::vec![
+                HostToken::VendorSpecific(0x9E),
+                HostToken::VendorSpecific(0x9F),
+                HostToken::VendorSpecific(0xA1),
+                HostToken::VendorSpecific(0x9E),
+            ]
+        );
+
+        let mut tokens = TokenGenerator::new();
+        for _ in 0..1024 {
+            assert!(!HostToken::is_reserved(tokens.next_token().into()));
+        }
+
+        assert_eq!(TokenGenerator::with_range(0xA0, 0xA0), None);
+        assert_eq!(TokenGenerator::with_range(0x10, 0x0F), None);
+    }
+
+    #[test]
+    fn test_token_is_stamped_on_transaction() {
+        let mut tokens = TokenGenerator::with_range(0x42, 0x50).unwrap();
+        let mut updater = CfuUpdater::new();
+        updater.set_token(tokens.next_token());
+        let mut writer = RecordingWriter::default();
+        let components = [MockComponent::new(1)];
+        let images = [MockImage::new(52)];
+        block_on(updater.run_update(&mut writer, &components, &images, &mut NoHooks)).unwrap();
+
+        // start transaction, start offer list, offer and end offer list all carry the same token
+        // the accepted offer isn't repeated ahead of the content
+        let stamped = writer.frames.iter().filter(|f| f.len() != 60 && f[3] == 0x42).count();
+        assert_eq!(stamped, 4);
+        assert_eq!(tokens.next_token(), HostToken::VendorSpecific(0x43));
+    }
+
+    fn offers(ids: &[ComponentId]) -> Vec<FwUpdateOffer> {
+        ids.iter()
+            .map(|id| FwUpdateOffer::new(HostToken::Driver, *id, FwVersion::default(), 0, 0))
+            .collect()
+    }
+
+    #[test]
+    fn test_run_offer_list_whole_list_retry() {
+        let mut updater = CfuUpdater::new();
+        updater.set_offer_retry(ListRetryMode::WholeList, 3);
+        let mut writer = RecordingWriter {
+            busy_offers: std::vec![(2, 1)],
+            ..Default::default()
+        };
+        let mut hooks = CountingDelay::default();
+
+        let responses = block_on(updater.run_offer_list(&mut writer, &offers(&[1, 2, 3]), &mut hooks)).unwrap();
+
+        // a single busy component causes every offer to be presented again
+        assert_eq!(writer.offered_components(), std::vec![1, 2, 3, 1, 2, 3]);
+        assert_eq!(hooks.delays, 1);
+        assert!(responses.iter().all(|(_, resp)| resp.status == OfferStatus::Accept));
+        let ids: Vec<ComponentId> = responses.iter().map(|(id, _)| *id).collect();
+        assert_eq!(ids, std::vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_run_offer_list_per_component_retry() {
+        let mut updater = CfuUpdater::new();
+        updater.set_offer_retry(ListRetryMode::PerComponent, 3);
+        let mut writer = RecordingWriter {
+            busy_offers: std::vec![(2, 5)],
+            ..Default::default()
+        };
+        let mut hooks = CountingDelay::default();
+
+        let responses = block_on(updater.run_offer_list(&mut writer, &offers(&[1, 2, 3]), &mut hooks)).unwrap();
+
+        // only the busy component is offered again, until retries run out
+        assert_eq!(writer.offered_components(), std::vec![1, 2, 2, 2, 2, 3]);
+        assert_eq!(hooks.delays, 3);
+        assert_eq!(responses[1].1.status, OfferStatus::Busy);
+    }
+
+    #[test]
+    fn test_send_offer_with_retry_exhausted() {
+        let mut updater = CfuUpdater::new();
+        updater.set_offer_retry(ListRetryMode::PerComponent, 2);
+        let mut writer = RecordingWriter {
+            offer_statuses: std::vec![(1, OfferStatus::Busy)],
+            ..Default::default()
+        };
+        let mut hooks = CountingDelay::default();
+
+        let offer = FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::default(), 0, 0);
+        assert_eq!(
+            block_on(updater.send_offer_with_retry(&mut writer, &offer, &mut hooks)),
+            Err(CfuProtocolError::RetriesExhausted {
+                component: 1,
+                last_status: OfferStatus::Busy,
+            })
+        );
+        assert_eq!(writer.offered_components(), std::vec![1, 1, 1]);
+        assert_eq!(hooks.delays, 2);
+
+        // a component that stops being busy within the retries is answered normally
+        let mut writer = RecordingWriter {
+            busy_offers: std::vec![(1, 2)],
+            ..Default::default()
+        };
+        let resp = block_on(updater.send_offer_with_retry(&mut writer, &offer, &mut hooks)).unwrap();
+        assert_eq!(resp.status, OfferStatus::Accept);
+    }
+
+    #[test]
+    fn test_skipped_component_is_queued_for_retry() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter {
+            offer_statuses: std::vec![(2, OfferStatus::Skip)],
+            ..Default::default()
+        };
+        let components = [MockComponent::new(1), MockComponent::new(2), MockComponent::new(3)];
+        let images = [MockImage::new(104); 3];
+
+        let results = block_on(updater.run_update(&mut writer, &components, &images, &mut NoHooks)).unwrap();
+        assert!(results[0].unwrap().1.is_ok());
+        assert!(results[2].unwrap().1.is_ok());
+        let skipped = SkipList::from_results(&results);
+        assert_eq!(skipped.as_slice(), &[2]);
+
+        // the component is ready now, only it is offered on the retry pass
+        writer.offer_statuses.clear();
+        writer.frames.clear();
+        let results =
+            block_on(updater.retry_skipped(&mut writer, &skipped, &components, &images, &mut NoHooks)).unwrap();
+        assert_eq!(writer.offered_components(), std::vec![2]);
+        let (id, result) = results[0].unwrap();
+        assert_eq!(id, 2);
+        assert!(result.is_ok());
+        assert!(results[1..].iter().all(Option::is_none));
+        assert!(SkipList::from_results(&results).is_empty());
+    }
+
+    #[test]
+    fn test_run_update_registry() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter {
+            offer_statuses: std::vec![(1, OfferStatus::Skip)],
+            ..Default::default()
+        };
+        let mut registry = ComponentRegistry::new();
+        registry.register(MockComponent::new(3)).unwrap();
+        registry.register(MockComponent::new(1)).unwrap();
+        let images = [MockImage::new(104); 2];
+
+        // components are offered in registration order
+        let results = block_on(updater.run_update_registry(&mut writer, &registry, &images, &mut NoHooks)).unwrap();
+        assert_eq!(writer.offered_components(), std::vec![3, 1]);
+        assert!(results[0].unwrap().1.is_ok());
+        let skipped = SkipList::from_results(&results);
+        assert_eq!(skipped.as_slice(), &[1]);
+
+        writer.offer_statuses.clear();
+        writer.frames.clear();
+        let results =
+            block_on(updater.retry_skipped_registry(&mut writer, &skipped, &registry, &images, &mut NoHooks)).unwrap();
+        assert_eq!(writer.offered_components(), std::vec![1]);
+        assert!(results[0].unwrap().1.is_ok());
+
+        // images still have to pair up with the registered components
+        assert_eq!(
+            block_on(updater.run_update_registry(&mut writer, &registry, &images[..1], &mut NoHooks)),
+            Err(CfuProtocolError::InvalidComponentCount)
+        );
+    }
+
+    #[test]
+    fn test_up_to_date_component_is_not_queued() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        let up_to_date = MockComponent {
+            current: FwVersion::new(0x01000000),
+            ..MockComponent::new(2)
+        };
+        let unready = MockComponent::new(3);
+        unready.not_ready.set(true);
+        let components = [MockComponent::new(1), up_to_date, unready];
+        let images = [MockImage::new(104); 3];
+
+        let results = block_on(updater.run_update(&mut writer, &components, &images, &mut NoHooks)).unwrap();
+        assert!(results[0].unwrap().1.is_ok());
+        // both are skipped, but only the component that wasn't ready has anything left to do
+        assert_eq!(results[1], Some((2, Err(CfuProtocolError::UpToDate(2)))));
+        // the version is read once to decide, not again to classify the skip
+        assert_eq!(components[1].version_reads.get(), 1);
+        assert_eq!(
+            results[2],
+            Some((3, Err(CfuProtocolError::CfuOfferStatusError(OfferStatus::Skip))))
+        );
+        assert_eq!(writer.offered_components(), std::vec![1]);
+        assert_eq!(SkipList::from_results(&results).as_slice(), &[3]);
+
+        // a component whose own policy skips the offer is deferred even though its version matches
+        let policy = MockComponent {
+            current: FwVersion::new(0x01000000),
+            policy_skip: true,
+            ..MockComponent::new(4)
+        };
+        let policy_results = block_on(updater.run_update(&mut writer, &[policy], &images[..1], &mut NoHooks)).unwrap();
+        assert_eq!(
+            policy_results[0],
+            Some((4, Err(CfuProtocolError::CfuOfferStatusError(OfferStatus::Skip))))
+        );
+        assert_eq!(SkipList::from_results(&policy_results).as_slice(), &[4]);
+
+        // so a caller retrying until the list is empty finishes
+        components[2].not_ready.set(false);
+        let skipped = SkipList::from_results(&results);
+        let results =
+            block_on(updater.retry_skipped(&mut writer, &skipped, &components, &images, &mut NoHooks)).unwrap();
+        assert!(SkipList::from_results(&results).is_empty());
+    }
+
+    #[test]
+    fn test_run_update_command_not_supported() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter {
+            offer_statuses: std::vec![(2, OfferStatus::CmdNotSupported), (3, OfferStatus::Reject)],
+            ..Default::default()
+        };
+        let components = [MockComponent::new(1), MockComponent::new(2), MockComponent::new(3)];
+        let images = [MockImage::new(104); 3];
+
+        let results = block_on(updater.run_update(&mut writer, &components, &images, &mut NoHooks)).unwrap();
+        assert!(results[0].unwrap().1.is_ok());
+        // told apart from a component that rejects the update
+        assert_eq!(results[1], Some((2, Err(CfuProtocolError::CommandNotSupported(2)))));
+        assert_eq!(
+            results[2],
+            Some((3, Err(CfuProtocolError::CfuOfferStatusError(OfferStatus::Reject))))
+        );
+        // and never queued to be offered again
+        assert!(SkipList::from_results(&results).is_empty());
+    }
+
+    #[test]
+    fn test_transposed_frame_is_caught_before_the_bus() {
+        let mut writer = RecordingWriter::default();
+        let mut resp = [0u8; FwUpdateContentResponse::SERIALIZED_SIZE];
+
+        // an offer sized buffer handed to the content path
+        let offer: [u8; FwUpdateOffer::SERIALIZED_SIZE] =
+            (&FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::default(), 0, 0)).into();
+        assert_eq!(
+            block_on(writer.cfu_write_read_frame(Some(0), &offer, FwUpdateContentCommand::SERIALIZED_SIZE, &mut resp)),
+            Err(CfuWriterError::ByteConversionError)
+        );
+        // and a content command handed to the offer path
+        let content = ContentBlockEncoder.first([0; DEFAULT_DATA_LENGTH]);
+        assert_eq!(
+            block_on(writer.cfu_write_read_frame(None, &content, FwUpdateOffer::SERIALIZED_SIZE, &mut resp)),
+            Err(CfuWriterError::ByteConversionError)
+        );
+        assert!(writer.frames.is_empty());
+
+        block_on(writer.cfu_write_read_frame(Some(0), &content, FwUpdateContentCommand::SERIALIZED_SIZE, &mut resp))
+            .unwrap();
+        assert_eq!(writer.content_commands().len(), 1);
+    }
+
+    #[test]
+    fn test_writer_timeout_is_reported_per_component() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter {
+            content_error: Some(CfuWriterError::Timeout),
+            ..Default::default()
+        };
+        let result = block_on(updater.write_data_chunks(&mut writer, MockImage::new(104), 3, 0));
+        assert_eq!(result, Err(CfuProtocolError::TimeoutError(3)));
+
+        // other writer errors are passed through unchanged
+        writer.content_error = Some(CfuWriterError::ByteConversionError);
+        let result = block_on(updater.write_data_chunks(&mut writer, MockImage::new(104), 3, 0));
+        assert_eq!(
+            result,
+            Err(CfuProtocolError::WriterError(CfuWriterError::ByteConversionError))
+        );
+    }
+
+    #[test]
+    fn test_final_block_data_length_is_remainder() {
+        let mut updater = CfuUpdater::new();
+        updater.set_fill_byte(0xFF);
+        let mut writer = RecordingWriter::default();
+        block_on(updater.write_data_chunks(&mut writer, MockImage::new(130), 1, 0)).unwrap();
+
+        let blocks = writer.content_commands();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].header.data_length as usize, DEFAULT_DATA_LENGTH);
+        assert_eq!(blocks[1].header.data_length as usize, DEFAULT_DATA_LENGTH);
+        let last = blocks[2];
+        let remainder = 130 % DEFAULT_DATA_LENGTH;
+        assert_eq!(last.header.data_length as usize, remainder);
+        assert_eq!(last.data[0], 104);
+        assert_eq!(last.data[remainder - 1], 129);
+        assert!(last.data[remainder..].iter().all(|b| *b == 0xFF));
+    }
+
+    #[test]
+    fn test_content_block_encoder() {
+        let encoder = ContentBlockEncoder;
+
+        let first = encoder.first([0x11; DEFAULT_DATA_LENGTH]);
+        assert_eq!(first[0], FW_UPDATE_FLAG_FIRST_BLOCK);
+        assert_eq!(first[1] as usize, DEFAULT_DATA_LENGTH);
+        assert_eq!(&first[2..4], &[0, 0]);
+        assert_eq!(&first[8..], &[0x11; DEFAULT_DATA_LENGTH]);
+
+        let middle = encoder.middle([0x22; DEFAULT_DATA_LENGTH], 0x0102);
+        assert_eq!(middle[0], 0);
+        assert_eq!(middle[1] as usize, DEFAULT_DATA_LENGTH);
+        assert_eq!(&middle[2..4], &[0x02, 0x01]);
+
+        let last = encoder.last([0x33; DEFAULT_DATA_LENGTH], 7, 26);
+        assert_eq!(last[0], FW_UPDATE_FLAG_LAST_BLOCK);
+        assert_eq!(last[1], 26);
+        assert_eq!(&last[2..4], &[7, 0]);
+        assert_eq!(&last[4..8], &[0; 4]);
+    }
+
+    #[test]
+    fn test_encode_in_place_matches_copy() {
+        let mut data = [0xFFu8; DEFAULT_DATA_LENGTH];
+        for (i, b) in data.iter_mut().take(40).enumerate() {
+            *b = i as u8;
+        }
+        let cmd = FwUpdateContentCommand {
+            header: FwUpdateContentHeader {
+                flags: FW_UPDATE_FLAG_LAST_BLOCK,
+                data_length: 40,
+                sequence_num: 0x0304,
+                firmware_address: 0,
+            },
+            data,
+        };
+        let copied: [u8; FwUpdateContentCommand::SERIALIZED_SIZE] = (&cmd).into();
+
+        let mut frame = [0xFFu8; FwUpdateContentCommand::SERIALIZED_SIZE];
+        for (i, b) in ContentBlockEncoder::data_mut(&mut frame)
+            .iter_mut()
+            .take(40)
+            .enumerate()
+        {
+            *b = i as u8;
+        }
+        ContentBlockEncoder.encode_header_into(&mut frame, FW_UPDATE_FLAG_LAST_BLOCK, 40, 0x0304);
+        assert_eq!(frame, copied);
+        assert_eq!(ContentBlockEncoder.last(data, 0x0304, 40), copied);
+    }
+
+    #[test]
+    fn test_send_extended_command() {
+        let mut updater = CfuUpdater::new();
+        updater.set_token(HostToken::Tool);
+        let mut writer = RecordingWriter::default();
+
+        let resp = block_on(updater.send_extended_command(
+            &mut writer,
+            OfferCommandExtendedCodeValues::OfferNotifyOnReady,
+            &mut NoHooks,
+        ))
+        .unwrap();
+        assert_eq!(resp.status, OfferStatus::Accept);
+        assert_eq!(resp.token, HostToken::Tool);
+
+        let frame = <&[u8; FwUpdateOfferExtended::SERIALIZED_SIZE]>::try_from(writer.frames[0].as_slice()).unwrap();
+        let cmd = FwUpdateOfferExtended::try_from(frame).unwrap();
+        assert_eq!(
+            cmd.component_info.code,
+            OfferCommandExtendedCodeValues::OfferNotifyOnReady
+        );
+        assert_eq!(cmd.component_info.component_id, SpecialComponentIds::Command);
+        assert_eq!(cmd.component_info.token, HostToken::Tool);
+    }
+
+    #[test]
+    fn test_on_update_failed_gets_failing_sequence() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter {
+            nak_sequence: Some(2),
+            ..Default::default()
+        };
+        let components = [MockComponent::new(1)];
+        let images = [MockImage::new(52 * 5)];
+
+        let results = block_on(updater.run_update(&mut writer, &components, &images, &mut NoHooks)).unwrap();
+        assert_eq!(results[0].unwrap().1, Err(CfuProtocolError::UpdateError(1)));
+        assert_eq!(updater.failed_sequence(), Some(2));
+        assert_eq!(components[0].failed_at.get(), Some(2));
+        // the update stopped at the failing block
+        assert_eq!(writer.content_commands().len(), 3);
+
+        // a successful write clears it
+        writer.nak_sequence = None;
+        block_on(updater.write_data_chunks(&mut writer, MockImage::new(52 * 5), 1, 0)).unwrap();
+        assert_eq!(updater.failed_sequence(), None);
+    }
+
+    #[test]
+    fn test_sequence_mismatch_fails_early() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter {
+            misack_sequence: Some((3, 5)),
+            ..Default::default()
+        };
+        let result = block_on(updater.write_data_chunks(&mut writer, MockImage::new(52 * 10), 1, 0));
+        assert_eq!(
+            result,
+            Err(CfuProtocolError::UnexpectedSequence { expected: 3, actual: 5 })
+        );
+        // nothing was sent after the bad ack
+        assert_eq!(writer.content_commands().len(), 4);
+        assert_eq!(updater.failed_sequence(), Some(3));
+    }
+
+    #[test]
+    fn test_blocks_honor_base_offset() {
+        let mut updater = CfuUpdater::new();
+        let mut writer = RecordingWriter::default();
+        block_on(updater.write_data_chunks(&mut writer, MockImage::new(130), 1, 0x1000)).unwrap();
+
+        // one offset per block
+        assert_eq!(
+            writer.offsets[..],
+            [Some(0x1000), Some(0x1000 + 52), Some(0x1000 + 104)]
+        );
 
-        FwUpdateContentResponse::try_from(resp_buf).map_err(|_| CfuWriterError::ByteConversionError)
+        // activating a staged image is a command, not a block, so it has no offset
+        updater.set_stage_only(true);
+        writer.offsets.clear();
+        block_on(updater.write_data_chunks(&mut writer, MockImage::new(130), 1, 0x1000)).unwrap();
+        block_on(updater.activate(&mut writer, 1, &mut NoHooks)).unwrap();
+        assert_eq!(writer.offsets.last(), Some(&None));
     }
 }
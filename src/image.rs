@@ -0,0 +1,745 @@
+//! This module provides CfuImage implementations over common image sources, and splits images into content blocks.
+
+#[cfg(feature = "alloc")]
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use embedded_io_async::{ErrorKind, ErrorType, Read, ReadExactError, Seek, SeekFrom};
+use embedded_storage::ReadStorage;
+
+use crate::protocol_definitions::{
+    FwUpdateContentCommand, FwUpdateContentHeader, DEFAULT_DATA_LENGTH, FW_UPDATE_FLAG_FIRST_BLOCK,
+    FW_UPDATE_FLAG_LAST_BLOCK,
+};
+use crate::CfuImage;
+
+/// Resolves a seek from `cur` within an image of `size` bytes
+/// Returns None if the new position would fall before the start or past the end of the image
+fn resolve_seek(pos: SeekFrom, cur: usize, size: usize) -> Option<usize> {
+    match pos {
+        SeekFrom::Start(offset) => usize::try_from(offset).ok(),
+        SeekFrom::End(delta) => isize::try_from(delta)
+            .ok()
+            .and_then(|delta| size.checked_add_signed(delta)),
+        SeekFrom::Current(delta) => isize::try_from(delta)
+            .ok()
+            .and_then(|delta| cur.checked_add_signed(delta)),
+    }
+    .filter(|new_pos| *new_pos <= size)
+}
+
+/// Errors produced while reading a FlashImage
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FlashImageError<E> {
+    /// The storage device failed the read
+    Storage(E),
+    /// The storage device is already borrowed elsewhere
+    Busy,
+    /// Seek or read outside of the image
+    OutOfBounds,
+}
+
+impl<E: core::fmt::Debug> embedded_io_async::Error for FlashImageError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            FlashImageError::Storage(_) => ErrorKind::Other,
+            FlashImageError::Busy => ErrorKind::Other,
+            FlashImageError::OutOfBounds => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+/// CfuImage stored in a flash device exposed through embedded-storage
+/// The image occupies `size` bytes starting at `start` in the device, and is read a chunk at a time
+/// so it never has to be buffered in memory
+#[derive(Debug)]
+pub struct FlashImage<'a, S> {
+    storage: &'a RefCell<S>,
+    start: u32,
+    size: usize,
+    pos: usize,
+}
+
+impl<'a, S: ReadStorage> FlashImage<'a, S> {
+    pub fn new(storage: &'a RefCell<S>, start: u32, size: usize) -> Self {
+        Self {
+            storage,
+            start,
+            size,
+            pos: 0,
+        }
+    }
+}
+
+// Manual impls, deriving would require S: Copy
+impl<S> Clone for FlashImage<'_, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S> Copy for FlashImage<'_, S> {}
+
+impl<S: ReadStorage> ErrorType for FlashImage<'_, S>
+where
+    S::Error: core::fmt::Debug,
+{
+    type Error = FlashImageError<S::Error>;
+}
+
+impl<S: ReadStorage> Read for FlashImage<'_, S>
+where
+    S::Error: core::fmt::Debug,
+{
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = buf.len().min(self.size.saturating_sub(self.pos));
+        if len == 0 {
+            return Ok(0);
+        }
+        let offset = u32::try_from(self.pos)
+            .ok()
+            .and_then(|pos| self.start.checked_add(pos))
+            .ok_or(FlashImageError::OutOfBounds)?;
+        let dest = buf.get_mut(..len).ok_or(FlashImageError::OutOfBounds)?;
+        self.storage
+            .try_borrow_mut()
+            .map_err(|_| FlashImageError::Busy)?
+            .read(offset, dest)
+            .map_err(FlashImageError::Storage)?;
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl<S: ReadStorage> Seek for FlashImage<'_, S>
+where
+    S::Error: core::fmt::Debug,
+{
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let new_pos = resolve_seek(pos, self.pos, self.size).ok_or(FlashImageError::OutOfBounds)?;
+        self.pos = new_pos;
+        Ok(new_pos as u64)
+    }
+}
+
+impl<S: ReadStorage> CfuImage for FlashImage<'_, S>
+where
+    S::Error: core::fmt::Debug,
+{
+    fn get_total_size(self) -> usize {
+        self.size
+    }
+
+    async fn get_bytes_for_chunk(mut self, buf: &mut [u8], offset: usize) -> Result<(), ReadExactError<Self::Error>> {
+        self.seek(SeekFrom::Start(offset as u64))
+            .await
+            .map_err(ReadExactError::Other)?;
+        self.read_exact(buf).await
+    }
+}
+
+/// CfuImage over an image held in memory
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SliceImage<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SliceImage<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+}
+
+impl ErrorType for SliceImage<'_> {
+    type Error = ErrorKind;
+}
+
+impl Read for SliceImage<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let remaining = self.data.get(self.pos..).unwrap_or_default();
+        let len = buf.len().min(remaining.len());
+        let (dest, src) = buf
+            .get_mut(..len)
+            .zip(remaining.get(..len))
+            .ok_or(ErrorKind::InvalidInput)?;
+        dest.copy_from_slice(src);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl Seek for SliceImage<'_> {
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let new_pos = resolve_seek(pos, self.pos, self.data.len()).ok_or(ErrorKind::InvalidInput)?;
+        self.pos = new_pos;
+        Ok(new_pos as u64)
+    }
+}
+
+impl CfuImage for SliceImage<'_> {
+    fn get_total_size(self) -> usize {
+        self.data.len()
+    }
+
+    async fn get_bytes_for_chunk(mut self, buf: &mut [u8], offset: usize) -> Result<(), ReadExactError<Self::Error>> {
+        self.seek(SeekFrom::Start(offset as u64))
+            .await
+            .map_err(ReadExactError::Other)?;
+        self.read_exact(buf).await
+    }
+}
+
+/// Errors produced while reading an OffsetImage
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OffsetImageError<E> {
+    /// The wrapped image failed the read
+    Inner(E),
+    /// Seek outside of the payload
+    OutOfBounds,
+}
+
+impl<E: embedded_io_async::Error> embedded_io_async::Error for OffsetImageError<E> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            OffsetImageError::Inner(e) => e.kind(),
+            OffsetImageError::OutOfBounds => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+/// CfuImage adapter for images stored behind a fixed length header (e.g. metadata), exposing only the payload
+/// Offset 0 is the first byte after the header, and the total size is the size of the wrapped image minus the header
+#[derive(Copy, Clone, Debug)]
+pub struct OffsetImage<I> {
+    inner: I,
+    header_len: usize,
+    pos: usize,
+}
+
+impl<I: CfuImage> OffsetImage<I> {
+    pub fn new(inner: I, header_len: usize) -> Self {
+        Self {
+            inner,
+            header_len,
+            pos: 0,
+        }
+    }
+
+    /// Returns the wrapped image, header included
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I: CfuImage> ErrorType for OffsetImage<I> {
+    type Error = OffsetImageError<I::Error>;
+}
+
+impl<I: CfuImage> Read for OffsetImage<I> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let offset = self.header_len.saturating_add(self.pos);
+        self.inner
+            .seek(SeekFrom::Start(offset as u64))
+            .await
+            .map_err(OffsetImageError::Inner)?;
+        let len = self.inner.read(buf).await.map_err(OffsetImageError::Inner)?;
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl<I: CfuImage> Seek for OffsetImage<I> {
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let new_pos = resolve_seek(pos, self.pos, self.get_total_size()).ok_or(OffsetImageError::OutOfBounds)?;
+        self.pos = new_pos;
+        Ok(new_pos as u64)
+    }
+}
+
+impl<I: CfuImage> CfuImage for OffsetImage<I> {
+    fn get_total_size(self) -> usize {
+        self.inner.get_total_size().saturating_sub(self.header_len)
+    }
+
+    async fn get_bytes_for_chunk(self, buf: &mut [u8], offset: usize) -> Result<(), ReadExactError<Self::Error>> {
+        let offset = offset
+            .checked_add(self.header_len)
+            .ok_or(ReadExactError::Other(OffsetImageError::OutOfBounds))?;
+        self.inner.get_bytes_for_chunk(buf, offset).await.map_err(|e| match e {
+            ReadExactError::UnexpectedEof => ReadExactError::UnexpectedEof,
+            ReadExactError::Other(e) => ReadExactError::Other(OffsetImageError::Inner(e)),
+        })
+    }
+}
+
+/// Errors produced while reading a ChainImage
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChainImageError<A, B> {
+    /// The first image failed the read
+    First(A),
+    /// The second image failed the read
+    Second(B),
+    /// Seek outside of the combined image
+    OutOfBounds,
+}
+
+impl<A: embedded_io_async::Error, B: embedded_io_async::Error> embedded_io_async::Error for ChainImageError<A, B> {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            ChainImageError::First(e) => e.kind(),
+            ChainImageError::Second(e) => e.kind(),
+            ChainImageError::OutOfBounds => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+/// Wraps the error of a chunk read from one of the images of a ChainImage
+fn chain_read_error<E, F>(e: ReadExactError<E>, wrap: impl FnOnce(E) -> F) -> ReadExactError<F> {
+    match e {
+        ReadExactError::UnexpectedEof => ReadExactError::UnexpectedEof,
+        ReadExactError::Other(e) => ReadExactError::Other(wrap(e)),
+    }
+}
+
+/// CfuImage adapter presenting two images back to back as one, e.g. a signed header and the payload it covers
+/// stored in different flash regions
+/// Offsets past the end of `first` are read from `second`, and a chunk straddling the boundary is stitched together
+/// from both
+#[derive(Copy, Clone, Debug)]
+pub struct ChainImage<A, B> {
+    first: A,
+    second: B,
+    pos: usize,
+}
+
+impl<A: CfuImage, B: CfuImage> ChainImage<A, B> {
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second, pos: 0 }
+    }
+
+    /// Returns the two images
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A: CfuImage, B: CfuImage> ErrorType for ChainImage<A, B> {
+    type Error = ChainImageError<A::Error, B::Error>;
+}
+
+impl<A: CfuImage, B: CfuImage> Read for ChainImage<A, B> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let first_size = self.first.get_total_size();
+        // reads stop at the end of the first image, read_exact carries on into the second
+        let len = if self.pos < first_size {
+            self.first
+                .seek(SeekFrom::Start(self.pos as u64))
+                .await
+                .map_err(ChainImageError::First)?;
+            self.first.read(buf).await.map_err(ChainImageError::First)?
+        } else {
+            self.second
+                .seek(SeekFrom::Start((self.pos - first_size) as u64))
+                .await
+                .map_err(ChainImageError::Second)?;
+            self.second.read(buf).await.map_err(ChainImageError::Second)?
+        };
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+impl<A: CfuImage, B: CfuImage> Seek for ChainImage<A, B> {
+    async fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let new_pos = resolve_seek(pos, self.pos, self.get_total_size()).ok_or(ChainImageError::OutOfBounds)?;
+        self.pos = new_pos;
+        Ok(new_pos as u64)
+    }
+}
+
+impl<A: CfuImage, B: CfuImage> CfuImage for ChainImage<A, B> {
+    fn get_total_size(self) -> usize {
+        self.first.get_total_size().saturating_add(self.second.get_total_size())
+    }
+
+    async fn get_bytes_for_chunk(self, buf: &mut [u8], offset: usize) -> Result<(), ReadExactError<Self::Error>> {
+        let first_size = self.first.get_total_size();
+        let first_len = buf.len().min(first_size.saturating_sub(offset));
+        let (head, tail) = buf.split_at_mut(first_len);
+        if !head.is_empty() {
+            self.first
+                .get_bytes_for_chunk(head, offset)
+                .await
+                .map_err(|e| chain_read_error(e, ChainImageError::First))?;
+        }
+        if !tail.is_empty() {
+            let offset = (offset + first_len).saturating_sub(first_size);
+            self.second
+                .get_bytes_for_chunk(tail, offset)
+                .await
+                .map_err(|e| chain_read_error(e, ChainImageError::Second))?;
+        }
+        Ok(())
+    }
+}
+
+/// Owned in-memory image
+/// CfuImage requires Copy, so the image is sent through the SliceImage borrowed from it with `image()`
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VecImage {
+    data: Vec<u8>,
+}
+
+#[cfg(feature = "alloc")]
+impl VecImage {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+
+    /// Borrows the image as a CfuImage
+    pub fn image(&self) -> SliceImage<'_> {
+        SliceImage::new(&self.data)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl From<Vec<u8>> for VecImage {
+    fn from(data: Vec<u8>) -> Self {
+        Self::new(data)
+    }
+}
+
+/// Lazily splits an image into the content commands that carry it, without sending anything
+/// Each command has its flags, data_length and sequence number set; the first block is flagged first and the
+/// final block last, so a single block image carries both flags. Padding past data_length is zeroed.
+#[derive(Copy, Clone, Debug)]
+pub struct ContentBlocks<I> {
+    image: I,
+    next_seq: usize,
+    num_blocks: usize,
+}
+
+impl<I: CfuImage> ContentBlocks<I> {
+    pub fn new(image: I) -> Self {
+        Self {
+            image,
+            next_seq: 0,
+            num_blocks: image.block_count(),
+        }
+    }
+
+    /// Number of blocks not yet produced
+    pub fn remaining(&self) -> usize {
+        self.num_blocks.saturating_sub(self.next_seq)
+    }
+
+    /// Reads the next block from the image, returns None once every block has been produced
+    /// Iteration stops after a read error
+    pub async fn next(&mut self) -> Option<Result<FwUpdateContentCommand, ReadExactError<I::Error>>> {
+        if self.remaining() == 0 {
+            return None;
+        }
+        let seq = self.next_seq;
+        let offset = seq * DEFAULT_DATA_LENGTH;
+        let data_length = self
+            .image
+            .get_total_size()
+            .saturating_sub(offset)
+            .min(DEFAULT_DATA_LENGTH);
+        let mut flags = 0;
+        if seq == 0 {
+            flags |= FW_UPDATE_FLAG_FIRST_BLOCK;
+        }
+        if seq + 1 == self.num_blocks {
+            flags |= FW_UPDATE_FLAG_LAST_BLOCK;
+        }
+
+        let mut data = [0u8; DEFAULT_DATA_LENGTH];
+        let read = match data.get_mut(..data_length) {
+            Some(buf) => self.image.get_bytes_for_chunk(buf, offset).await,
+            None => Err(ReadExactError::UnexpectedEof),
+        };
+        if let Err(e) = read {
+            self.next_seq = self.num_blocks;
+            return Some(Err(e));
+        }
+        self.next_seq += 1;
+
+        Some(Ok(FwUpdateContentCommand {
+            header: FwUpdateContentHeader {
+                flags,
+                data_length: data_length as u8,
+                sequence_num: seq as u16,
+                firmware_address: 0,
+            },
+            data,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    /// RAM backed stand-in for a flash device
+    struct RamFlash {
+        mem: [u8; 256],
+    }
+
+    impl RamFlash {
+        fn new() -> Self {
+            let mut mem = [0u8; 256];
+            for (i, b) in mem.iter_mut().enumerate() {
+                *b = i as u8;
+            }
+            Self { mem }
+        }
+    }
+
+    impl ReadStorage for RamFlash {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(self.mem.get(offset..offset + bytes.len()).ok_or(())?);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.mem.len()
+        }
+    }
+
+    #[test]
+    fn test_flash_image_reads_chunks() {
+        let flash = RefCell::new(RamFlash::new());
+        let image = FlashImage::new(&flash, 16, 100);
+        assert_eq!(image.get_total_size(), 100);
+
+        let mut chunk = [0u8; 52];
+        block_on(image.get_bytes_for_chunk(&mut chunk, 0)).unwrap();
+        assert_eq!(chunk[0], 16);
+        assert_eq!(chunk[51], 67);
+
+        // final partial chunk
+        let mut tail = [0u8; 48];
+        block_on(image.get_bytes_for_chunk(&mut tail, 52)).unwrap();
+        assert_eq!(tail[0], 68);
+        assert_eq!(tail[47], 115);
+
+        // reading past the end of the image is reported rather than spilling into the rest of the flash
+        let mut chunk = [0u8; 52];
+        assert_eq!(
+            block_on(image.get_bytes_for_chunk(&mut chunk, 52)),
+            Err(ReadExactError::UnexpectedEof)
+        );
+        assert_eq!(
+            block_on(image.get_bytes_for_chunk(&mut chunk, 101)),
+            Err(ReadExactError::Other(FlashImageError::OutOfBounds))
+        );
+    }
+
+    #[test]
+    fn test_content_blocks_flags() {
+        let flash = RefCell::new(RamFlash::new());
+        let mut blocks = FlashImage::new(&flash, 0, 130).blocks();
+        assert_eq!(blocks.remaining(), 3);
+
+        let mut headers = [FwUpdateContentHeader {
+            flags: 0xFF,
+            data_length: 0,
+            sequence_num: 0,
+            firmware_address: 0,
+        }; 3];
+        for header in headers.iter_mut() {
+            let block = block_on(blocks.next()).unwrap().unwrap();
+            assert_eq!(
+                block.data[0],
+                (block.header.sequence_num as usize * DEFAULT_DATA_LENGTH) as u8
+            );
+            *header = block.header;
+        }
+        assert!(block_on(blocks.next()).is_none());
+
+        let flags = headers.map(|h| h.flags);
+        assert_eq!(flags, [FW_UPDATE_FLAG_FIRST_BLOCK, 0, FW_UPDATE_FLAG_LAST_BLOCK]);
+        assert_eq!(headers.map(|h| h.sequence_num), [0, 1, 2]);
+        assert_eq!(headers.map(|h| h.data_length), [52, 52, 26]);
+
+        // a single block image is both the first and the last block
+        let mut blocks = FlashImage::new(&flash, 0, 10).blocks();
+        let block = block_on(blocks.next()).unwrap().unwrap();
+        assert_eq!(
+            block.header.flags,
+            FW_UPDATE_FLAG_FIRST_BLOCK | FW_UPDATE_FLAG_LAST_BLOCK
+        );
+        assert_eq!(&block.data[10..], &[0; DEFAULT_DATA_LENGTH - 10]);
+    }
+
+    #[test]
+    fn test_read_embedded_version() {
+        let mut data = [0xEEu8; 64];
+        data[..4].copy_from_slice(&0x01020304u32.to_le_bytes());
+        let version = block_on(SliceImage::new(&data).read_embedded_version()).unwrap();
+        assert_eq!(u32::from(version), 0x01020304);
+        assert_eq!((version.major, version.minor, version.variant), (0x01, 0x0203, 0x04));
+
+        assert_eq!(
+            block_on(SliceImage::new(&data[..3]).read_embedded_version()),
+            Err(ReadExactError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_block_count() {
+        let data = [0u8; 104];
+        for (size, blocks) in [(0, 0), (1, 1), (52, 1), (53, 2), (104, 2)] {
+            assert_eq!(crate::block_count(size, DEFAULT_DATA_LENGTH), blocks);
+            let image = SliceImage::new(&data[..size]);
+            assert_eq!(image.block_count(), blocks);
+            // agrees with the blocks actually produced for the image
+            assert_eq!(image.blocks().remaining(), blocks);
+        }
+        assert_eq!(crate::block_count(10, 0), 0);
+    }
+
+    #[test]
+    fn test_read_from_exact_chunk_len() {
+        let mut data = [0u8; 300];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut image = SliceImage::new(&data);
+
+        let mut chunk = [0u8; 128];
+        block_on(crate::read_from_exact(&mut image, 1, 128, &mut chunk)).unwrap();
+        assert_eq!(chunk[0], 128);
+        assert_eq!(chunk[127], 255);
+        // the seek used the same 128 byte stride, leaving the image at the end of the block
+        assert_eq!(block_on(image.stream_position()).unwrap(), 256);
+
+        let mut chunk = [0u8; DEFAULT_DATA_LENGTH];
+        block_on(crate::read_from_exact(&mut image, 1, DEFAULT_DATA_LENGTH, &mut chunk)).unwrap();
+        assert_eq!(chunk[0], 52);
+
+        // a 128 byte block past the end of the image can't be read
+        let mut chunk = [0u8; 128];
+        assert_eq!(
+            block_on(crate::read_from_exact(&mut image, 2, 128, &mut chunk)),
+            Err(ReadExactError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_slice_image_partial_chunk() {
+        let mut data = [0u8; 130];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let image = SliceImage::new(&data);
+        assert_eq!(image.get_total_size(), 130);
+
+        let mut chunk = [0u8; DEFAULT_DATA_LENGTH];
+        block_on(image.get_bytes_for_chunk(&mut chunk, 52)).unwrap();
+        assert_eq!(chunk[0], 52);
+
+        // the final chunk only holds the remainder
+        let mut tail = [0u8; 130 % DEFAULT_DATA_LENGTH];
+        block_on(image.get_bytes_for_chunk(&mut tail, 104)).unwrap();
+        assert_eq!(tail[0], 104);
+        assert_eq!(tail[25], 129);
+        assert_eq!(
+            block_on(image.get_bytes_for_chunk(&mut chunk, 104)),
+            Err(ReadExactError::UnexpectedEof)
+        );
+        assert_eq!(
+            block_on(image.get_bytes_for_chunk(&mut chunk, 131)),
+            Err(ReadExactError::Other(ErrorKind::InvalidInput))
+        );
+    }
+
+    #[test]
+    fn test_offset_image_skips_header() {
+        let mut data = [0xEEu8; 64 + 100];
+        for (i, b) in data[64..].iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let image = OffsetImage::new(SliceImage::new(&data), 64);
+        assert_eq!(image.get_total_size(), 100);
+
+        let mut chunk = [0u8; DEFAULT_DATA_LENGTH];
+        block_on(image.get_bytes_for_chunk(&mut chunk, 0)).unwrap();
+        assert_eq!(chunk[0], 0);
+        assert_eq!(chunk[51], 51);
+        assert_eq!(
+            block_on(image.get_bytes_for_chunk(&mut chunk, 52)),
+            Err(ReadExactError::UnexpectedEof)
+        );
+
+        // streaming reads and seeks are relative to the payload too
+        let mut image = image;
+        block_on(image.seek(SeekFrom::End(-4))).unwrap();
+        let mut tail = [0u8; 4];
+        block_on(image.read_exact(&mut tail)).unwrap();
+        assert_eq!(tail, [96, 97, 98, 99]);
+        assert_eq!(
+            block_on(image.seek(SeekFrom::Start(101))),
+            Err(OffsetImageError::OutOfBounds)
+        );
+    }
+
+    #[test]
+    fn test_chain_image_stitches_chunks() {
+        let mut header = [0u8; 30];
+        let mut payload = [0u8; 100];
+        for (i, b) in header.iter_mut().chain(payload.iter_mut()).enumerate() {
+            *b = i as u8;
+        }
+        let image = ChainImage::new(SliceImage::new(&header), SliceImage::new(&payload));
+        assert_eq!(image.get_total_size(), 130);
+        assert_eq!(image.block_count(), 3);
+
+        // the first block runs from the end of the header into the payload
+        let mut chunk = [0u8; DEFAULT_DATA_LENGTH];
+        block_on(image.get_bytes_for_chunk(&mut chunk, 0)).unwrap();
+        assert!(chunk.iter().enumerate().all(|(i, b)| *b == i as u8));
+        // blocks past the header come from the payload alone
+        block_on(image.get_bytes_for_chunk(&mut chunk, 52)).unwrap();
+        assert_eq!((chunk[0], chunk[51]), (52, 103));
+        let mut tail = [0u8; 26];
+        block_on(image.get_bytes_for_chunk(&mut tail, 104)).unwrap();
+        assert_eq!(tail[25], 129);
+        assert_eq!(
+            block_on(image.get_bytes_for_chunk(&mut chunk, 104)),
+            Err(ReadExactError::UnexpectedEof)
+        );
+
+        // streaming reads cross the boundary too
+        let mut image = image;
+        block_on(image.seek(SeekFrom::Start(28))).unwrap();
+        let mut across = [0u8; 4];
+        block_on(image.read_exact(&mut across)).unwrap();
+        assert_eq!(across, [28, 29, 30, 31]);
+        assert_eq!(
+            block_on(image.seek(SeekFrom::Start(131))),
+            Err(ChainImageError::OutOfBounds)
+        );
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn test_vec_image() {
+        let image = VecImage::from(alloc::vec![0xA5; 60]);
+        assert_eq!(image.image().get_total_size(), 60);
+        let mut tail = [0u8; 8];
+        block_on(image.image().get_bytes_for_chunk(&mut tail, 52)).unwrap();
+        assert_eq!(tail, [0xA5; 8]);
+    }
+}
@@ -1,4 +1,7 @@
 #![no_std]
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
 use core::future::Future;
 
 use embedded_io_async::{Read, ReadExactError, Seek, SeekFrom};
@@ -9,7 +12,14 @@ pub mod client;
 pub mod components;
 pub mod fmt;
 pub mod host;
+pub mod image;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod loopback;
 pub mod protocol_definitions;
+pub mod script;
+#[cfg(feature = "sha256")]
+pub mod sha256;
+pub mod wire_fixtures;
 pub mod writer;
 
 // re-export the error enum
@@ -17,6 +27,10 @@ pub use CfuProtocolError::*;
 
 /// Trait to define the characteristics of a CFU image that will be sent by a Cfu Host
 pub trait CfuImage: Read + Seek + Copy {
+    /// Offset of the little-endian u32 fw version embedded in the image, read by `read_embedded_version`
+    /// Images with a different header layout override it
+    const EMBEDDED_VERSION_OFFSET: usize = 0;
+
     /// Gets the total size in bytes of an image
     fn get_total_size(self) -> usize;
     /// converts the image into a slice of bytes
@@ -26,15 +40,44 @@ pub trait CfuImage: Read + Seek + Copy {
         buf: &mut [u8],
         offset: usize,
     ) -> impl Future<Output = Result<(), ReadExactError<Self::Error>>>;
+    /// Reads the fw version embedded in the image at EMBEDDED_VERSION_OFFSET
+    /// Lets the version in an offer be cross-checked against the content that will be sent for it
+    fn read_embedded_version(self) -> impl Future<Output = Result<FwVersion, ReadExactError<Self::Error>>> {
+        async move {
+            let mut version = [0u8; 4];
+            self.get_bytes_for_chunk(&mut version, Self::EMBEDDED_VERSION_OFFSET)
+                .await?;
+            Ok(FwVersion::new(u32::from_le_bytes(version)))
+        }
+    }
+    /// Number of content blocks the image is sent in, including a final partial block
+    fn block_count(self) -> usize {
+        block_count(self.get_total_size(), DEFAULT_DATA_LENGTH)
+    }
+    /// Lazily splits the image into the content commands that carry it, so the caller can send them itself
+    fn blocks(self) -> image::ContentBlocks<Self> {
+        image::ContentBlocks::new(self)
+    }
+}
+
+/// Number of blocks of chunk_size bytes it takes to carry total_bytes, the last one holding any remainder
+/// 0 for an empty image, or if chunk_size is 0
+pub fn block_count(total_bytes: usize, chunk_size: usize) -> usize {
+    if chunk_size == 0 {
+        return 0;
+    }
+    total_bytes.div_ceil(chunk_size)
 }
 
 /// Helper function to read from an image at the offset matching the sequence number
+/// Blocks are chunk_len bytes apart, DEFAULT_DATA_LENGTH unless a component negotiated larger blocks
 pub async fn read_from_exact<I: CfuImage>(
     image: &mut I,
     seq_num: usize,
+    chunk_len: usize,
     buf: &mut [u8],
 ) -> Result<(), ReadExactError<I::Error>> {
-    let offset = seq_num * DEFAULT_DATA_LENGTH;
+    let offset = seq_num * chunk_len;
     image
         .seek(SeekFrom::Start(offset as u64))
         .await
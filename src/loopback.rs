@@ -0,0 +1,316 @@
+//! In-memory transport that connects a Host straight to a StandardClient, for exercising the whole protocol in tests.
+//!
+//! Every frame the Host writes through a LoopbackWriter is decoded by the client, and the client's response is what
+//! the Host reads back, so serialization is checked on both sides at once. RamComponent gives the client a fake
+//! storage to write the image into.
+
+use core::cell::{Cell, RefCell};
+
+use crate::client::{StandardClient, STANDARD_RESPONSE_SIZE};
+use crate::components::{CfuComponentFinalize, CfuComponentInfo, CfuComponentStorage, CfuComponentTraits};
+use crate::protocol_definitions::{CfuProtocolError, ComponentId, FwVersion, MAX_SUBCMPT_COUNT};
+use crate::writer::{CfuWriterAsync, CfuWriterError};
+
+/// CfuWriterAsync that hands every frame to a StandardClient and reads back its response
+pub struct LoopbackWriter<'a, C> {
+    client: &'a StandardClient<C>,
+    /// Response to the last frame written with cfu_write, returned by the next cfu_read
+    pending: Option<[u8; STANDARD_RESPONSE_SIZE]>,
+}
+
+impl<'a, C: CfuComponentTraits> LoopbackWriter<'a, C> {
+    pub fn new(client: &'a StandardClient<C>) -> Self {
+        Self { client, pending: None }
+    }
+
+    async fn exchange(&self, data: &[u8]) -> Result<[u8; STANDARD_RESPONSE_SIZE], CfuWriterError> {
+        self.client
+            .handle_frame(data)
+            .await
+            .map_err(|_| CfuWriterError::ByteConversionError)
+    }
+}
+
+/// Copies a response into the Host's read buffer, which must be exactly the size of the response
+fn copy_response(resp: &[u8; STANDARD_RESPONSE_SIZE], read: &mut [u8]) -> Result<(), CfuWriterError> {
+    if read.len() != resp.len() {
+        return Err(CfuWriterError::ByteConversionError);
+    }
+    read.copy_from_slice(resp);
+    Ok(())
+}
+
+impl<C: CfuComponentTraits> CfuWriterAsync for LoopbackWriter<'_, C> {
+    async fn cfu_write_read(
+        &mut self,
+        _mem_offset: Option<usize>,
+        data: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), CfuWriterError> {
+        let resp = self.exchange(data).await?;
+        copy_response(&resp, read)
+    }
+
+    async fn cfu_read(&mut self, _mem_offset: Option<usize>, read: &mut [u8]) -> Result<(), CfuWriterError> {
+        let resp = self.pending.take().ok_or(CfuWriterError::Other)?;
+        copy_response(&resp, read)
+    }
+
+    async fn cfu_write(&mut self, _mem_offset: Option<usize>, data: &[u8]) -> Result<(), CfuWriterError> {
+        self.pending = Some(self.exchange(data).await?);
+        Ok(())
+    }
+
+    async fn cfu_storage(&mut self, _mem_offset: usize, _data: &[u8]) -> Result<(), CfuWriterError> {
+        Ok(())
+    }
+}
+
+/// Component whose storage is N bytes of RAM
+/// Accepts offers whose version is newer than its current version, and keeps the image written to it
+pub struct RamComponent<const N: usize> {
+    id: ComponentId,
+    current: FwVersion,
+    offered: FwVersion,
+    storage: RefCell<[u8; N]>,
+    /// End of the furthest block written since the last storage_prepare
+    written: Cell<usize>,
+    finalized: Cell<bool>,
+    /// The next storage_write_block fails, as after a transient flash glitch
+    fail_next_write: Cell<bool>,
+}
+
+impl<const N: usize> RamComponent<N> {
+    pub fn new(id: ComponentId, current: FwVersion, offered: FwVersion) -> Self {
+        Self {
+            id,
+            current,
+            offered,
+            storage: RefCell::new([0; N]),
+            written: Cell::new(0),
+            finalized: Cell::new(false),
+            fail_next_write: Cell::new(false),
+        }
+    }
+
+    /// The bytes written since the last storage_prepare
+    pub fn image(&self) -> heapless::Vec<u8, N> {
+        let storage = self.storage.borrow();
+        heapless::Vec::from_slice(storage.get(..self.written.get()).unwrap_or_default()).unwrap_or_default()
+    }
+
+    /// Whether the written image has been committed with storage_finalize
+    pub fn is_finalized(&self) -> bool {
+        self.finalized.get()
+    }
+
+    /// Makes the next storage_write_block fail, leaving the storage untouched
+    pub fn fail_next_write(&self) {
+        self.fail_next_write.set(true);
+    }
+}
+
+impl<const N: usize> Default for RamComponent<N> {
+    fn default() -> Self {
+        Self::new(0, FwVersion::default(), FwVersion::default())
+    }
+}
+
+impl<const N: usize> CfuComponentInfo for RamComponent<N> {
+    async fn get_fw_version(&self) -> Result<FwVersion, CfuProtocolError> {
+        Ok(self.current)
+    }
+
+    fn get_component_id(&self) -> ComponentId {
+        self.id
+    }
+
+    fn offered_version(&self) -> FwVersion {
+        self.offered
+    }
+
+    fn is_dual_bank(&self) -> bool {
+        false
+    }
+
+    fn get_subcomponents(&self) -> [Option<ComponentId>; MAX_SUBCMPT_COUNT] {
+        [None; MAX_SUBCMPT_COUNT]
+    }
+}
+
+impl<const N: usize> CfuComponentStorage for RamComponent<N> {
+    async fn storage_prepare(&self) -> Result<(), CfuWriterError> {
+        *self.storage.borrow_mut() = [0; N];
+        self.written.set(0);
+        self.finalized.set(false);
+        Ok(())
+    }
+
+    async fn storage_write(&self) -> Result<(), CfuWriterError> {
+        Ok(())
+    }
+
+    async fn storage_finalize(&self) -> Result<(), CfuWriterError> {
+        self.finalized.set(true);
+        Ok(())
+    }
+
+    async fn storage_write_block(&self, offset: usize, data: &[u8]) -> Result<(), CfuWriterError> {
+        if self.fail_next_write.take() {
+            return Err(CfuWriterError::StorageError);
+        }
+        let end = offset + data.len();
+        self.storage
+            .borrow_mut()
+            .get_mut(offset..end)
+            .ok_or(CfuWriterError::StorageError)?
+            .copy_from_slice(data);
+        self.written.set(self.written.get().max(end));
+        Ok(())
+    }
+
+    fn max_image_size(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> CfuComponentTraits for RamComponent<N> {}
+
+impl<const N: usize> CfuComponentFinalize for RamComponent<N> {}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::host::{CfuUpdateContent, CfuUpdater, NoHooks};
+    use crate::image::SliceImage;
+    use crate::protocol_definitions::{
+        FwUpdateOffer, OfferRejectReason, OfferStatus, DEFAULT_DATA_LENGTH, PROTOCOL_VER,
+    };
+
+    fn test_image<const L: usize>() -> [u8; L] {
+        let mut data = [0u8; L];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+        data
+    }
+
+    fn test_client() -> StandardClient<RamComponent<256>> {
+        StandardClient::new(RamComponent::new(
+            1,
+            FwVersion::new(0x01000000),
+            FwVersion::new(0x02000000),
+        ))
+    }
+
+    #[test]
+    fn test_loopback_offer_then_content() {
+        let client = test_client();
+        let mut writer = LoopbackWriter::new(&client);
+        let mut updater = CfuUpdater::new();
+        let data = test_image::<{ DEFAULT_DATA_LENGTH * 3 + 26 }>();
+
+        let resp = block_on(updater.start_transaction_with_hooks(&mut writer, &mut NoHooks)).unwrap();
+        assert_eq!(resp.status, OfferStatus::Accept);
+        let offer = FwUpdateOffer::builder(updater.token(), 1, FwVersion::new(0x02000000)).build();
+        assert_eq!(
+            block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks))
+                .unwrap()
+                .status,
+            OfferStatus::Accept
+        );
+
+        let resp = block_on(updater.write_data_chunks(&mut writer, SliceImage::new(&data), 1, 0)).unwrap();
+        assert_eq!(resp.sequence, 3);
+        assert!(client.component().is_finalized());
+        assert_eq!(client.component().image(), data);
+
+        // an offer for a component the client doesn't update is turned down over the wire too
+        let offer = FwUpdateOffer::builder(updater.token(), 2, FwVersion::new(0x02000000)).build();
+        let resp = block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks)).unwrap();
+        assert_eq!(resp.status, OfferStatus::Reject);
+        assert_eq!(resp.reject_reason, OfferRejectReason::InvalidComponent);
+    }
+
+    #[test]
+    fn test_loopback_stage_then_activate() {
+        let client = test_client();
+        let mut writer = LoopbackWriter::new(&client);
+        let mut updater = CfuUpdater::new();
+        updater.set_stage_only(true);
+        let data = test_image::<{ DEFAULT_DATA_LENGTH * 2 }>();
+
+        let offer = FwUpdateOffer::builder(updater.token(), 1, FwVersion::new(0x02000000)).build();
+        assert_eq!(
+            block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks))
+                .unwrap()
+                .status,
+            OfferStatus::Accept
+        );
+        block_on(updater.write_data_chunks(&mut writer, SliceImage::new(&data), 1, 0)).unwrap();
+        // the whole image is written but not committed until the Host activates it
+        assert_eq!(client.component().image(), data);
+        assert!(!client.component().is_finalized());
+
+        block_on(updater.activate(&mut writer, 1, &mut NoHooks)).unwrap();
+        assert!(client.component().is_finalized());
+        assert_eq!(client.component().image(), data);
+    }
+
+    #[test]
+    fn test_loopback_retries_failed_write() {
+        let client = test_client();
+        let mut writer = LoopbackWriter::new(&client);
+        let mut updater = CfuUpdater::new();
+        updater.set_block_retries(1);
+        let data = test_image::<{ DEFAULT_DATA_LENGTH * 3 }>();
+
+        let offer = FwUpdateOffer::builder(updater.token(), 1, FwVersion::new(0x02000000)).build();
+        assert_eq!(
+            block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks))
+                .unwrap()
+                .status,
+            OfferStatus::Accept
+        );
+        // the first write fails once, the client keeps the offer so the re-sent block lands
+        client.component().fail_next_write();
+        let resp = block_on(updater.write_data_chunks(&mut writer, SliceImage::new(&data), 1, 0)).unwrap();
+        assert_eq!(resp.sequence, 2);
+        assert!(client.component().is_finalized());
+        assert_eq!(client.component().image(), data);
+    }
+
+    #[test]
+    fn test_loopback_protocol_version_check() {
+        let client = test_client();
+        let mut writer = LoopbackWriter::new(&client);
+        let mut updater = CfuUpdater::new();
+        let data = test_image::<{ DEFAULT_DATA_LENGTH * 2 + 10 }>();
+        let components = [RamComponent::<256>::new(
+            1,
+            FwVersion::new(0x01000000),
+            FwVersion::new(0x02000000),
+        )];
+
+        // the offers run_update builds carry PROTOCOL_VER, so the client takes them
+        let results =
+            block_on(updater.run_update(&mut writer, &components, &[SliceImage::new(&data)], &mut NoHooks)).unwrap();
+        let (cmpt_id, result) = results[0].unwrap();
+        assert_eq!(cmpt_id, 1);
+        assert_eq!(result.unwrap().sequence, 2);
+        assert_eq!(client.component().image(), data);
+
+        // an offer with another revision is turned down before it is evaluated
+        let offer = FwUpdateOffer::new(
+            updater.token(),
+            1,
+            FwVersion::new(0x03000000),
+            0,
+            u32::from(PROTOCOL_VER + 1),
+        );
+        let resp = block_on(updater.send_offer(&mut writer, &offer, &mut NoHooks)).unwrap();
+        assert_eq!(resp.status, OfferStatus::CmdNotSupported);
+    }
+}
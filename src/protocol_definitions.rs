@@ -1,5 +1,8 @@
+use core::cmp::Ordering;
 use core::convert::TryFrom;
+use core::fmt;
 
+use crate::trace;
 use crate::writer::CfuWriterError;
 
 // Max is 7 components in CfuUpdateOfferResponse, 1 primary and 6 subcomponents
@@ -32,6 +35,61 @@ impl FwVersion {
             major: ((fw_version >> 24) & 0xFF) as u8,
         }
     }
+
+    /// Returns the version with the major number incremented, saturating at u8::MAX
+    /// When `reset_lower` is set minor and variant are cleared, unless major was already saturated
+    pub fn bump_major(self, reset_lower: bool) -> Self {
+        if self.major == u8::MAX {
+            return self;
+        }
+        let bumped = Self {
+            major: self.major + 1,
+            ..self
+        };
+        if reset_lower {
+            Self {
+                minor: 0,
+                variant: 0,
+                ..bumped
+            }
+        } else {
+            bumped
+        }
+    }
+
+    /// Returns the version with the minor number incremented, saturating at u16::MAX
+    pub fn bump_minor(self) -> Self {
+        Self {
+            minor: self.minor.saturating_add(1),
+            ..self
+        }
+    }
+
+    /// Returns the version with the variant incremented, saturating at u8::MAX
+    pub fn bump_variant(self) -> Self {
+        Self {
+            variant: self.variant.saturating_add(1),
+            ..self
+        }
+    }
+
+    /// Compares major.minor only, for products that use the variant as a build tag rather than as part of the version
+    pub fn cmp_ignoring_variant(&self, other: &Self) -> Ordering {
+        (self.major, self.minor).cmp(&(other.major, other.minor))
+    }
+}
+
+// Versions order by major, then minor, then variant, the same as their u32 form
+impl Ord for FwVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        u32::from(*self).cmp(&u32::from(*other))
+    }
+}
+
+impl PartialOrd for FwVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
 }
 
 impl From<FwVersion> for u32 {
@@ -48,8 +106,259 @@ pub struct GetFwVersionResponse {
     pub component_info: [FwVerComponentInfo; MAX_CMPT_COUNT],
 }
 
-// CFU protocol spec at ver 2.0
-const PROTOCOL_VER: u8 = 0b0010;
+impl GetFwVersionResponse {
+    /// Size of the response on the wire
+    pub const SERIALIZED_SIZE: usize = 60;
+
+    /// Decodes a response read off the wire, which may be shorter than SERIALIZED_SIZE when fewer than
+    /// MAX_CMPT_COUNT components are reported
+    /// Returns BadResponse if the buffer doesn't hold every component it declares, and DeserializeField naming the
+    /// header field if the component count exceeds MAX_CMPT_COUNT or the protocol version is not supported
+    pub fn deserialize_checked(bytes: &[u8]) -> Result<Self, CfuProtocolError> {
+        let component_count = *bytes.first().ok_or(CfuProtocolError::BadResponse)? as usize;
+        if component_count > MAX_CMPT_COUNT {
+            trace!("component count {} exceeds {}", component_count, MAX_CMPT_COUNT);
+            return Err(CfuProtocolError::DeserializeField {
+                struct_name: "GetFwVersionResponseHeader",
+                field_name: "component_count",
+            });
+        }
+        // a missing byte3 is caught by the length check below
+        if bytes
+            .get(3)
+            .is_some_and(|byte3| GetFwVerRespHeaderByte3::try_from(*byte3).is_err())
+        {
+            return Err(CfuProtocolError::DeserializeField {
+                struct_name: "GetFwVersionResponseHeader",
+                field_name: "byte3",
+            });
+        }
+        let len = GetFwVersionResponseHeader::SERIALIZED_SIZE + component_count * FwVerComponentInfo::SERIALIZED_SIZE;
+        let mut buf = [0u8; Self::SERIALIZED_SIZE];
+        let src = bytes.get(..len).ok_or(CfuProtocolError::BadResponse)?;
+        buf.get_mut(..len)
+            .ok_or(CfuProtocolError::BadResponse)?
+            .copy_from_slice(src);
+        Self::try_from(&buf).map_err(|_| CfuProtocolError::BadResponse)
+    }
+
+    /// Returns the info of the component with the given id
+    /// Only the component_count entries the response reports are searched, so stale entries past it never match
+    pub fn component_by_id(&self, id: ComponentId) -> Option<&FwVerComponentInfo> {
+        self.component_info
+            .iter()
+            .take(self.header.component_count as usize)
+            .find(|info| info.component_id == id)
+    }
+
+    /// Returns whether extension component info packets follow the response
+    pub fn has_extension(&self) -> bool {
+        self.header.byte3 == GetFwVerRespHeaderByte3::ExtensionFlagSet
+    }
+
+    /// Parses the extension component info that follows the response, one entry per reported component in the
+    /// same order as component_info
+    /// Returns None without reading `buf` if the extension flag is clear, and BadResponse if `buf` is too short
+    pub fn parse_extension(
+        &self,
+        buf: &[u8],
+    ) -> Result<Option<[FwVerComponentExtension; MAX_CMPT_COUNT]>, CfuProtocolError> {
+        if !self.has_extension() {
+            return Ok(None);
+        }
+        let mut extensions = [FwVerComponentExtension::default(); MAX_CMPT_COUNT];
+        let mut entries = buf.chunks_exact(FwVerComponentExtension::SERIALIZED_SIZE);
+        for extension in extensions.iter_mut().take(self.header.component_count as usize) {
+            let entry = entries.next().ok_or(CfuProtocolError::BadResponse)?;
+            extension.properties.copy_from_slice(entry);
+        }
+        Ok(Some(extensions))
+    }
+
+    /// Serializes the response and splits it into fragments of at most `max_len` bytes, for transports that can't
+    /// carry it in one transfer. Only the header and the components it reports are sent
+    /// Fragments end on a header or component boundary, an entry is only split if it is longer than max_len
+    /// Returns ValueOutOfRange if max_len is 0
+    pub fn fragments(&self, max_len: usize) -> Result<FwVersionFragments, ConversionError> {
+        if max_len == 0 {
+            return Err(ConversionError::ValueOutOfRange);
+        }
+        let bytes = <[u8; Self::SERIALIZED_SIZE]>::try_from(self)?;
+        Ok(FwVersionFragments {
+            bytes,
+            len: GetFwVersionResponseHeader::SERIALIZED_SIZE
+                + self.header.component_count as usize * FwVerComponentInfo::SERIALIZED_SIZE,
+            pos: 0,
+            max_len,
+        })
+    }
+}
+
+/// One fragment of a GetFwVersionResponse, see `GetFwVersionResponse::fragments`
+pub type FwVersionFragment = heapless::Vec<u8, { GetFwVersionResponse::SERIALIZED_SIZE }>;
+
+/// Iterator over the fragments of a serialized GetFwVersionResponse
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FwVersionFragments {
+    bytes: [u8; GetFwVersionResponse::SERIALIZED_SIZE],
+    len: usize,
+    pos: usize,
+    max_len: usize,
+}
+
+impl FwVersionFragments {
+    /// First header or component boundary after `pos`
+    fn next_boundary(pos: usize) -> usize {
+        let header = GetFwVersionResponseHeader::SERIALIZED_SIZE;
+        if pos < header {
+            header
+        } else {
+            header + ((pos - header) / FwVerComponentInfo::SERIALIZED_SIZE + 1) * FwVerComponentInfo::SERIALIZED_SIZE
+        }
+    }
+}
+
+impl Iterator for FwVersionFragments {
+    type Item = FwVersionFragment;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.len {
+            return None;
+        }
+        let mut end = self.pos;
+        while end < self.len {
+            let boundary = Self::next_boundary(end).min(self.len);
+            if boundary - self.pos > self.max_len {
+                break;
+            }
+            end = boundary;
+        }
+        if end == self.pos {
+            // the next entry alone is longer than a fragment, it has to be split
+            end = (self.pos + self.max_len).min(self.len);
+        }
+        let fragment = FwVersionFragment::from_slice(self.bytes.get(self.pos..end)?).ok()?;
+        self.pos = end;
+        Some(fragment)
+    }
+}
+
+/// Rebuilds a GetFwVersionResponse from the fragments produced by `GetFwVersionResponse::fragments`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FwVersionReassembler {
+    buf: [u8; GetFwVersionResponse::SERIALIZED_SIZE],
+    len: usize,
+}
+
+impl Default for FwVersionReassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FwVersionReassembler {
+    pub fn new() -> Self {
+        Self {
+            buf: [0; GetFwVersionResponse::SERIALIZED_SIZE],
+            len: 0,
+        }
+    }
+
+    /// Appends the next fragment, returning the response once every byte its header declares has arrived
+    /// Returns BadResponse if the fragments run past the length the header declares, or the error of
+    /// `GetFwVersionResponse::deserialize_checked` for a bad header
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<GetFwVersionResponse>, CfuProtocolError> {
+        self.buf
+            .get_mut(self.len..self.len + fragment.len())
+            .ok_or(CfuProtocolError::BadResponse)?
+            .copy_from_slice(fragment);
+        self.len += fragment.len();
+        if self.len == 0 {
+            return Ok(None);
+        }
+
+        let component_count = self.buf[0] as usize;
+        if component_count > MAX_CMPT_COUNT {
+            return Err(CfuProtocolError::DeserializeField {
+                struct_name: "GetFwVersionResponseHeader",
+                field_name: "component_count",
+            });
+        }
+        let total = GetFwVersionResponseHeader::SERIALIZED_SIZE + component_count * FwVerComponentInfo::SERIALIZED_SIZE;
+        match self.len.cmp(&total) {
+            Ordering::Less => Ok(None),
+            Ordering::Equal => {
+                GetFwVersionResponse::deserialize_checked(self.buf.get(..total).unwrap_or_default()).map(Some)
+            }
+            Ordering::Greater => {
+                trace!("fw version fragments hold {} bytes, expected {}", self.len, total);
+                Err(CfuProtocolError::BadResponse)
+            }
+        }
+    }
+}
+
+/// Builds a GetFwVersionResponse, keeping the component count in sync with the components added
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GetFwVersionResponseBuilder {
+    component_info: [FwVerComponentInfo; MAX_CMPT_COUNT],
+    component_count: u8,
+    extension: bool,
+}
+
+impl GetFwVersionResponseBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a component to the response
+    /// Returns InvalidComponentCount if MAX_CMPT_COUNT components were already added
+    pub fn add_component(&mut self, component: FwVerComponentInfo) -> Result<&mut Self, CfuProtocolError> {
+        let slot = self
+            .component_info
+            .get_mut(self.component_count as usize)
+            .ok_or(CfuProtocolError::InvalidComponentCount)?;
+        *slot = component;
+        self.component_count += 1;
+        Ok(self)
+    }
+
+    /// Selects whether the response advertises extension component info
+    pub fn extension(&mut self, extension: bool) -> &mut Self {
+        self.extension = extension;
+        self
+    }
+
+    /// Builds the response with the supported protocol version in the header
+    pub fn build(&self) -> GetFwVersionResponse {
+        let byte3 = if self.extension {
+            GetFwVerRespHeaderByte3::ExtensionFlagSet
+        } else {
+            GetFwVerRespHeaderByte3::NoSpecialFlags
+        };
+        GetFwVersionResponse {
+            header: GetFwVersionResponseHeader::new(self.component_count, byte3),
+            component_info: self.component_info,
+        }
+    }
+}
+
+/// Extension component info advertised after a GetFwVersionResponse with the extension flag set
+/// The properties are vendor defined, this crate only frames them
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FwVerComponentExtension {
+    pub properties: [u8; FwVerComponentExtension::SERIALIZED_SIZE],
+}
+
+impl FwVerComponentExtension {
+    /// Size of each extension entry on the wire
+    pub const SERIALIZED_SIZE: usize = 8;
+}
+
+/// Revision of the CFU protocol this crate implements, spec version 2.0
+/// Reported in GetFwVersionResponse and stamped into the offers the Host builds
+pub const PROTOCOL_VER: u8 = 0b0010;
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -61,6 +370,9 @@ pub struct GetFwVersionResponseHeader {
 }
 
 impl GetFwVersionResponseHeader {
+    /// Size of the header on the wire
+    pub const SERIALIZED_SIZE: usize = 4;
+
     pub fn new(component_count: u8, byte3: GetFwVerRespHeaderByte3) -> Self {
         Self {
             component_count,
@@ -85,15 +397,45 @@ pub enum GetFwVerRespHeaderByte3 {
     ExtensionFlagSet = (PROTOCOL_VER << 4) | 1,
 }
 
+// Conversion from u8 to GetFwVerRespHeaderByte3
+impl TryFrom<u8> for GetFwVerRespHeaderByte3 {
+    type Error = ConversionError;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        const NO_SPECIAL_FLAGS: u8 = GetFwVerRespHeaderByte3::NoSpecialFlags as u8;
+        const EXTENSION_FLAG_SET: u8 = GetFwVerRespHeaderByte3::ExtensionFlagSet as u8;
+        match value {
+            NO_SPECIAL_FLAGS => Ok(GetFwVerRespHeaderByte3::NoSpecialFlags),
+            EXTENSION_FLAG_SET => Ok(GetFwVerRespHeaderByte3::ExtensionFlagSet),
+            _ => Err(ConversionError::ValueOutOfRange),
+        }
+    }
+}
+
 pub type ComponentId = u8;
+
+/// Component id of an Offer Command Extended frame
+pub const COMPONENT_ID_COMMAND: ComponentId = 0xFE;
+/// Component id of an Offer Information frame
+pub const COMPONENT_ID_INFO: ComponentId = 0xFF;
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SpecialComponentIds {
     /// Special Component ID in the Component Information bytes for Offer Command Extended.
-    Command = 0xFE,
+    /// A frame offering to this id is an extended command rather than an update, and is decoded as one
+    Command = COMPONENT_ID_COMMAND,
     /// Special Component ID in the Component Information bytes for Offer Information.
-    Info = 0xFF,
+    Info = COMPONENT_ID_INFO,
+}
+
+impl SpecialComponentIds {
+    /// Returns whether a component id is reserved by the spec for offer information or extended commands
+    /// Offers to a reserved id are never updates of a real component
+    pub fn is_reserved(id: ComponentId) -> bool {
+        id == COMPONENT_ID_COMMAND || id == COMPONENT_ID_INFO
+    }
 }
 
 // Conversion from u8 to SpecialComponentIds
@@ -102,8 +444,8 @@ impl TryFrom<u8> for SpecialComponentIds {
 
     fn try_from(value: u8) -> Result<Self, Self::Error> {
         match value {
-            0xFE => Ok(SpecialComponentIds::Command),
-            0xFF => Ok(SpecialComponentIds::Info),
+            COMPONENT_ID_COMMAND => Ok(SpecialComponentIds::Command),
+            COMPONENT_ID_INFO => Ok(SpecialComponentIds::Info),
             _ => Err(ConversionError::ValueOutOfRange),
         }
     }
@@ -136,6 +478,9 @@ pub struct FwVerComponentInfo {
 }
 
 impl FwVerComponentInfo {
+    /// Size of each component entry on the wire
+    pub const SERIALIZED_SIZE: usize = 8;
+
     pub fn new(fw_version: FwVersion, component_id: ComponentId) -> Self {
         Self {
             fw_version,
@@ -160,6 +505,37 @@ impl FwVerComponentInfo {
             vendor_specific1,
         }
     }
+
+    /// Same as `new_with_vendor_specific_info`, but rejects a bank that doesn't fit in 2 bits or a
+    /// vendor_specific0 that doesn't fit in 4 bits instead of truncating them
+    pub fn try_new_with_vendor_specific_info(
+        fw_version: FwVersion,
+        component_id: ComponentId,
+        bank: BankType,
+        vendor_specific0: u8,
+        vendor_specific1: u16,
+    ) -> Result<Self, ConversionError> {
+        if u8::from(bank) > 0x3 || vendor_specific0 > 0xF {
+            return Err(ConversionError::ValueOutOfRange);
+        }
+        Ok(Self::new_with_vendor_specific_info(
+            fw_version,
+            component_id,
+            bank,
+            vendor_specific0,
+            vendor_specific1,
+        ))
+    }
+
+    /// Bank type decoded from bits 0-1 of the packed byte
+    pub fn bank(&self) -> BankType {
+        BankType::VendorSpecific(self.packed_byte & 0x3)
+    }
+
+    /// vendor_specific0 decoded from bits 4-7 of the packed byte
+    pub fn vendor_bits(&self) -> u8 {
+        (self.packed_byte >> 4) & 0xF
+    }
 }
 
 impl Default for FwVerComponentInfo {
@@ -169,11 +545,11 @@ impl Default for FwVerComponentInfo {
 }
 
 // Convert to bytes
-impl TryFrom<&GetFwVersionResponse> for [u8; 60] {
+impl TryFrom<&GetFwVersionResponse> for [u8; GetFwVersionResponse::SERIALIZED_SIZE] {
     type Error = ConversionError;
 
     fn try_from(response: &GetFwVersionResponse) -> Result<Self, Self::Error> {
-        let mut bytes = [0u8; 60];
+        let mut bytes = [0u8; GetFwVersionResponse::SERIALIZED_SIZE];
 
         // Serialize header
         bytes[0] = response.header.component_count;
@@ -207,12 +583,16 @@ impl TryFrom<&GetFwVersionResponse> for [u8; 60] {
 }
 
 // Convert from bytes
-impl TryFrom<&[u8; 60]> for GetFwVersionResponse {
+impl TryFrom<&[u8; GetFwVersionResponse::SERIALIZED_SIZE]> for GetFwVersionResponse {
     type Error = ConversionError;
 
     #[allow(clippy::indexing_slicing)] // static_check and fixed size array guarantees indexing is safe
-    fn try_from(bytes: &[u8; 60]) -> Result<Self, Self::Error> {
-        const _: () = assert!(MAX_CMPT_COUNT * 8 + 4 <= 60, "Component count exceeds maximum allowed");
+    fn try_from(bytes: &[u8; GetFwVersionResponse::SERIALIZED_SIZE]) -> Result<Self, Self::Error> {
+        const _: () = assert!(
+            GetFwVersionResponseHeader::SERIALIZED_SIZE + MAX_CMPT_COUNT * FwVerComponentInfo::SERIALIZED_SIZE
+                <= GetFwVersionResponse::SERIALIZED_SIZE,
+            "Component count exceeds maximum allowed"
+        );
 
         let component_count = bytes[0];
 
@@ -225,11 +605,7 @@ impl TryFrom<&[u8; 60]> for GetFwVersionResponse {
                 .try_into()
                 .map_err(|_| ConversionError::ByteConversionError)?,
         );
-        let byte3 = match bytes[3] {
-            0x20 => GetFwVerRespHeaderByte3::NoSpecialFlags,
-            0x21 => GetFwVerRespHeaderByte3::ExtensionFlagSet,
-            _ => return Err(ConversionError::ValueOutOfRange),
-        };
+        let byte3 = GetFwVerRespHeaderByte3::try_from(bytes[3])?;
 
         let mut component_info = [FwVerComponentInfo::default(); MAX_CMPT_COUNT];
         let mut offset = 4;
@@ -266,13 +642,16 @@ impl TryFrom<&[u8; 60]> for GetFwVersionResponse {
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 /// LSB first Representation of FwUpdateOffer
 pub struct FwUpdateOffer {
-    pub component_info: UpdateOfferComponentInfo, // u32
-    pub firmware_version: FwVersion,              // u32
-    pub vendor_specific: u32,                     // u32
-    pub misc_and_protocol_version: u32,           // u32
+    pub component_info: UpdateOfferComponentInfo,   // u32
+    pub firmware_version: FwVersion,                // u32
+    pub vendor_specific: u32,                       // u32
+    pub misc_and_protocol_version: MiscAndProtocol, // u32
 }
 
 impl FwUpdateOffer {
+    /// Size of the command on the wire, the same as the offer information and offer extended commands
+    pub const SERIALIZED_SIZE: usize = 16;
+
     pub fn new(
         token: HostToken,
         component_id: ComponentId,
@@ -284,11 +663,135 @@ impl FwUpdateOffer {
             component_info: UpdateOfferComponentInfo::new(token, component_id),
             firmware_version,
             vendor_specific,
-            misc_and_protocol_version: misc,
+            misc_and_protocol_version: MiscAndProtocol::from(misc),
+        }
+    }
+
+    /// Builds an offer for the component described by `info` (as reported in a GetFwVersionResponse),
+    /// offering it the `target` firmware version with this crate's PROTOCOL_VER
+    pub fn for_component(info: &FwVerComponentInfo, target: FwVersion, token: HostToken) -> Self {
+        Self::builder(token, info.component_id, target).build()
+    }
+
+    /// Protocol revision the Host speaks, carried in misc_and_protocol_version
+    pub fn protocol_version(&self) -> u8 {
+        self.misc_and_protocol_version.protocol_version()
+    }
+
+    /// The vendor specific dword as the 4 bytes sent on the wire, in wire order
+    pub fn vendor_specific_bytes(&self) -> [u8; 4] {
+        self.vendor_specific.to_le_bytes()
+    }
+
+    /// Sets the vendor specific dword so that `bytes` are sent on the wire in the order given
+    pub fn set_vendor_specific_bytes(&mut self, bytes: [u8; 4]) {
+        self.vendor_specific = u32::from_le_bytes(bytes);
+    }
+
+    /// The vendor specific dword as two little-endian words, the first one sent first
+    pub fn vendor_specific_words(&self) -> [u16; 2] {
+        [self.vendor_specific as u16, (self.vendor_specific >> 16) as u16]
+    }
+
+    /// Sets the vendor specific dword from two words, sent little-endian with the first one first
+    pub fn set_vendor_specific_words(&mut self, words: [u16; 2]) {
+        self.vendor_specific = u32::from(words[0]) | (u32::from(words[1]) << 16);
+    }
+
+    /// Starts building an offer of `firmware_version` to a component, with no force flags, a zeroed vendor specific
+    /// field and this crate's PROTOCOL_VER with zeroed misc bits
+    pub fn builder(token: HostToken, component_id: ComponentId, firmware_version: FwVersion) -> FwUpdateOfferBuilder {
+        FwUpdateOfferBuilder {
+            offer: Self::new(token, component_id, firmware_version, 0, u32::from(PROTOCOL_VER)),
+            force_ignore_version: false,
+            force_reset: false,
+        }
+    }
+}
+
+/// Builds a FwUpdateOffer, created with `FwUpdateOffer::builder`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FwUpdateOfferBuilder {
+    offer: FwUpdateOffer,
+    force_ignore_version: bool,
+    force_reset: bool,
+}
+
+impl FwUpdateOfferBuilder {
+    /// Asks the component to take the offer whatever version it is running
+    pub fn force_ignore_version(&mut self) -> &mut Self {
+        self.force_ignore_version = true;
+        self
+    }
+
+    /// Asks the component to reset as soon as the update is complete
+    pub fn force_reset(&mut self) -> &mut Self {
+        self.force_reset = true;
+        self
+    }
+
+    /// Sets the segment of the image being offered, for images split into several segments
+    pub fn segment_number(&mut self, segment_number: u8) -> &mut Self {
+        self.offer.component_info.segment_number = segment_number;
+        self
+    }
+
+    /// Sets the vendor specific dword sent with the offer
+    pub fn vendor_specific(&mut self, vendor_specific: u32) -> &mut Self {
+        self.offer.vendor_specific = vendor_specific;
+        self
+    }
+
+    /// Overrides the protocol revision and misc bits, which default to PROTOCOL_VER and zero
+    pub fn misc_and_protocol_version(&mut self, misc_and_protocol_version: MiscAndProtocol) -> &mut Self {
+        self.offer.misc_and_protocol_version = misc_and_protocol_version;
+        self
+    }
+
+    /// Returns the offer with the force flags applied
+    pub fn build(&self) -> FwUpdateOffer {
+        FwUpdateOffer {
+            component_info: self
+                .offer
+                .component_info
+                .with_flags(self.force_ignore_version, self.force_reset),
+            ..self.offer
         }
     }
 }
 
+/// Single line summary of an offer for defmt logging, created with `FwUpdateOffer::summary`
+#[cfg(feature = "defmt")]
+#[derive(Copy, Clone, Debug)]
+pub struct FwUpdateOfferSummary<'a>(&'a FwUpdateOffer);
+
+#[cfg(feature = "defmt")]
+impl FwUpdateOffer {
+    /// Summarizes the token, component, offered version and force flags of the offer in one defmt value
+    pub fn summary(&self) -> FwUpdateOfferSummary<'_> {
+        FwUpdateOfferSummary(self)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for FwUpdateOfferSummary<'_> {
+    fn format(&self, fmt: defmt::Formatter) {
+        let offer = self.0;
+        let version = offer.firmware_version;
+        defmt::write!(
+            fmt,
+            "offer token={} component={} version={}.{}.{} force_ignore_version={} force_reset={}",
+            offer.component_info.token,
+            offer.component_info.component_id,
+            version.major,
+            version.minor,
+            version.variant,
+            offer.component_info.byte1.force_ignore_version(),
+            offer.component_info.byte1.force_reset(),
+        )
+    }
+}
+
 impl Default for FwUpdateOffer {
     fn default() -> Self {
         Self::new(HostToken::Driver, 0, FwVersion::default(), 0, 0)
@@ -296,9 +799,9 @@ impl Default for FwUpdateOffer {
 }
 
 // Convert to bytes
-impl From<&FwUpdateOffer> for [u8; 32] {
+impl From<&FwUpdateOffer> for [u8; FwUpdateOffer::SERIALIZED_SIZE] {
     fn from(command: &FwUpdateOffer) -> Self {
-        let mut bytes = [0u8; 32];
+        let mut bytes = [0u8; FwUpdateOffer::SERIALIZED_SIZE];
 
         // Serialize component_info
         bytes[0] = command.component_info.segment_number;
@@ -315,17 +818,17 @@ impl From<&FwUpdateOffer> for [u8; 32] {
         bytes[8..12].copy_from_slice(&command.vendor_specific.to_le_bytes());
 
         // Serialize misc_and_protocol_version
-        bytes[12..16].copy_from_slice(&command.misc_and_protocol_version.to_le_bytes());
+        bytes[12..16].copy_from_slice(&u32::from(command.misc_and_protocol_version).to_le_bytes());
 
         bytes
     }
 }
 
 // Convert from bytes
-impl TryFrom<&[u8; 32]> for FwUpdateOffer {
+impl TryFrom<&[u8; FwUpdateOffer::SERIALIZED_SIZE]> for FwUpdateOffer {
     type Error = ConversionError;
 
-    fn try_from(bytes: &[u8; 32]) -> Result<Self, Self::Error> {
+    fn try_from(bytes: &[u8; FwUpdateOffer::SERIALIZED_SIZE]) -> Result<Self, Self::Error> {
         let component_info = UpdateOfferComponentInfo {
             segment_number: bytes[0],
             byte1: UpdateOfferComponentInfoByte1 { packed_byte: bytes[1] },
@@ -358,7 +861,7 @@ impl TryFrom<&[u8; 32]> for FwUpdateOffer {
             component_info,
             firmware_version,
             vendor_specific,
-            misc_and_protocol_version,
+            misc_and_protocol_version: MiscAndProtocol::from(misc_and_protocol_version),
         })
     }
 }
@@ -382,6 +885,14 @@ impl UpdateOfferComponentInfo {
             token,
         }
     }
+
+    /// Returns the component info with the force flags set, the reserved bits of byte1 are cleared
+    pub fn with_flags(self, force_ignore_version: bool, force_reset: bool) -> Self {
+        Self {
+            byte1: UpdateOfferComponentInfoByte1::new(force_ignore_version, force_reset),
+            ..self
+        }
+    }
 }
 
 impl Default for UpdateOfferComponentInfo {
@@ -412,6 +923,43 @@ impl UpdateOfferComponentInfoByte1 {
     }
 }
 
+/// Last dword of an offer: the protocol revision the Host speaks and the miscellaneous bits above it
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MiscAndProtocol {
+    pub packed_dword: u32, // 32-bits: 4 bits for protocol version, 28 bits for misc
+}
+
+impl MiscAndProtocol {
+    /// `misc` is truncated to the 28 bits above the protocol version, and `protocol_version` to its 4 bits
+    pub fn new(protocol_version: u8, misc: u32) -> Self {
+        let mut packed_dword = (protocol_version as u32) & 0x0000000F; // Bits 3:0
+        packed_dword |= (misc << 4) & 0xFFFFFFF0; // Bits 31:4
+        Self { packed_dword }
+    }
+
+    pub fn protocol_version(&self) -> u8 {
+        (self.packed_dword & 0x0000000F) as u8
+    }
+
+    /// Miscellaneous bits 31:4, shifted down to start at bit 0
+    pub fn misc(&self) -> u32 {
+        self.packed_dword >> 4
+    }
+}
+
+impl From<u32> for MiscAndProtocol {
+    fn from(packed_dword: u32) -> Self {
+        Self { packed_dword }
+    }
+}
+
+impl From<MiscAndProtocol> for u32 {
+    fn from(value: MiscAndProtocol) -> Self {
+        value.packed_dword
+    }
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 /// LSB first Representation of OfferInformationComponentInfo
@@ -444,6 +992,9 @@ pub struct FwUpdateOfferInformation {
 }
 
 impl FwUpdateOfferInformation {
+    /// Size of the command on the wire
+    pub const SERIALIZED_SIZE: usize = 16;
+
     pub fn new(component_info: OfferInformationComponentInfo) -> Self {
         Self {
             component_info,
@@ -455,9 +1006,9 @@ impl FwUpdateOfferInformation {
 }
 
 // Convert to bytes
-impl From<&FwUpdateOfferInformation> for [u8; 16] {
+impl From<&FwUpdateOfferInformation> for [u8; FwUpdateOfferInformation::SERIALIZED_SIZE] {
     fn from(info: &FwUpdateOfferInformation) -> Self {
-        let mut bytes = [0u8; 16];
+        let mut bytes = [0u8; FwUpdateOfferInformation::SERIALIZED_SIZE];
 
         // Serialize the component_info
         bytes[0..4].copy_from_slice(&[
@@ -477,10 +1028,10 @@ impl From<&FwUpdateOfferInformation> for [u8; 16] {
 }
 
 // Convert from bytes
-impl TryFrom<&[u8; 16]> for FwUpdateOfferInformation {
+impl TryFrom<&[u8; FwUpdateOfferInformation::SERIALIZED_SIZE]> for FwUpdateOfferInformation {
     type Error = ConversionError;
 
-    fn try_from(bytes: &[u8; 16]) -> Result<Self, Self::Error> {
+    fn try_from(bytes: &[u8; FwUpdateOfferInformation::SERIALIZED_SIZE]) -> Result<Self, Self::Error> {
         let code = match bytes[0] {
             0x00 => OfferInformationCodeValues::StartEntireTransaction,
             0x01 => OfferInformationCodeValues::StartOfferList,
@@ -563,6 +1114,9 @@ pub struct FwUpdateOfferExtended {
 }
 
 impl FwUpdateOfferExtended {
+    /// Size of the command on the wire
+    pub const SERIALIZED_SIZE: usize = 16;
+
     pub fn new(component_info: OfferExtendedComponentInfo) -> Self {
         Self {
             component_info,
@@ -583,6 +1137,13 @@ pub enum OfferCommandExtendedCodeValues {
     VendorSpecific(u8),
 }
 
+impl OfferCommandExtendedCodeValues {
+    /// Vendor specific code this crate uses to commit an image staged without the last block flag
+    /// Not part of CFU: only components built on this crate's StandardClient understand it, any other component
+    /// answers CmdNotSupported
+    pub const ACTIVATE_STAGED_IMAGE: Self = Self::VendorSpecific(0xA0);
+}
+
 // Convert to bytes
 impl From<OfferCommandExtendedCodeValues> for u8 {
     fn from(value: OfferCommandExtendedCodeValues) -> Self {
@@ -604,9 +1165,9 @@ impl From<u8> for OfferCommandExtendedCodeValues {
 }
 
 // Convert to bytes
-impl From<&FwUpdateOfferExtended> for [u8; 16] {
+impl From<&FwUpdateOfferExtended> for [u8; FwUpdateOfferExtended::SERIALIZED_SIZE] {
     fn from(command: &FwUpdateOfferExtended) -> Self {
-        let mut bytes = [0u8; 16];
+        let mut bytes = [0u8; FwUpdateOfferExtended::SERIALIZED_SIZE];
         // Serialize the component_info
         bytes[0..4].copy_from_slice(&[
             command.component_info.code.into(),
@@ -625,10 +1186,10 @@ impl From<&FwUpdateOfferExtended> for [u8; 16] {
 }
 
 // Convert from bytes
-impl TryFrom<&[u8; 16]> for FwUpdateOfferExtended {
+impl TryFrom<&[u8; FwUpdateOfferExtended::SERIALIZED_SIZE]> for FwUpdateOfferExtended {
     type Error = ConversionError;
 
-    fn try_from(bytes: &[u8; 16]) -> Result<Self, Self::Error> {
+    fn try_from(bytes: &[u8; FwUpdateOfferExtended::SERIALIZED_SIZE]) -> Result<Self, Self::Error> {
         let code = OfferCommandExtendedCodeValues::from(bytes[0]);
         let reserved = 0; // bytes[1] is reserved
         let component_id = SpecialComponentIds::try_from(bytes[2]).map_err(|_| ConversionError::ValueOutOfRange)?;
@@ -672,16 +1233,87 @@ pub struct FwUpdateContentHeader {
     pub firmware_address: u32,
 }
 
+impl FwUpdateContentCommand {
+    /// Size of the command on the wire
+    pub const SERIALIZED_SIZE: usize = 60;
+
+    /// Builds a content command whose flags, sequence number and data_length agree
+    /// A first block must have sequence number 0, and every block must carry 1..=DEFAULT_DATA_LENGTH bytes of data.
+    /// Returns InvalidBlockTransition or InvalidDataLength otherwise
+    pub fn new(
+        flags: u8,
+        sequence_num: u16,
+        data: [u8; DEFAULT_DATA_LENGTH],
+        data_length: u8,
+    ) -> Result<Self, CfuProtocolError> {
+        if flags & FW_UPDATE_FLAG_FIRST_BLOCK != 0 && sequence_num != 0 {
+            return Err(CfuProtocolError::InvalidBlockTransition);
+        }
+        if !(1..=DEFAULT_DATA_LENGTH).contains(&(data_length as usize)) {
+            return Err(CfuProtocolError::InvalidDataLength(data_length));
+        }
+        Ok(Self {
+            header: FwUpdateContentHeader {
+                flags,
+                data_length,
+                sequence_num,
+                firmware_address: 0,
+            },
+            data,
+        })
+    }
+
+    /// Decodes a command received from the Host
+    /// Returns BadResponse if the frame is not SERIALIZED_SIZE bytes, and DeserializeField if data_length exceeds the
+    /// data it can carry
+    pub fn deserialize_checked(bytes: &[u8]) -> Result<Self, CfuProtocolError> {
+        let bytes = <&[u8; Self::SERIALIZED_SIZE]>::try_from(bytes).map_err(|_| CfuProtocolError::BadResponse)?;
+        let cmd = Self::try_from(bytes).map_err(|_| CfuProtocolError::BadResponse)?;
+        if cmd.header.data_length as usize > DEFAULT_DATA_LENGTH {
+            trace!(
+                "content block {} data_length {} too long",
+                cmd.header.sequence_num,
+                cmd.header.data_length
+            );
+            return Err(CfuProtocolError::DeserializeField {
+                struct_name: "FwUpdateContentHeader",
+                field_name: "data_length",
+            });
+        }
+        Ok(cmd)
+    }
+
+    /// The data_length bytes of data the command carries, or None if data_length exceeds the data array
+    pub fn payload(&self) -> Option<&[u8]> {
+        self.data.get(..self.header.data_length as usize)
+    }
+}
+
+impl FwUpdateContentHeader {
+    /// Size of the header on the wire
+    pub const SERIALIZED_SIZE: usize = 8;
+}
+
 // Convert to bytes
-impl From<&FwUpdateContentCommand> for [u8; 60] {
+impl From<&FwUpdateContentHeader> for [u8; FwUpdateContentHeader::SERIALIZED_SIZE] {
+    fn from(header: &FwUpdateContentHeader) -> Self {
+        let mut bytes = [0u8; FwUpdateContentHeader::SERIALIZED_SIZE];
+        bytes[0] = header.flags;
+        bytes[1] = header.data_length;
+        bytes[2..4].copy_from_slice(&header.sequence_num.to_le_bytes());
+        bytes[4..8].copy_from_slice(&header.firmware_address.to_le_bytes());
+        bytes
+    }
+}
+
+// Convert to bytes
+impl From<&FwUpdateContentCommand> for [u8; FwUpdateContentCommand::SERIALIZED_SIZE] {
     fn from(command: &FwUpdateContentCommand) -> Self {
-        let mut bytes = [0u8; 60];
+        let mut bytes = [0u8; FwUpdateContentCommand::SERIALIZED_SIZE];
 
         // Serialize header
-        bytes[0] = command.header.flags;
-        bytes[1] = command.header.data_length;
-        bytes[2..4].copy_from_slice(&command.header.sequence_num.to_le_bytes());
-        bytes[4..8].copy_from_slice(&command.header.firmware_address.to_le_bytes());
+        let header: [u8; FwUpdateContentHeader::SERIALIZED_SIZE] = (&command.header).into();
+        bytes[..FwUpdateContentHeader::SERIALIZED_SIZE].copy_from_slice(&header);
 
         // Serialize data
         bytes[8..].copy_from_slice(&command.data);
@@ -691,10 +1323,10 @@ impl From<&FwUpdateContentCommand> for [u8; 60] {
 }
 
 // Convert from bytes
-impl TryFrom<&[u8; 60]> for FwUpdateContentCommand {
+impl TryFrom<&[u8; FwUpdateContentCommand::SERIALIZED_SIZE]> for FwUpdateContentCommand {
     type Error = ConversionError;
 
-    fn try_from(bytes: &[u8; 60]) -> Result<Self, Self::Error> {
+    fn try_from(bytes: &[u8; FwUpdateContentCommand::SERIALIZED_SIZE]) -> Result<Self, Self::Error> {
         let flags = bytes[0];
         let data_length = bytes[1];
         let sequence_num = u16::from_le_bytes(
@@ -737,6 +1369,13 @@ pub enum HostToken {
     VendorSpecific(u8),
 }
 
+impl HostToken {
+    /// Returns whether a raw token value is reserved by the spec for the standard hosts (driver or tool)
+    pub fn is_reserved(value: u8) -> bool {
+        value == u8::from(HostToken::Driver) || value == u8::from(HostToken::Tool)
+    }
+}
+
 // Convert to byte
 impl From<HostToken> for u8 {
     fn from(token: HostToken) -> Self {
@@ -775,6 +1414,9 @@ pub struct FwUpdateOfferResponse {
 }
 
 impl FwUpdateOfferResponse {
+    /// Size of the response on the wire
+    pub const SERIALIZED_SIZE: usize = 16;
+
     pub fn new_accept(token: HostToken) -> Self {
         Self {
             token,
@@ -800,10 +1442,42 @@ impl FwUpdateOfferResponse {
     }
 }
 
+/// FwUpdateOfferResponse status and reject reason collapsed into a single matchable value
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OfferOutcome {
+    /// Component has accepted the offer
+    Accepted,
+    /// Component has rejected the offer for the given reason
+    Rejected(OfferRejectReason),
+    /// Component is busy, the offer must be sent again once it is ready
+    Busy,
+    /// Component has skipped the offer, it must be offered again later
+    Skipped,
+    /// Primary component is ready to accept offers after an OFFER_NOTIFY_ON_READY request
+    CommandReady,
+    /// Component does not support the command
+    NotSupported,
+}
+
+impl FwUpdateOfferResponse {
+    /// Classifies the response, the reject reason is only meaningful for a rejected offer
+    pub fn outcome(&self) -> OfferOutcome {
+        match self.status {
+            OfferStatus::Accept => OfferOutcome::Accepted,
+            OfferStatus::Reject => OfferOutcome::Rejected(self.reject_reason),
+            OfferStatus::Busy => OfferOutcome::Busy,
+            OfferStatus::Skip => OfferOutcome::Skipped,
+            OfferStatus::CommandReady => OfferOutcome::CommandReady,
+            OfferStatus::CmdNotSupported => OfferOutcome::NotSupported,
+        }
+    }
+}
+
 // Convert to bytes
-impl From<&FwUpdateOfferResponse> for [u8; 16] {
+impl From<&FwUpdateOfferResponse> for [u8; FwUpdateOfferResponse::SERIALIZED_SIZE] {
     fn from(response: &FwUpdateOfferResponse) -> Self {
-        let mut buffer = [0u8; 16];
+        let mut buffer = [0u8; FwUpdateOfferResponse::SERIALIZED_SIZE];
 
         // Initialize fields 0..3, 4..8, 9..12, 13..16 to 0.
         buffer[0..3].copy_from_slice(&[0; 3]); // response._reserved0 is reserved
@@ -818,10 +1492,10 @@ impl From<&FwUpdateOfferResponse> for [u8; 16] {
 }
 
 // Convert from bytes
-impl TryFrom<[u8; 16]> for FwUpdateOfferResponse {
+impl TryFrom<[u8; FwUpdateOfferResponse::SERIALIZED_SIZE]> for FwUpdateOfferResponse {
     type Error = ConversionError;
 
-    fn try_from(buffer: [u8; 16]) -> Result<Self, Self::Error> {
+    fn try_from(buffer: [u8; FwUpdateOfferResponse::SERIALIZED_SIZE]) -> Result<Self, Self::Error> {
         Ok(Self {
             token: HostToken::try_from(buffer[3]).map_err(|_| ConversionError::ByteConversionError)?,
             reject_reason: OfferRejectReason::try_from(buffer[8]).map_err(|_| ConversionError::ByteConversionError)?,
@@ -895,6 +1569,27 @@ pub enum OfferStatus {
     CmdNotSupported = 0xFF,
 }
 
+impl fmt::Display for OfferStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OfferStatus::Skip => "offer skipped, it must be offered again later",
+            OfferStatus::Accept => "offer accepted",
+            OfferStatus::Reject => "offer rejected",
+            OfferStatus::Busy => "component busy, offer must be sent again once it is ready",
+            OfferStatus::CommandReady => "component ready to accept offers",
+            OfferStatus::CmdNotSupported => "command not supported",
+        })
+    }
+}
+
+impl OfferStatus {
+    /// Returns whether the same offer may be accepted if it is sent again later
+    /// Only Busy is transient, every other status is the component's final answer to the offer
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, OfferStatus::Busy)
+    }
+}
+
 // Convert to byte
 impl From<OfferStatus> for u8 {
     fn from(value: OfferStatus) -> Self {
@@ -958,6 +1653,36 @@ pub enum CfuUpdateContentResponseStatus {
     ErrorInvalid = 0x0B,
 }
 
+impl fmt::Display for CfuUpdateContentResponseStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CfuUpdateContentResponseStatus::Success => "content block written",
+            CfuUpdateContentResponseStatus::ErrorPrepare => "component not prepared to receive content",
+            CfuUpdateContentResponseStatus::ErrorWrite => "content block could not be written",
+            CfuUpdateContentResponseStatus::ErrorComplete => "swap could not be set up in response to last block",
+            CfuUpdateContentResponseStatus::ErrorVerify => "verification failed in response to verify flag",
+            CfuUpdateContentResponseStatus::ErrorCrc => "CRC verification failed in response to last block",
+            CfuUpdateContentResponseStatus::ErrorSignature => "signature verification failed in response to last block",
+            CfuUpdateContentResponseStatus::ErrorVersion => "version verification failed in response to last block",
+            CfuUpdateContentResponseStatus::SwapPending => "swap pending, no further update commands accepted",
+            CfuUpdateContentResponseStatus::ErrorInvalidAddr => "invalid destination address for content",
+            CfuUpdateContentResponseStatus::ErrorNoOffer => "content received without an accepted offer",
+            CfuUpdateContentResponseStatus::ErrorInvalid => "invalid content command",
+        })
+    }
+}
+
+impl CfuUpdateContentResponseStatus {
+    /// Returns whether re-sending the same block may succeed, e.g. after a transient flash glitch
+    /// Verification failures and protocol errors are terminal, the block would fail the same way again
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            CfuUpdateContentResponseStatus::ErrorWrite | CfuUpdateContentResponseStatus::ErrorPrepare
+        )
+    }
+}
+
 // Convert to byte
 impl From<CfuUpdateContentResponseStatus> for u8 {
     fn from(value: CfuUpdateContentResponseStatus) -> Self {
@@ -1012,6 +1737,9 @@ pub struct FwUpdateContentResponse {
 }
 
 impl FwUpdateContentResponse {
+    /// Size of the response on the wire
+    pub const SERIALIZED_SIZE: usize = 16;
+
     pub fn new(sequence: u16, status: CfuUpdateContentResponseStatus) -> Self {
         Self {
             sequence,
@@ -1020,12 +1748,79 @@ impl FwUpdateContentResponse {
             _reserved1: [0; 11],
         }
     }
+
+    /// Acks the content block with the given sequence number
+    pub fn success(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::Success)
+    }
+
+    /// Reports a failure of the content block with the given sequence number
+    pub fn error(sequence: u16, status: CfuUpdateContentResponseStatus) -> Self {
+        Self::new(sequence, status)
+    }
+
+    pub fn prepare_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorPrepare)
+    }
+
+    pub fn write_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorWrite)
+    }
+
+    pub fn complete_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorComplete)
+    }
+
+    pub fn verify_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorVerify)
+    }
+
+    pub fn crc_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorCrc)
+    }
+
+    pub fn signature_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorSignature)
+    }
+
+    pub fn version_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorVersion)
+    }
+
+    pub fn swap_pending(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::SwapPending)
+    }
+
+    pub fn invalid_addr_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorInvalidAddr)
+    }
+
+    pub fn no_offer_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorNoOffer)
+    }
+
+    pub fn invalid_error(sequence: u16) -> Self {
+        Self::new(sequence, CfuUpdateContentResponseStatus::ErrorInvalid)
+    }
+
+    /// Decodes a burst of back to back responses, e.g. from a transport that pipelines content blocks
+    /// Each frame yields its own result, a trailing partial frame yields BadResponse and a frame with an unknown
+    /// status yields DeserializeField
+    pub fn deserialize_many(buf: &[u8]) -> impl Iterator<Item = Result<Self, CfuProtocolError>> + '_ {
+        buf.chunks(Self::SERIALIZED_SIZE).map(|frame| {
+            let bytes = <[u8; Self::SERIALIZED_SIZE]>::try_from(frame).map_err(|_| CfuProtocolError::BadResponse)?;
+            Self::try_from(bytes).map_err(|_| CfuProtocolError::DeserializeField {
+                struct_name: "FwUpdateContentResponse",
+                field_name: "status",
+            })
+        })
+    }
 }
 
 // Convert to bytes
-impl From<&FwUpdateContentResponse> for [u8; 16] {
+impl From<&FwUpdateContentResponse> for [u8; FwUpdateContentResponse::SERIALIZED_SIZE] {
     fn from(response: &FwUpdateContentResponse) -> Self {
-        let mut buffer = [0u8; 16];
+        let mut buffer = [0u8; FwUpdateContentResponse::SERIALIZED_SIZE];
         buffer[0..2].copy_from_slice(&response.sequence.to_le_bytes());
         buffer[2..4].copy_from_slice(&[0; 2]); // response._reserved0 is reserved
         buffer[4] = response.status.into();
@@ -1035,10 +1830,10 @@ impl From<&FwUpdateContentResponse> for [u8; 16] {
 }
 
 // Convert from bytes
-impl TryFrom<[u8; 16]> for FwUpdateContentResponse {
+impl TryFrom<[u8; FwUpdateContentResponse::SERIALIZED_SIZE]> for FwUpdateContentResponse {
     type Error = ConversionError;
 
-    fn try_from(buffer: [u8; 16]) -> Result<Self, Self::Error> {
+    fn try_from(buffer: [u8; FwUpdateContentResponse::SERIALIZED_SIZE]) -> Result<Self, Self::Error> {
         Ok(Self {
             sequence: u16::from_le_bytes([buffer[0], buffer[1]]),
             _reserved0: 0, // [buffer[2], buffer[3]] is reserved
@@ -1049,6 +1844,68 @@ impl TryFrom<[u8; 16]> for FwUpdateContentResponse {
     }
 }
 
+/// Content response of a component that sends extension data after the standard fields
+/// N is the full size of the response on the wire, standard fields included
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ExtendedContentResponse<const N: usize> {
+    /// Standard fields, decoded from the front of the response
+    pub response: FwUpdateContentResponse,
+    raw: [u8; N],
+}
+
+impl<const N: usize> ExtendedContentResponse<N> {
+    /// Decodes the standard fields from the front of `bytes`, keeping the rest as extension data
+    /// Returns BadResponse if N is smaller than a standard response, and DeserializeField for an unknown status
+    pub fn deserialize(bytes: [u8; N]) -> Result<Self, CfuProtocolError> {
+        let standard = bytes
+            .get(..FwUpdateContentResponse::SERIALIZED_SIZE)
+            .and_then(|b| <[u8; FwUpdateContentResponse::SERIALIZED_SIZE]>::try_from(b).ok())
+            .ok_or(CfuProtocolError::BadResponse)?;
+        let response = FwUpdateContentResponse::try_from(standard).map_err(|_| CfuProtocolError::DeserializeField {
+            struct_name: "FwUpdateContentResponse",
+            field_name: "status",
+        })?;
+        Ok(Self { response, raw: bytes })
+    }
+
+    /// Bytes the component sent after the standard fields
+    pub fn extension(&self) -> &[u8] {
+        self.raw
+            .get(FwUpdateContentResponse::SERIALIZED_SIZE..)
+            .unwrap_or_default()
+    }
+}
+
+/// Any command a Host sends to a component, decoded from its frame with `CfuCommand::decode`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum CfuCommand {
+    Offer(FwUpdateOffer),
+    Information(FwUpdateOfferInformation),
+    Extended(FwUpdateOfferExtended),
+    Content(FwUpdateContentCommand),
+}
+
+impl CfuCommand {
+    /// Decodes a frame received from the Host
+    /// Content commands are told apart by their size; offers, offer information and offer extended commands share
+    /// a size and are told apart by their component id. Returns BadResponse for any other frame
+    pub fn decode(bytes: &[u8]) -> Result<Self, CfuProtocolError> {
+        if bytes.len() == FwUpdateContentCommand::SERIALIZED_SIZE {
+            return FwUpdateContentCommand::deserialize_checked(bytes).map(CfuCommand::Content);
+        }
+        let bytes =
+            <&[u8; FwUpdateOffer::SERIALIZED_SIZE]>::try_from(bytes).map_err(|_| CfuProtocolError::BadResponse)?;
+        let cmd = match bytes[2] {
+            COMPONENT_ID_INFO => FwUpdateOfferInformation::try_from(bytes).map(CfuCommand::Information),
+            COMPONENT_ID_COMMAND => FwUpdateOfferExtended::try_from(bytes).map(CfuCommand::Extended),
+            _ => FwUpdateOffer::try_from(bytes).map(CfuCommand::Offer),
+        };
+        cmd.map_err(|_| CfuProtocolError::BadResponse)
+    }
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
@@ -1059,6 +1916,8 @@ pub enum CfuProtocolError {
     TimeoutError(u8),
     /// Invalid Block transition
     InvalidBlockTransition,
+    /// Component acked a content block with a different sequence number than the one sent
+    UnexpectedSequence { expected: u16, actual: u16 },
     /// Bad Response
     BadResponse,
     /// WriterError
@@ -1067,8 +1926,118 @@ pub enum CfuProtocolError {
     CfuContentUpdateResponseError(CfuUpdateContentResponseStatus),
     /// OfferStatusError
     CfuOfferStatusError(OfferStatus),
+    /// Too many components were given, or they don't pair up with the images
+    InvalidComponentCount,
+    /// Image is larger than the component can store
+    ImageTooLarge,
+    /// Component depends on itself through its subcomponents
+    DependencyCycle(ComponentId),
+    /// Content command data_length is zero or exceeds the data a block can carry
+    InvalidDataLength(u8),
+    /// Component answered an offer with CmdNotSupported, it should be skipped rather than retried
+    CommandNotSupported(ComponentId),
+    /// Component already runs the offered version, there is nothing to update and nothing to retry
+    UpToDate(ComponentId),
+    /// A field of a received struct holds a value that can't be decoded
+    DeserializeField {
+        struct_name: &'static str,
+        field_name: &'static str,
+    },
+    /// Content read back from a component differs from the image in the block with this sequence number
+    VerifyMismatch { sequence: u16 },
+    /// Content stream to a component was cancelled through its hooks
+    Cancelled(ComponentId),
+    /// A component with this id is already registered
+    DuplicateComponent(ComponentId),
+    /// Component was still answering an offer with a retryable status once the offer retries were used up
+    RetriesExhausted {
+        component: ComponentId,
+        last_status: OfferStatus,
+    },
+    /// Component was still failing a content block with a retryable status once the block retries were used up
+    BlockRetriesExhausted {
+        component: ComponentId,
+        sequence: u16,
+        last_status: CfuUpdateContentResponseStatus,
+    },
+}
+
+/// Writer errors surface as WriterError, so `?` can be used on writer calls
+/// Call sites that know the component should use the host's mapping instead, which reports a timeout as
+/// TimeoutError of that component
+impl From<CfuWriterError> for CfuProtocolError {
+    fn from(error: CfuWriterError) -> Self {
+        CfuProtocolError::WriterError(error)
+    }
 }
 
+impl fmt::Display for CfuProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CfuProtocolError::UpdateError(cmpt_id) => write!(f, "update of component {cmpt_id} failed"),
+            CfuProtocolError::TimeoutError(cmpt_id) => write!(f, "timed out waiting on component {cmpt_id}"),
+            CfuProtocolError::InvalidBlockTransition => f.write_str("invalid content block transition"),
+            CfuProtocolError::UnexpectedSequence { expected, actual } => {
+                write!(f, "expected ack for sequence {expected}, got {actual}")
+            }
+            CfuProtocolError::BadResponse => f.write_str("malformed response"),
+            CfuProtocolError::WriterError(e) => write!(f, "writer error: {e}"),
+            CfuProtocolError::CfuContentUpdateResponseError(status) => write!(f, "content rejected: {status}"),
+            CfuProtocolError::CfuOfferStatusError(status) => write!(f, "offer not accepted: {status}"),
+            CfuProtocolError::InvalidComponentCount => {
+                f.write_str("too many components, or components and images don't pair up")
+            }
+            CfuProtocolError::ImageTooLarge => f.write_str("image is larger than the component can store"),
+            CfuProtocolError::DependencyCycle(cmpt_id) => {
+                write!(f, "component {cmpt_id} depends on itself through its subcomponents")
+            }
+            CfuProtocolError::InvalidDataLength(data_length) => {
+                write!(f, "content block data_length {data_length} out of range")
+            }
+            CfuProtocolError::CommandNotSupported(cmpt_id) => {
+                write!(f, "component {cmpt_id} does not support the offer")
+            }
+            CfuProtocolError::UpToDate(cmpt_id) => write!(f, "component {cmpt_id} already runs the offered version"),
+            CfuProtocolError::DeserializeField {
+                struct_name,
+                field_name,
+            } => {
+                write!(f, "invalid value in {struct_name}.{field_name}")
+            }
+            CfuProtocolError::VerifyMismatch { sequence } => {
+                write!(f, "block {sequence} read back differs from the image")
+            }
+            CfuProtocolError::Cancelled(cmpt_id) => write!(f, "content stream to component {cmpt_id} was cancelled"),
+            CfuProtocolError::DuplicateComponent(cmpt_id) => write!(f, "component {cmpt_id} is already registered"),
+            CfuProtocolError::RetriesExhausted { component, last_status } => {
+                write!(f, "gave up offering component {component}: {last_status}")
+            }
+            CfuProtocolError::BlockRetriesExhausted {
+                component,
+                sequence,
+                last_status,
+            } => {
+                write!(f, "gave up on block {sequence} of component {component}: {last_status}")
+            }
+        }
+    }
+}
+
+// Wire sizes fixed by the CFU spec, checked at compile time so a layout change that breaks them fails to build
+const _: () = {
+    assert!(GetFwVersionResponse::SERIALIZED_SIZE == 60);
+    assert!(FwUpdateOfferInformation::SERIALIZED_SIZE == 16);
+    assert!(FwUpdateOfferExtended::SERIALIZED_SIZE == 16);
+    assert!(FwUpdateOffer::SERIALIZED_SIZE == 16);
+    assert!(FwUpdateContentCommand::SERIALIZED_SIZE == 60);
+    assert!(FwUpdateOfferResponse::SERIALIZED_SIZE == 16);
+    assert!(FwUpdateContentResponse::SERIALIZED_SIZE == 16);
+    assert!(
+        FwUpdateContentHeader::SERIALIZED_SIZE + DEFAULT_DATA_LENGTH == FwUpdateContentCommand::SERIALIZED_SIZE,
+        "content header and data must fill the content command"
+    );
+};
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1085,11 +2054,13 @@ mod tests {
                 variant: 3,
             },
             vendor_specific: 0x2,
-            misc_and_protocol_version: 0x87654321,
+            misc_and_protocol_version: MiscAndProtocol::from(0x87654321),
         };
 
         // Serialize the offer command to a byte array
-        let offer_command_serialized: [u8; 32] = (&offer_command_orig).into();
+        let offer_command_serialized: [u8; 16] = (&offer_command_orig).into();
+        assert_eq!(offer_command_serialized[2], 1);
+        assert_eq!(&offer_command_serialized[12..], &0x87654321u32.to_le_bytes());
 
         // Deserialize the byte array back to a FwUpdateOffer instance
         let offer_command_deserialized = FwUpdateOffer::try_from(&offer_command_serialized);
@@ -1252,4 +2223,618 @@ mod tests {
         // Compare both
         assert_eq!(content_response_orig, content_response_deserialized);
     }
+
+    #[test]
+    fn test_offer_force_flags() {
+        let mut component_info = UpdateOfferComponentInfo::new(HostToken::Driver, 1);
+        component_info.byte1.packed_byte = 0x3F;
+        let component_info = component_info.with_flags(true, false);
+        assert!(component_info.byte1.force_ignore_version());
+        assert!(!component_info.byte1.force_reset());
+
+        // force_ignore_version is the most significant bit of byte 1, the reserved bits stay clear
+        let offer = FwUpdateOffer {
+            component_info,
+            ..FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::default(), 0, 0)
+        };
+        let bytes: [u8; FwUpdateOffer::SERIALIZED_SIZE] = (&offer).into();
+        assert_eq!(bytes[1], 0b1000_0000);
+        let bytes: [u8; FwUpdateOffer::SERIALIZED_SIZE] = (&FwUpdateOffer {
+            component_info: component_info.with_flags(false, true),
+            ..offer
+        })
+            .into();
+        assert_eq!(bytes[1], 0b0100_0000);
+    }
+
+    #[test]
+    fn test_offer_vendor_specific_layout() {
+        let mut offer = FwUpdateOffer::default();
+        offer.set_vendor_specific_bytes([0x11, 0x22, 0x33, 0x44]);
+        let bytes: [u8; FwUpdateOffer::SERIALIZED_SIZE] = (&offer).into();
+        assert_eq!(bytes[8..12], [0x11, 0x22, 0x33, 0x44]);
+
+        let offer = FwUpdateOffer::try_from(&bytes).unwrap();
+        assert_eq!(offer.vendor_specific_bytes(), [0x11, 0x22, 0x33, 0x44]);
+        assert_eq!(offer.vendor_specific_words(), [0x2211, 0x4433]);
+
+        let mut offer = FwUpdateOffer::default();
+        offer.set_vendor_specific_words([0xBEEF, 0xCAFE]);
+        let bytes: [u8; FwUpdateOffer::SERIALIZED_SIZE] = (&offer).into();
+        assert_eq!(bytes[8..12], [0xEF, 0xBE, 0xFE, 0xCA]);
+        assert_eq!(
+            FwUpdateOffer::try_from(&bytes).unwrap().vendor_specific_words(),
+            [0xBEEF, 0xCAFE]
+        );
+    }
+
+    #[test]
+    fn test_offer_builder() {
+        let version = FwVersion::new(0x02000000);
+        let offer = FwUpdateOffer::builder(HostToken::Tool, 3, version)
+            .force_reset()
+            .build();
+        let bytes: [u8; FwUpdateOffer::SERIALIZED_SIZE] = (&offer).into();
+        assert_eq!(bytes[1], 0b0100_0000);
+        assert_eq!(bytes[2], 3);
+        assert_eq!(offer.firmware_version, version);
+        // the builder stamps the crate's protocol revision unless told otherwise
+        assert_eq!(offer.protocol_version(), PROTOCOL_VER);
+
+        let offer = FwUpdateOffer::builder(HostToken::Driver, 3, version)
+            .force_ignore_version()
+            .force_reset()
+            .segment_number(2)
+            .vendor_specific(0xDEADBEEF)
+            .misc_and_protocol_version(MiscAndProtocol::new(4, 0x10))
+            .build();
+        assert!(offer.component_info.byte1.force_ignore_version());
+        assert!(offer.component_info.byte1.force_reset());
+        assert_eq!(offer.component_info.segment_number, 2);
+        assert_eq!(offer.vendor_specific, 0xDEADBEEF);
+        assert_eq!(offer.protocol_version(), 4);
+        assert_eq!(offer.misc_and_protocol_version.misc(), 0x10);
+
+        // without any of the optional fields the builder matches new with PROTOCOL_VER
+        assert_eq!(
+            FwUpdateOffer::builder(HostToken::Driver, 3, version).build(),
+            FwUpdateOffer::new(HostToken::Driver, 3, version, 0, u32::from(PROTOCOL_VER))
+        );
+    }
+
+    #[test]
+    fn test_misc_and_protocol() {
+        let fields = |dword: u32| {
+            let misc = MiscAndProtocol::from(dword);
+            (misc.protocol_version(), misc.misc())
+        };
+        assert_eq!(fields(0x00000002), (2, 0));
+        assert_eq!(fields(0x00000004), (4, 0));
+        assert_eq!(fields(0x87654321), (1, 0x08765432));
+        assert_eq!(fields(0xFFFFFFF0), (0, 0x0FFFFFFF));
+
+        let misc = MiscAndProtocol::new(2, 0x1234);
+        assert_eq!(u32::from(misc), 0x00012342);
+        // out of range fields are truncated rather than spilling into each other
+        assert_eq!(u32::from(MiscAndProtocol::new(0x12, 0xF0000001)), 0x00000012);
+    }
+
+    #[test]
+    fn test_reserved_component_ids() {
+        assert!(SpecialComponentIds::is_reserved(0xFE));
+        assert!(SpecialComponentIds::is_reserved(COMPONENT_ID_INFO));
+        assert!(!SpecialComponentIds::is_reserved(1));
+        assert!(!SpecialComponentIds::is_reserved(0xFD));
+        assert_eq!(SpecialComponentIds::Command as ComponentId, COMPONENT_ID_COMMAND);
+        assert_eq!(SpecialComponentIds::Info as ComponentId, COMPONENT_ID_INFO);
+    }
+
+    #[test]
+    fn test_cfu_command_decode() {
+        let offer = FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::new(0x01020304), 0, 2);
+        let bytes: [u8; 16] = (&offer).into();
+        assert_eq!(CfuCommand::decode(&bytes), Ok(CfuCommand::Offer(offer)));
+
+        let info = FwUpdateOfferInformation::new(OfferInformationComponentInfo::new(
+            HostToken::Tool,
+            SpecialComponentIds::Info,
+            OfferInformationCodeValues::StartOfferList,
+        ));
+        let bytes: [u8; 16] = (&info).into();
+        assert_eq!(CfuCommand::decode(&bytes), Ok(CfuCommand::Information(info)));
+
+        let extended = FwUpdateOfferExtended::new(OfferExtendedComponentInfo::new(
+            HostToken::Tool,
+            SpecialComponentIds::Command,
+            OfferCommandExtendedCodeValues::OfferNotifyOnReady,
+        ));
+        let bytes: [u8; 16] = (&extended).into();
+        assert_eq!(CfuCommand::decode(&bytes), Ok(CfuCommand::Extended(extended)));
+
+        let mut bytes = [0u8; 60];
+        bytes[0] = FW_UPDATE_FLAG_FIRST_BLOCK;
+        bytes[1] = 10;
+        bytes[2] = 3;
+        let Ok(CfuCommand::Content(cmd)) = CfuCommand::decode(&bytes) else {
+            panic!("content frame not decoded as content");
+        };
+        assert_eq!(cmd.header.sequence_num, 3);
+        assert_eq!(cmd.header.data_length, 10);
+
+        assert_eq!(CfuCommand::decode(&bytes[..20]), Err(CfuProtocolError::BadResponse));
+    }
+
+    #[test]
+    fn test_content_command_new() {
+        let data = [0x5A; DEFAULT_DATA_LENGTH];
+        let cmd = FwUpdateContentCommand::new(FW_UPDATE_FLAG_FIRST_BLOCK, 0, data, 52).unwrap();
+        assert_eq!(cmd.payload(), Some(&data[..]));
+        assert!(FwUpdateContentCommand::new(0, 7, data, 1).is_ok());
+        assert!(FwUpdateContentCommand::new(FW_UPDATE_FLAG_LAST_BLOCK, 7, data, 20).is_ok());
+        assert!(
+            FwUpdateContentCommand::new(FW_UPDATE_FLAG_FIRST_BLOCK | FW_UPDATE_FLAG_LAST_BLOCK, 0, data, 40).is_ok()
+        );
+
+        // first block with a nonzero sequence number
+        assert_eq!(
+            FwUpdateContentCommand::new(FW_UPDATE_FLAG_FIRST_BLOCK, 1, data, 52),
+            Err(CfuProtocolError::InvalidBlockTransition)
+        );
+        assert_eq!(
+            FwUpdateContentCommand::new(FW_UPDATE_FLAG_FIRST_BLOCK | FW_UPDATE_FLAG_LAST_BLOCK, 3, data, 10),
+            Err(CfuProtocolError::InvalidBlockTransition)
+        );
+        // blocks without data
+        assert_eq!(
+            FwUpdateContentCommand::new(FW_UPDATE_FLAG_LAST_BLOCK, 2, data, 0),
+            Err(CfuProtocolError::InvalidDataLength(0))
+        );
+        assert_eq!(
+            FwUpdateContentCommand::new(FW_UPDATE_FLAG_FIRST_BLOCK | FW_UPDATE_FLAG_LAST_BLOCK, 0, data, 0),
+            Err(CfuProtocolError::InvalidDataLength(0))
+        );
+        // more data than a block carries
+        assert_eq!(
+            FwUpdateContentCommand::new(0, 1, data, 53),
+            Err(CfuProtocolError::InvalidDataLength(53))
+        );
+    }
+
+    #[test]
+    fn test_extended_content_response() {
+        let mut bytes = [0u8; 32];
+        let standard: [u8; 16] = (&FwUpdateContentResponse::new(7, CfuUpdateContentResponseStatus::ErrorCrc)).into();
+        bytes[..16].copy_from_slice(&standard);
+        for (i, b) in bytes[16..].iter_mut().enumerate() {
+            *b = 0xC0 + i as u8;
+        }
+
+        let resp = ExtendedContentResponse::deserialize(bytes).unwrap();
+        assert_eq!(resp.response.sequence, 7);
+        assert_eq!(resp.response.status, CfuUpdateContentResponseStatus::ErrorCrc);
+        assert_eq!(resp.extension(), &bytes[16..]);
+
+        // a standard sized response carries no extension
+        let resp = ExtendedContentResponse::deserialize(standard).unwrap();
+        assert!(resp.extension().is_empty());
+        assert_eq!(
+            ExtendedContentResponse::deserialize([0u8; 8]),
+            Err(CfuProtocolError::BadResponse)
+        );
+        bytes[4] = 0x55;
+        assert_eq!(
+            ExtendedContentResponse::deserialize(bytes),
+            Err(CfuProtocolError::DeserializeField {
+                struct_name: "FwUpdateContentResponse",
+                field_name: "status",
+            })
+        );
+    }
+
+    #[test]
+    fn test_content_response_deserialize_many() {
+        let mut buf = [0u8; 3 * FwUpdateContentResponse::SERIALIZED_SIZE + 5];
+        for (frame, sequence) in buf
+            .chunks_mut(FwUpdateContentResponse::SERIALIZED_SIZE)
+            .zip([7u16, 8, 9])
+        {
+            let bytes: [u8; 16] = (&FwUpdateContentResponse::success(sequence)).into();
+            frame.copy_from_slice(&bytes);
+        }
+
+        let sequences = FwUpdateContentResponse::deserialize_many(&buf[..48]).map(|r| r.unwrap().sequence);
+        assert!(sequences.eq([7, 8, 9]));
+
+        // the trailing partial frame is reported, not dropped
+        let mut responses = FwUpdateContentResponse::deserialize_many(&buf);
+        assert!(responses.by_ref().take(3).all(|r| r.is_ok()));
+        assert_eq!(responses.next(), Some(Err(CfuProtocolError::BadResponse)));
+        assert_eq!(responses.next(), None);
+
+        // an unknown status is reported against the field
+        let mut bad_status = buf;
+        bad_status[20] = 0x55;
+        let mut responses = FwUpdateContentResponse::deserialize_many(&bad_status[..48]);
+        assert!(responses.next().unwrap().is_ok());
+        assert_eq!(
+            responses.next(),
+            Some(Err(CfuProtocolError::DeserializeField {
+                struct_name: "FwUpdateContentResponse",
+                field_name: "status",
+            }))
+        );
+    }
+
+    #[test]
+    fn test_status_is_retryable() {
+        use CfuUpdateContentResponseStatus::*;
+        for (status, retryable) in [
+            (Success, false),
+            (ErrorPrepare, true),
+            (ErrorWrite, true),
+            (ErrorComplete, false),
+            (ErrorVerify, false),
+            (ErrorCrc, false),
+            (ErrorSignature, false),
+            (ErrorVersion, false),
+            (SwapPending, false),
+            (ErrorInvalidAddr, false),
+            (ErrorNoOffer, false),
+            (ErrorInvalid, false),
+        ] {
+            assert_eq!(status.is_retryable(), retryable, "{status:?}");
+        }
+
+        for (status, retryable) in [
+            (OfferStatus::Skip, false),
+            (OfferStatus::Accept, false),
+            (OfferStatus::Reject, false),
+            (OfferStatus::Busy, true),
+            (OfferStatus::CommandReady, false),
+            (OfferStatus::CmdNotSupported, false),
+        ] {
+            assert_eq!(status.is_retryable(), retryable, "{status:?}");
+        }
+    }
+
+    #[test]
+    fn test_writer_error_conversion() {
+        fn write(result: Result<(), CfuWriterError>) -> Result<(), CfuProtocolError> {
+            result?;
+            Ok(())
+        }
+        assert_eq!(write(Ok(())), Ok(()));
+        assert_eq!(
+            write(Err(CfuWriterError::StorageError)),
+            Err(CfuProtocolError::WriterError(CfuWriterError::StorageError))
+        );
+        assert_eq!(
+            CfuProtocolError::from(CfuWriterError::Timeout),
+            CfuProtocolError::WriterError(CfuWriterError::Timeout)
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        extern crate std;
+        use std::string::ToString;
+
+        assert_eq!(
+            CfuProtocolError::CfuContentUpdateResponseError(CfuUpdateContentResponseStatus::ErrorCrc).to_string(),
+            "content rejected: CRC verification failed in response to last block"
+        );
+        assert_eq!(
+            CfuProtocolError::UnexpectedSequence { expected: 3, actual: 5 }.to_string(),
+            "expected ack for sequence 3, got 5"
+        );
+        assert_eq!(
+            CfuProtocolError::DeserializeField {
+                struct_name: "GetFwVersionResponseHeader",
+                field_name: "byte3",
+            }
+            .to_string(),
+            "invalid value in GetFwVersionResponseHeader.byte3"
+        );
+        assert_eq!(
+            CfuProtocolError::WriterError(CfuWriterError::Timeout).to_string(),
+            "writer error: timed out waiting on the bus"
+        );
+        assert_eq!(OfferStatus::Reject.to_string(), "offer rejected");
+    }
+
+    #[test]
+    fn test_fwversion_bump() {
+        let version = FwVersion::new(0x01020304);
+        assert_eq!(u32::from(version.bump_major(false)), 0x02020304);
+        assert_eq!(u32::from(version.bump_major(true)), 0x02000000);
+        assert_eq!(u32::from(version.bump_minor()), 0x01020404);
+        assert_eq!(u32::from(version.bump_variant()), 0x01020305);
+
+        // saturating fields stay at max instead of wrapping into the next field
+        let max = FwVersion::new(0xFFFFFFFF);
+        assert_eq!(max.bump_major(true), max);
+        assert_eq!(max.bump_minor(), max);
+        assert_eq!(max.bump_variant(), max);
+
+        // the variant is the least significant part of the version, and ignored on request
+        let build_a = FwVersion::new(0x01020304);
+        let build_b = FwVersion::new(0x01020307);
+        assert!(build_a < build_b);
+        assert_ne!(build_a.cmp(&build_b), Ordering::Equal);
+        assert_eq!(build_a.cmp_ignoring_variant(&build_b), Ordering::Equal);
+        assert_eq!(
+            build_b.cmp_ignoring_variant(&FwVersion::new(0x01030000)),
+            Ordering::Less
+        );
+        assert!(FwVersion::new(0x01FFFF00) < FwVersion::new(0x02000000));
+        assert_eq!(u32::from(FwVersion::new(0x01FFFF00).bump_minor()), 0x01FFFF00);
+    }
+
+    #[test]
+    fn test_content_command_deserialize_checked() {
+        let mut bytes = [0u8; FwUpdateContentCommand::SERIALIZED_SIZE];
+        bytes[1] = 40;
+        let cmd = FwUpdateContentCommand::deserialize_checked(&bytes).unwrap();
+        assert_eq!(cmd.payload().map(<[u8]>::len), Some(40));
+
+        // a data_length past the end of the data array is rejected rather than trusted
+        bytes[1] = 200;
+        assert_eq!(
+            FwUpdateContentCommand::deserialize_checked(&bytes),
+            Err(CfuProtocolError::DeserializeField {
+                struct_name: "FwUpdateContentHeader",
+                field_name: "data_length",
+            })
+        );
+        assert_eq!(FwUpdateContentCommand::try_from(&bytes).unwrap().payload(), None);
+        assert_eq!(
+            FwUpdateContentCommand::deserialize_checked(&bytes[..59]),
+            Err(CfuProtocolError::BadResponse)
+        );
+    }
+
+    #[test]
+    fn test_get_fw_version_response_builder() {
+        let mut builder = GetFwVersionResponseBuilder::new();
+        for id in 1..=3 {
+            builder
+                .add_component(FwVerComponentInfo::new(FwVersion::new(0x01000000 + id as u32), id))
+                .unwrap();
+        }
+        let response = builder.build();
+        assert_eq!(response.header.component_count, 3);
+        assert_eq!(response.header.byte3, GetFwVerRespHeaderByte3::NoSpecialFlags);
+        assert_eq!(response.component_info[2].component_id, 3);
+
+        // round trips through the wire format with the count intact
+        let bytes: [u8; GetFwVersionResponse::SERIALIZED_SIZE] = (&response).try_into().unwrap();
+        assert_eq!(bytes[3] >> 4, PROTOCOL_VER);
+        assert_eq!(GetFwVersionResponse::deserialize_checked(&bytes).unwrap(), response);
+
+        assert!(builder.extension(true).build().has_extension());
+        for id in 4..=7 {
+            builder
+                .add_component(FwVerComponentInfo::new(FwVersion::default(), id))
+                .unwrap();
+        }
+        assert_eq!(
+            builder.add_component(FwVerComponentInfo::default()).map(|_| ()),
+            Err(CfuProtocolError::InvalidComponentCount)
+        );
+        assert_eq!(builder.build().header.component_count as usize, MAX_CMPT_COUNT);
+    }
+
+    #[test]
+    fn test_fwversion_response_component_by_id() {
+        let mut builder = GetFwVersionResponseBuilder::new();
+        builder
+            .add_component(FwVerComponentInfo::new(FwVersion::new(0x01000000), 1))
+            .unwrap()
+            .add_component(FwVerComponentInfo::new(FwVersion::new(0x02000000), 2))
+            .unwrap();
+        let mut response = builder.build();
+        // stale entry past component_count
+        response.component_info[2] = FwVerComponentInfo::new(FwVersion::new(0x03000000), 3);
+
+        assert_eq!(response.component_by_id(2).map(|c| c.fw_version.major), Some(2));
+        assert_eq!(response.component_by_id(3), None);
+        assert_eq!(response.component_by_id(9), None);
+    }
+
+    #[test]
+    fn test_fwupdate_content_response_helpers() {
+        let response = FwUpdateContentResponse::crc_error(7);
+        assert_eq!(response.status, CfuUpdateContentResponseStatus::ErrorCrc);
+        assert_eq!(response.sequence, 7);
+
+        let bytes: [u8; 16] = (&FwUpdateContentResponse::success(0x0102)).into();
+        assert_eq!(bytes, [0x02, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            FwUpdateContentResponse::error(3, CfuUpdateContentResponseStatus::ErrorWrite),
+            FwUpdateContentResponse::write_error(3)
+        );
+        assert_eq!(
+            FwUpdateContentResponse::swap_pending(9).status,
+            CfuUpdateContentResponseStatus::SwapPending
+        );
+    }
+
+    #[test]
+    fn test_fwupdate_offer_for_component() {
+        let current = FwVersion::new(0x01020300);
+        let target = FwVersion::new(0x01030000);
+        let mut component_info = [FwVerComponentInfo::default(); MAX_CMPT_COUNT];
+        component_info[0] = FwVerComponentInfo::new(current, 1);
+        component_info[1] = FwVerComponentInfo::new(current, 7);
+        let response = GetFwVersionResponse {
+            header: GetFwVersionResponseHeader::new(2, GetFwVerRespHeaderByte3::NoSpecialFlags),
+            component_info,
+        };
+
+        let offer = FwUpdateOffer::for_component(&response.component_info[1], target, HostToken::Tool);
+
+        assert_eq!(offer.component_info.component_id, 7);
+        assert_eq!(offer.component_info.token, HostToken::Tool);
+        assert_eq!(offer.firmware_version, target);
+        assert_eq!(offer.protocol_version(), PROTOCOL_VER);
+    }
+
+    #[test]
+    fn test_fw_ver_component_info_packed_accessors() {
+        let info = FwVerComponentInfo::try_new_with_vendor_specific_info(
+            FwVersion::default(),
+            3,
+            BankType::VendorSpecific(2),
+            0xA,
+            0x1234,
+        )
+        .unwrap();
+        assert_eq!(info.bank(), BankType::VendorSpecific(2));
+        assert_eq!(info.vendor_bits(), 0xA);
+        assert_eq!(info.component_id, 3);
+
+        // Out of range values must be rejected rather than truncated into adjacent bits
+        assert_eq!(
+            FwVerComponentInfo::try_new_with_vendor_specific_info(
+                FwVersion::default(),
+                3,
+                BankType::VendorSpecific(0),
+                0x10,
+                0
+            ),
+            Err(ConversionError::ValueOutOfRange)
+        );
+        assert_eq!(
+            FwVerComponentInfo::try_new_with_vendor_specific_info(
+                FwVersion::default(),
+                3,
+                BankType::VendorSpecific(4),
+                0,
+                0
+            ),
+            Err(ConversionError::ValueOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_offer_response_outcome() {
+        let resp =
+            FwUpdateOfferResponse::new_with_failure(HostToken::Driver, OfferRejectReason::OldFw, OfferStatus::Reject);
+        assert_eq!(resp.outcome(), OfferOutcome::Rejected(OfferRejectReason::OldFw));
+
+        // the reject reason is ignored unless the offer was rejected
+        let resp = FwUpdateOfferResponse::new_accept(HostToken::Driver);
+        assert_eq!(resp.outcome(), OfferOutcome::Accepted);
+        let resp =
+            FwUpdateOfferResponse::new_with_failure(HostToken::Driver, OfferRejectReason::OldFw, OfferStatus::Busy);
+        assert_eq!(resp.outcome(), OfferOutcome::Busy);
+    }
+
+    #[test]
+    fn test_fwversion_response_deserialize_checked() {
+        let component_info = [FwVerComponentInfo::new(FwVersion::new(0x01020304), 1); MAX_CMPT_COUNT];
+        let response = GetFwVersionResponse {
+            header: GetFwVersionResponseHeader::new(2, GetFwVerRespHeaderByte3::NoSpecialFlags),
+            component_info,
+        };
+        let bytes: [u8; GetFwVersionResponse::SERIALIZED_SIZE] = (&response).try_into().unwrap();
+
+        // a response only as long as the components it declares
+        let decoded = GetFwVersionResponse::deserialize_checked(&bytes[..20]).unwrap();
+        assert_eq!(decoded.header.component_count, 2);
+        assert_eq!(decoded.component_info[..2], component_info[..2]);
+        assert_eq!(
+            GetFwVersionResponse::deserialize_checked(&bytes[..19]),
+            Err(CfuProtocolError::BadResponse)
+        );
+
+        let mut too_many = bytes;
+        too_many[0] = 9;
+        assert_eq!(
+            GetFwVersionResponse::deserialize_checked(&too_many),
+            Err(CfuProtocolError::DeserializeField {
+                struct_name: "GetFwVersionResponseHeader",
+                field_name: "component_count",
+            })
+        );
+
+        // the error names the field that couldn't be decoded
+        let mut bad_version = bytes;
+        bad_version[3] = 0x30;
+        assert_eq!(
+            GetFwVersionResponse::deserialize_checked(&bad_version),
+            Err(CfuProtocolError::DeserializeField {
+                struct_name: "GetFwVersionResponseHeader",
+                field_name: "byte3",
+            })
+        );
+    }
+
+    #[test]
+    fn test_fwversion_response_fragments() {
+        extern crate std;
+        use std::vec::Vec;
+
+        let mut builder = GetFwVersionResponseBuilder::new();
+        for id in 1..=MAX_CMPT_COUNT as u8 {
+            builder
+                .add_component(FwVerComponentInfo::new(FwVersion::new(0x01020300 | id as u32), id))
+                .unwrap();
+        }
+        let response = builder.build();
+
+        // the header shares a fragment with the first entry, then two entries fit in each fragment
+        let fragments: Vec<FwVersionFragment> = response.fragments(16).unwrap().collect();
+        let lens: Vec<usize> = fragments.iter().map(|f| f.len()).collect();
+        assert_eq!(lens, [12, 16, 16, 16]);
+
+        let mut reassembler = FwVersionReassembler::new();
+        for fragment in &fragments[..3] {
+            assert_eq!(reassembler.push(fragment), Ok(None));
+        }
+        assert_eq!(reassembler.push(&fragments[3]), Ok(Some(response)));
+        // nothing may follow the last byte the header declares
+        assert_eq!(reassembler.push(&[0]), Err(CfuProtocolError::BadResponse));
+
+        // entries are only split when they can't fit a fragment on their own
+        let lens: Vec<usize> = response.fragments(6).unwrap().map(|f| f.len()).collect();
+        assert_eq!(lens[..3], [4, 6, 2]);
+        let mut reassembler = FwVersionReassembler::new();
+        let rebuilt = response
+            .fragments(6)
+            .unwrap()
+            .map(|f| reassembler.push(&f).unwrap())
+            .last();
+        assert_eq!(rebuilt, Some(Some(response)));
+
+        // a response with fewer components only sends the ones it reports
+        let short = GetFwVersionResponseBuilder::new()
+            .add_component(FwVerComponentInfo::new(FwVersion::new(0x01000000), 1))
+            .unwrap()
+            .build();
+        assert_eq!(short.fragments(64).unwrap().map(|f| f.len()).collect::<Vec<_>>(), [12]);
+        assert_eq!(short.fragments(0).err(), Some(ConversionError::ValueOutOfRange));
+    }
+
+    #[test]
+    fn test_fwversion_response_extension() {
+        let mut response = GetFwVersionResponse {
+            header: GetFwVersionResponseHeader::new(2, GetFwVerRespHeaderByte3::NoSpecialFlags),
+            component_info: [FwVerComponentInfo::default(); MAX_CMPT_COUNT],
+        };
+        let extension_bytes = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        assert!(!response.has_extension());
+        assert_eq!(response.parse_extension(&extension_bytes), Ok(None));
+
+        response.header.byte3 = GetFwVerRespHeaderByte3::ExtensionFlagSet;
+        assert!(response.has_extension());
+        let extensions = response.parse_extension(&extension_bytes).unwrap().unwrap();
+        assert_eq!(extensions[0].properties, [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(extensions[1].properties, [9, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(extensions[2], FwVerComponentExtension::default());
+        assert_eq!(
+            response.parse_extension(&extension_bytes[..12]),
+            Err(CfuProtocolError::BadResponse)
+        );
+    }
 }
@@ -0,0 +1,191 @@
+//! This module records the frames a Cfu Host sends into a flat, replayable byte stream.
+//!
+//! Each frame is stored as a little-endian u16 length followed by the frame bytes, in the order the frames were sent.
+//! The resulting stream can be saved and later loaded to drive a component without a real host.
+
+use crate::protocol_definitions::ConversionError;
+use crate::writer::{CfuWriterAsync, CfuWriterError};
+
+/// Size of the length prefix written before every frame
+pub const SCRIPT_FRAME_HEADER_LEN: usize = 2;
+
+/// Flat, length-framed capture of host-to-device frames, stored in a caller-provided buffer
+pub struct SessionScript<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> SessionScript<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// Appends a frame to the script
+    /// Fails without modifying the script if the frame doesn't fit in the remaining buffer
+    pub fn record(&mut self, frame: &[u8]) -> Result<(), CfuWriterError> {
+        let frame_len = u16::try_from(frame.len()).map_err(|_| CfuWriterError::ByteConversionError)?;
+        let end = self.len + SCRIPT_FRAME_HEADER_LEN + frame.len();
+        let dest = self.buf.get_mut(self.len..end).ok_or(CfuWriterError::Other)?;
+        let (header, body) = dest.split_at_mut(SCRIPT_FRAME_HEADER_LEN);
+        header.copy_from_slice(&frame_len.to_le_bytes());
+        body.copy_from_slice(frame);
+        self.len = end;
+        Ok(())
+    }
+
+    /// The recorded byte stream
+    pub fn as_bytes(&self) -> &[u8] {
+        self.buf.get(..self.len).unwrap_or_default()
+    }
+
+    /// Iterates over the frames recorded so far
+    pub fn frames(&self) -> ScriptFrames<'_> {
+        ScriptFrames::load(self.as_bytes())
+    }
+}
+
+/// Loader that iterates over the frames in a recorded byte stream
+#[derive(Copy, Clone, Debug)]
+pub struct ScriptFrames<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ScriptFrames<'a> {
+    pub fn load(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+}
+
+impl<'a> Iterator for ScriptFrames<'a> {
+    type Item = Result<&'a [u8], ConversionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let frame = self
+            .bytes
+            .split_first_chunk::<SCRIPT_FRAME_HEADER_LEN>()
+            .and_then(|(header, rest)| {
+                let len = u16::from_le_bytes(*header) as usize;
+                rest.get(..len).zip(rest.get(len..))
+            });
+        match frame {
+            Some((frame, rest)) => {
+                self.bytes = rest;
+                Some(Ok(frame))
+            }
+            None => {
+                // truncated stream, stop after reporting it
+                self.bytes = &[];
+                Some(Err(ConversionError::ByteConversionError))
+            }
+        }
+    }
+}
+
+/// CfuWriterAsync decorator that records every frame sent to the component before forwarding it to the inner writer
+pub struct ScriptRecorder<'a, W> {
+    inner: W,
+    script: SessionScript<'a>,
+}
+
+impl<'a, W: CfuWriterAsync> ScriptRecorder<'a, W> {
+    pub fn new(inner: W, buf: &'a mut [u8]) -> Self {
+        Self {
+            inner,
+            script: SessionScript::new(buf),
+        }
+    }
+
+    /// The script recorded so far
+    pub fn script(&self) -> &SessionScript<'a> {
+        &self.script
+    }
+
+    /// Consumes the recorder, returning the inner writer and the recorded script
+    pub fn into_parts(self) -> (W, SessionScript<'a>) {
+        (self.inner, self.script)
+    }
+}
+
+impl<W: CfuWriterAsync> CfuWriterAsync for ScriptRecorder<'_, W> {
+    async fn cfu_write_read(
+        &mut self,
+        mem_offset: Option<usize>,
+        data: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), CfuWriterError> {
+        self.script.record(data)?;
+        self.inner.cfu_write_read(mem_offset, data, read).await
+    }
+
+    async fn cfu_read(&mut self, mem_offset: Option<usize>, read: &mut [u8]) -> Result<(), CfuWriterError> {
+        self.inner.cfu_read(mem_offset, read).await
+    }
+
+    async fn cfu_write(&mut self, mem_offset: Option<usize>, data: &[u8]) -> Result<(), CfuWriterError> {
+        self.script.record(data)?;
+        self.inner.cfu_write(mem_offset, data).await
+    }
+
+    async fn cfu_storage(&mut self, mem_offset: usize, data: &[u8]) -> Result<(), CfuWriterError> {
+        self.inner.cfu_storage(mem_offset, data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::host::{CfuUpdateContent, CfuUpdater, NoHooks};
+    use crate::protocol_definitions::{FwUpdateOffer, FwVersion, HostToken, DEFAULT_DATA_LENGTH};
+    use crate::writer::CfuWriterNop;
+
+    #[test]
+    fn test_session_script_round_trip() {
+        let mut buf = [0u8; 256];
+        let mut recorder = ScriptRecorder::new(CfuWriterNop, &mut buf);
+        let mut updater = CfuUpdater::new();
+
+        let offer = FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::new(0x01000000), 0, 0);
+        block_on(updater.start_transaction_with_hooks(&mut recorder, &mut NoHooks)).unwrap();
+        block_on(updater.notify_start_offer_list_with_hooks(&mut recorder, &mut NoHooks)).unwrap();
+        block_on(updater.send_offer(&mut recorder, &offer, &mut NoHooks)).unwrap();
+        block_on(updater.process_first_data_block(&mut recorder, [0x5A; DEFAULT_DATA_LENGTH], 0)).unwrap();
+        block_on(updater.notify_end_offer_list_with_hooks(&mut recorder, &mut NoHooks)).unwrap();
+
+        let (_, script) = recorder.into_parts();
+        assert_eq!(
+            script.as_bytes().len(),
+            5 * SCRIPT_FRAME_HEADER_LEN + 16 + 16 + 16 + 60 + 16
+        );
+
+        // reload from the raw bytes, as a firmware test rig would
+        let frames: Vec<&[u8]> = ScriptFrames::load(script.as_bytes()).map(Result::unwrap).collect();
+        let lengths: Vec<usize> = frames.iter().map(|f| f.len()).collect();
+        assert_eq!(lengths, std::vec![16, 16, 16, 60, 16]);
+        let offer_bytes: [u8; 16] = (&offer).into();
+        assert_eq!(frames[2], offer_bytes.as_slice());
+        assert_eq!(&frames[3][8..], &[0x5A; DEFAULT_DATA_LENGTH]);
+        assert_eq!(frames, script.frames().map(Result::unwrap).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_session_script_limits() {
+        let mut buf = [0u8; 8];
+        let mut script = SessionScript::new(&mut buf);
+        script.record(&[1, 2, 3]).unwrap();
+        assert_eq!(script.record(&[4, 5, 6, 7]), Err(CfuWriterError::Other));
+        assert_eq!(script.frames().count(), 1);
+
+        // a truncated stream reports an error instead of a short frame
+        let mut frames = ScriptFrames::load(&[3, 0, 1, 2]);
+        assert_eq!(frames.next(), Some(Err(ConversionError::ByteConversionError)));
+        assert_eq!(frames.next(), None);
+    }
+}
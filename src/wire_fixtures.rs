@@ -0,0 +1,199 @@
+//! Canonical CFU frames for pinning the wire layout of the protocol definitions.
+//!
+//! Each fixture is a complete frame as it appears on the wire. The serialization tests check the protocol
+//! definitions against these bytes in both directions, so an endianness or field order regression fails them.
+//! They are public so crates implementing a Host or a component can check their own framing against the same bytes.
+
+/// GetFwVersion response from the primary component: one component, id 0x01, version 1.2.3
+#[rustfmt::skip]
+pub const FW_VERSION_RESPONSE: [u8; 12] = [
+    0x01, 0x00, 0x00, 0x20, // component count, reserved, protocol version 2
+    0x00, 0x01, 0x00, 0x00, // bank, component id, vendor specific
+    0x01, 0x02, 0x00, 0x03, // major, minor, variant
+];
+
+/// Offer information: start entire transaction, sent by the driver
+#[rustfmt::skip]
+pub const OFFER_INFO_START_TRANSACTION: [u8; 16] = [
+    0x00, 0x00, 0xFF, 0xA0, // code, reserved, info component id, driver token
+    0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+/// Offer: version 1.2.3 for component 0x01, sent by the driver with protocol version 2
+#[rustfmt::skip]
+pub const OFFER_COMMAND: [u8; 16] = [
+    0x00, 0x00, 0x01, 0xA0, // segment, flags, component id, driver token
+    0x03, 0x02, 0x00, 0x01, // variant, minor, major
+    0x00, 0x00, 0x00, 0x00, // vendor specific
+    0x02, 0x00, 0x00, 0x00, // protocol version 2, misc
+];
+
+/// Offer response: offer accepted by the component, for the driver
+#[rustfmt::skip]
+pub const OFFER_RESPONSE_ACCEPT: [u8; 16] = [
+    0x00, 0x00, 0x00, 0xA0, // reserved, driver token
+    0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, // reject reason (unused), reserved
+    0x01, 0x00, 0x00, 0x00, // status accept, reserved
+];
+
+/// Offer response: offer rejected by the component because its firmware is not older, for the driver
+#[rustfmt::skip]
+pub const OFFER_RESPONSE_REJECT_OLD_FW: [u8; 16] = [
+    0x00, 0x00, 0x00, 0xA0, // reserved, driver token
+    0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, // reject reason old fw, reserved
+    0x02, 0x00, 0x00, 0x00, // status reject, reserved
+];
+
+/// Content command: first block, sequence 0, carrying the bytes 0x00..=0x33
+#[rustfmt::skip]
+pub const CONTENT_COMMAND_FIRST_BLOCK: [u8; 60] = [
+    0x80, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // flags (first block), data_length, sequence, address
+    0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07,
+    0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+    0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0x17,
+    0x18, 0x19, 0x1A, 0x1B, 0x1C, 0x1D, 0x1E, 0x1F,
+    0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27,
+    0x28, 0x29, 0x2A, 0x2B, 0x2C, 0x2D, 0x2E, 0x2F,
+    0x30, 0x31, 0x32, 0x33,
+];
+
+/// Content response: block 0x0102 written successfully
+#[rustfmt::skip]
+pub const CONTENT_RESPONSE_SUCCESS: [u8; 16] = [
+    0x02, 0x01, 0x00, 0x00, // sequence, reserved
+    0x00, 0x00, 0x00, 0x00, // status success, reserved
+    0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol_definitions::*;
+
+    #[test]
+    fn test_fw_version_response_fixture() {
+        let mut component_info = [FwVerComponentInfo::default(); MAX_CMPT_COUNT];
+        component_info[0] = FwVerComponentInfo::new(FwVersion::new(0x01000203), 1);
+        let response = GetFwVersionResponse {
+            header: GetFwVersionResponseHeader::new(1, GetFwVerRespHeaderByte3::NoSpecialFlags),
+            component_info,
+        };
+        let bytes: [u8; GetFwVersionResponse::SERIALIZED_SIZE] = (&response).try_into().unwrap();
+        assert_eq!(bytes[..FW_VERSION_RESPONSE.len()], FW_VERSION_RESPONSE);
+        assert_eq!(
+            GetFwVersionResponse::deserialize_checked(&FW_VERSION_RESPONSE),
+            Ok(response)
+        );
+    }
+
+    #[test]
+    fn test_fw_version_byte_order() {
+        // every field distinct, and a minor wide enough that swapping its bytes shows
+        let version = FwVersion {
+            major: 0x01,
+            minor: 0x0203,
+            variant: 0x04,
+        };
+        assert_eq!(u32::from(version), 0x01020304);
+        assert_eq!(FwVersion::new(0x01020304), version);
+
+        // an offer carries the version as a little-endian dword
+        let offer = FwUpdateOffer::new(HostToken::Driver, 1, version, 0, 0);
+        let bytes = <[u8; 16]>::from(&offer);
+        assert_eq!(bytes[4..8], [0x04, 0x03, 0x02, 0x01]);
+        assert_eq!(FwUpdateOffer::try_from(&bytes).unwrap().firmware_version, version);
+
+        // a GetFwVersion component entry carries it in its high 4 bytes, major first and minor little-endian
+        let mut component_info = [FwVerComponentInfo::default(); MAX_CMPT_COUNT];
+        component_info[0] =
+            FwVerComponentInfo::new_with_vendor_specific_info(version, 0x05, BankType::VendorSpecific(1), 0x0, 0x0706);
+        let response = GetFwVersionResponse {
+            header: GetFwVersionResponseHeader::new(1, GetFwVerRespHeaderByte3::NoSpecialFlags),
+            component_info,
+        };
+        let bytes: [u8; GetFwVersionResponse::SERIALIZED_SIZE] = (&response).try_into().unwrap();
+        assert_eq!(bytes[4..12], [0x01, 0x05, 0x06, 0x07, 0x01, 0x03, 0x02, 0x04]);
+        assert_eq!(
+            GetFwVersionResponse::deserialize_checked(&bytes[..12])
+                .unwrap()
+                .component_info[0]
+                .fw_version,
+            version
+        );
+    }
+
+    #[test]
+    fn test_offer_info_fixture() {
+        let info = FwUpdateOfferInformation::new(OfferInformationComponentInfo::new(
+            HostToken::Driver,
+            SpecialComponentIds::Info,
+            OfferInformationCodeValues::StartEntireTransaction,
+        ));
+        assert_eq!(<[u8; 16]>::from(&info), OFFER_INFO_START_TRANSACTION);
+        assert_eq!(
+            FwUpdateOfferInformation::try_from(&OFFER_INFO_START_TRANSACTION),
+            Ok(info)
+        );
+    }
+
+    #[test]
+    fn test_offer_command_fixture() {
+        let offer = FwUpdateOffer::new(HostToken::Driver, 1, FwVersion::new(0x01000203), 0, 2);
+        assert_eq!(<[u8; 16]>::from(&offer), OFFER_COMMAND);
+        assert_eq!(FwUpdateOffer::try_from(&OFFER_COMMAND), Ok(offer));
+        assert_eq!(offer.protocol_version(), 2);
+    }
+
+    #[test]
+    fn test_offer_response_fixtures() {
+        let reject =
+            FwUpdateOfferResponse::new_with_failure(HostToken::Driver, OfferRejectReason::OldFw, OfferStatus::Reject);
+        assert_eq!(<[u8; 16]>::from(&reject), OFFER_RESPONSE_REJECT_OLD_FW);
+        assert_eq!(
+            FwUpdateOfferResponse::try_from(OFFER_RESPONSE_REJECT_OLD_FW),
+            Ok(reject)
+        );
+
+        // the reject reason of an accepted offer is unused, so only the token and status are compared
+        let accept = FwUpdateOfferResponse::try_from(OFFER_RESPONSE_ACCEPT).unwrap();
+        assert_eq!(accept.token, HostToken::Driver);
+        assert_eq!(accept.status, OfferStatus::Accept);
+        let bytes: [u8; 16] = (&FwUpdateOfferResponse::new_accept(HostToken::Driver)).into();
+        assert_eq!(bytes[..8], OFFER_RESPONSE_ACCEPT[..8]);
+        assert_eq!(bytes[9..], OFFER_RESPONSE_ACCEPT[9..]);
+    }
+
+    #[test]
+    fn test_content_fixtures() {
+        let mut data = [0u8; DEFAULT_DATA_LENGTH];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let command = FwUpdateContentCommand {
+            header: FwUpdateContentHeader {
+                flags: FW_UPDATE_FLAG_FIRST_BLOCK,
+                data_length: DEFAULT_DATA_LENGTH as u8,
+                sequence_num: 0,
+                firmware_address: 0,
+            },
+            data,
+        };
+        assert_eq!(<[u8; 60]>::from(&command), CONTENT_COMMAND_FIRST_BLOCK);
+        assert_eq!(
+            FwUpdateContentCommand::try_from(&CONTENT_COMMAND_FIRST_BLOCK),
+            Ok(command)
+        );
+
+        let response = FwUpdateContentResponse::new(0x0102, CfuUpdateContentResponseStatus::Success);
+        assert_eq!(<[u8; 16]>::from(&response), CONTENT_RESPONSE_SUCCESS);
+        assert_eq!(
+            FwUpdateContentResponse::try_from(CONTENT_RESPONSE_SUCCESS),
+            Ok(response)
+        );
+    }
+}
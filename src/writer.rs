@@ -2,14 +2,41 @@
 
 use core::future::Future;
 
+#[cfg(feature = "embedded-hal-async")]
+use embedded_hal_async::i2c::{AddressMode, I2c, Operation};
+#[cfg(feature = "embedded-hal-async")]
+use embedded_hal_async::spi::{Operation as SpiOperation, SpiDevice};
+
+use crate::trace;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Ord, PartialOrd, Hash)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum CfuWriterError {
+    /// Programming the component's flash failed
     StorageError,
     ByteConversionError,
+    /// The driver gave up waiting on the bus, the transaction can be retried
+    Timeout,
+    /// The bus transaction failed, it can be retried
+    TransportError,
+    /// The image being sent could not be read, retrying won't help
+    ImageReadError,
     Other,
 }
 
+impl core::fmt::Display for CfuWriterError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(match self {
+            CfuWriterError::StorageError => "storage access failed",
+            CfuWriterError::ByteConversionError => "bytes could not be converted",
+            CfuWriterError::Timeout => "timed out waiting on the bus",
+            CfuWriterError::TransportError => "bus transaction failed",
+            CfuWriterError::ImageReadError => "image could not be read",
+            CfuWriterError::Other => "writer failed",
+        })
+    }
+}
+
 /// Trait to define R/W behavior for driver that can talk to a CFU component or client
 pub trait CfuWriterAsync {
     /// writes a chunk of data to a component and reads back to another buffer
@@ -33,6 +60,30 @@ pub trait CfuWriterAsync {
 
     /// Manages erasing sectors and writing pages into flash based on the CFU offset
     fn cfu_storage(&mut self, mem_offset: usize, data: &[u8]) -> impl Future<Output = Result<(), CfuWriterError>>;
+
+    /// Same as cfu_write_read, but first checks that `data` is a whole frame of `frame_len` bytes, e.g.
+    /// FwUpdateContentCommand::SERIALIZED_SIZE for a content command
+    /// Catches an offer and a content buffer transposed during bring-up before anything reaches the bus
+    /// Returns ByteConversionError on a mismatch
+    fn cfu_write_read_frame(
+        &mut self,
+        mem_offset: Option<usize>,
+        data: &[u8],
+        frame_len: usize,
+        read: &mut [u8],
+    ) -> impl Future<Output = Result<(), CfuWriterError>> {
+        async move {
+            if data.len() != frame_len {
+                trace!(
+                    "{} byte frame where a {} byte frame was expected",
+                    data.len(),
+                    frame_len
+                );
+                return Err(CfuWriterError::ByteConversionError);
+            }
+            self.cfu_write_read(mem_offset, data, read).await
+        }
+    }
 }
 
 /// Trait to define R/W behavior for driver that can talk to a CFU component or client
@@ -92,3 +143,663 @@ impl CfuWriterSync for CfuWriterNop {
         Ok(())
     }
 }
+
+/// Number of leading bytes of each frame included in the traces emitted by LoggingWriter
+#[cfg(feature = "defmt")]
+pub const LOGGING_WRITER_PREVIEW_LEN: usize = 8;
+
+/// CfuWriterAsync decorator that traces every transaction before forwarding it to the inner writer
+/// Results from the inner writer are returned unchanged, failures are traced along with the error
+#[cfg(feature = "defmt")]
+pub struct LoggingWriter<W> {
+    inner: W,
+}
+
+#[cfg(feature = "defmt")]
+impl<W: CfuWriterAsync> LoggingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Consumes the decorator, returning the inner writer
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(feature = "defmt")]
+fn preview(data: &[u8]) -> &[u8] {
+    data.get(..LOGGING_WRITER_PREVIEW_LEN).unwrap_or(data)
+}
+
+#[cfg(feature = "defmt")]
+impl<W: CfuWriterAsync> CfuWriterAsync for LoggingWriter<W> {
+    async fn cfu_write_read(
+        &mut self,
+        mem_offset: Option<usize>,
+        data: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), CfuWriterError> {
+        trace!(
+            "cfu_write_read offset {} len {} read len {} data {=[u8]:#x}",
+            mem_offset,
+            data.len(),
+            read.len(),
+            preview(data)
+        );
+        self.inner
+            .cfu_write_read(mem_offset, data, read)
+            .await
+            .inspect(|_| trace!("cfu_write_read response {=[u8]:#x}", preview(read)))
+            .inspect_err(|e| trace!("cfu_write_read failed: {}", e))
+    }
+
+    async fn cfu_read(&mut self, mem_offset: Option<usize>, read: &mut [u8]) -> Result<(), CfuWriterError> {
+        trace!("cfu_read offset {} len {}", mem_offset, read.len());
+        self.inner
+            .cfu_read(mem_offset, read)
+            .await
+            .inspect(|_| trace!("cfu_read data {=[u8]:#x}", preview(read)))
+            .inspect_err(|e| trace!("cfu_read failed: {}", e))
+    }
+
+    async fn cfu_write(&mut self, mem_offset: Option<usize>, data: &[u8]) -> Result<(), CfuWriterError> {
+        trace!(
+            "cfu_write offset {} len {} data {=[u8]:#x}",
+            mem_offset,
+            data.len(),
+            preview(data)
+        );
+        self.inner
+            .cfu_write(mem_offset, data)
+            .await
+            .inspect_err(|e| trace!("cfu_write failed: {}", e))
+    }
+
+    async fn cfu_storage(&mut self, mem_offset: usize, data: &[u8]) -> Result<(), CfuWriterError> {
+        trace!("cfu_storage offset {} len {}", mem_offset, data.len());
+        self.inner
+            .cfu_storage(mem_offset, data)
+            .await
+            .inspect_err(|e| trace!("cfu_storage failed: {}", e))
+    }
+}
+
+/// Width of the register address an I2cWriter sends ahead of the data
+#[cfg(feature = "embedded-hal-async")]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum I2cRegisterWidth {
+    #[default]
+    OneByte,
+    /// Sent most significant byte first
+    TwoBytes,
+}
+
+/// CfuWriterAsync over an I2C bus, for a component that is an I2C device at a fixed address
+/// `cfu_write_read` becomes a write then read and `cfu_write` a write. A `mem_offset` is sent as a register address
+/// ahead of the data, in the same transaction; `cfu_storage` writes to its offset the same way
+/// Bus failures are reported as TransportError, and an offset too wide for the register address as
+/// ByteConversionError
+#[cfg(feature = "embedded-hal-async")]
+pub struct I2cWriter<I, A> {
+    i2c: I,
+    address: A,
+    register_width: I2cRegisterWidth,
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl<I: I2c<A>, A: AddressMode + Copy> I2cWriter<I, A> {
+    /// Writer with one byte register addresses
+    pub fn new(i2c: I, address: A) -> Self {
+        Self::with_register_width(i2c, address, I2cRegisterWidth::OneByte)
+    }
+
+    pub fn with_register_width(i2c: I, address: A, register_width: I2cRegisterWidth) -> Self {
+        Self {
+            i2c,
+            address,
+            register_width,
+        }
+    }
+
+    /// Consumes the writer, returning the bus
+    pub fn into_inner(self) -> I {
+        self.i2c
+    }
+
+    /// Encodes an offset as a register address
+    fn register(&self, mem_offset: usize) -> Result<heapless::Vec<u8, 2>, CfuWriterError> {
+        let mut register = heapless::Vec::new();
+        let encoded = match self.register_width {
+            I2cRegisterWidth::OneByte => u8::try_from(mem_offset).ok().and_then(|r| register.push(r).ok()),
+            I2cRegisterWidth::TwoBytes => u16::try_from(mem_offset)
+                .ok()
+                .and_then(|r| register.extend_from_slice(&r.to_be_bytes()).ok()),
+        };
+        encoded.ok_or(CfuWriterError::ByteConversionError)?;
+        Ok(register)
+    }
+
+    async fn transaction(&mut self, operations: &mut [Operation<'_>]) -> Result<(), CfuWriterError> {
+        self.i2c.transaction(self.address, operations).await.map_err(|_| {
+            trace!("i2c transaction with component failed");
+            CfuWriterError::TransportError
+        })
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl<I: I2c<A>, A: AddressMode + Copy> CfuWriterAsync for I2cWriter<I, A> {
+    async fn cfu_write_read(
+        &mut self,
+        mem_offset: Option<usize>,
+        data: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), CfuWriterError> {
+        match mem_offset {
+            Some(offset) => {
+                let register = self.register(offset)?;
+                self.transaction(&mut [
+                    Operation::Write(&register),
+                    Operation::Write(data),
+                    Operation::Read(read),
+                ])
+                .await
+            }
+            None => {
+                self.transaction(&mut [Operation::Write(data), Operation::Read(read)])
+                    .await
+            }
+        }
+    }
+
+    async fn cfu_read(&mut self, mem_offset: Option<usize>, read: &mut [u8]) -> Result<(), CfuWriterError> {
+        match mem_offset {
+            Some(offset) => {
+                let register = self.register(offset)?;
+                self.transaction(&mut [Operation::Write(&register), Operation::Read(read)])
+                    .await
+            }
+            None => self.transaction(&mut [Operation::Read(read)]).await,
+        }
+    }
+
+    async fn cfu_write(&mut self, mem_offset: Option<usize>, data: &[u8]) -> Result<(), CfuWriterError> {
+        match mem_offset {
+            Some(offset) => {
+                let register = self.register(offset)?;
+                self.transaction(&mut [Operation::Write(&register), Operation::Write(data)])
+                    .await
+            }
+            None => self.transaction(&mut [Operation::Write(data)]).await,
+        }
+    }
+
+    async fn cfu_storage(&mut self, mem_offset: usize, data: &[u8]) -> Result<(), CfuWriterError> {
+        self.cfu_write(Some(mem_offset), data).await
+    }
+}
+
+/// Longest command/address an SpiWriter sends ahead of the data
+#[cfg(feature = "embedded-hal-async")]
+pub const SPI_ADDRESS_MAX_LEN: usize = 4;
+
+/// Encodes a `mem_offset` as the command/address bytes an SpiWriter sends ahead of the data
+/// Returns ByteConversionError if the offset can't be encoded
+#[cfg(feature = "embedded-hal-async")]
+pub type SpiAddressEncoder = fn(usize) -> Result<heapless::Vec<u8, SPI_ADDRESS_MAX_LEN>, CfuWriterError>;
+
+/// CfuWriterAsync over an SPI device, for a component that sits on SPI
+/// `cfu_write_read` becomes a full duplex transfer, `cfu_write` and `cfu_read` half duplex writes and reads.
+/// A `mem_offset` is encoded with the encoder given at construction and sent ahead of the data, in the same
+/// transaction; `cfu_storage` writes to its offset the same way
+/// Bus failures are reported as TransportError
+#[cfg(feature = "embedded-hal-async")]
+pub struct SpiWriter<S> {
+    spi: S,
+    encode_address: SpiAddressEncoder,
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl<S: SpiDevice> SpiWriter<S> {
+    pub fn new(spi: S, encode_address: SpiAddressEncoder) -> Self {
+        Self { spi, encode_address }
+    }
+
+    /// Consumes the writer, returning the device
+    pub fn into_inner(self) -> S {
+        self.spi
+    }
+
+    async fn transaction(&mut self, operations: &mut [SpiOperation<'_, u8>]) -> Result<(), CfuWriterError> {
+        self.spi.transaction(operations).await.map_err(|_| {
+            trace!("spi transaction with component failed");
+            CfuWriterError::TransportError
+        })
+    }
+}
+
+#[cfg(feature = "embedded-hal-async")]
+impl<S: SpiDevice> CfuWriterAsync for SpiWriter<S> {
+    async fn cfu_write_read(
+        &mut self,
+        mem_offset: Option<usize>,
+        data: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), CfuWriterError> {
+        match mem_offset {
+            Some(offset) => {
+                let address = (self.encode_address)(offset)?;
+                self.transaction(&mut [SpiOperation::Write(&address), SpiOperation::Transfer(read, data)])
+                    .await
+            }
+            None => self.transaction(&mut [SpiOperation::Transfer(read, data)]).await,
+        }
+    }
+
+    async fn cfu_read(&mut self, mem_offset: Option<usize>, read: &mut [u8]) -> Result<(), CfuWriterError> {
+        match mem_offset {
+            Some(offset) => {
+                let address = (self.encode_address)(offset)?;
+                self.transaction(&mut [SpiOperation::Write(&address), SpiOperation::Read(read)])
+                    .await
+            }
+            None => self.transaction(&mut [SpiOperation::Read(read)]).await,
+        }
+    }
+
+    async fn cfu_write(&mut self, mem_offset: Option<usize>, data: &[u8]) -> Result<(), CfuWriterError> {
+        match mem_offset {
+            Some(offset) => {
+                let address = (self.encode_address)(offset)?;
+                self.transaction(&mut [SpiOperation::Write(&address), SpiOperation::Write(data)])
+                    .await
+            }
+            None => self.transaction(&mut [SpiOperation::Write(data)]).await,
+        }
+    }
+
+    async fn cfu_storage(&mut self, mem_offset: usize, data: &[u8]) -> Result<(), CfuWriterError> {
+        self.cfu_write(Some(mem_offset), data).await
+    }
+}
+
+/// CfuWriterAsync decorator that coalesces `cfu_storage` writes into whole, page aligned programs of PAGE bytes
+///
+/// Content arrives in DEFAULT_DATA_LENGTH sized blocks, which is slow to program and wears flash with larger
+/// pages. Contiguous storage writes are buffered until a page is full and then written with a single call.
+/// A write that doesn't continue the buffered data flushes it first. The buffered tail must be written
+/// with `flush` once the image is complete. Other transactions are forwarded unchanged.
+pub struct PagedStorage<W, const PAGE: usize> {
+    inner: W,
+    page: [u8; PAGE],
+    /// Offset of the start of the buffered page
+    page_base: usize,
+    /// Buffered bytes are page[start..end]
+    start: usize,
+    end: usize,
+}
+
+impl<W: CfuWriterAsync, const PAGE: usize> PagedStorage<W, PAGE> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            page: [0; PAGE],
+            page_base: 0,
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Writes any buffered data that doesn't fill a whole page
+    pub async fn flush(&mut self) -> Result<(), CfuWriterError> {
+        if self.start == self.end {
+            return Ok(());
+        }
+        let data = self.page.get(self.start..self.end).ok_or(CfuWriterError::Other)?;
+        let result = self.inner.cfu_storage(self.page_base + self.start, data).await;
+        self.start = 0;
+        self.end = 0;
+        result
+    }
+
+    /// Flushes the buffered data and returns the inner writer
+    pub async fn finish(mut self) -> Result<W, CfuWriterError> {
+        self.flush().await?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: CfuWriterAsync, const PAGE: usize> CfuWriterAsync for PagedStorage<W, PAGE> {
+    async fn cfu_write_read(
+        &mut self,
+        mem_offset: Option<usize>,
+        data: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), CfuWriterError> {
+        self.inner.cfu_write_read(mem_offset, data, read).await
+    }
+
+    async fn cfu_read(&mut self, mem_offset: Option<usize>, read: &mut [u8]) -> Result<(), CfuWriterError> {
+        self.inner.cfu_read(mem_offset, read).await
+    }
+
+    async fn cfu_write(&mut self, mem_offset: Option<usize>, data: &[u8]) -> Result<(), CfuWriterError> {
+        self.inner.cfu_write(mem_offset, data).await
+    }
+
+    async fn cfu_storage(&mut self, mem_offset: usize, mut data: &[u8]) -> Result<(), CfuWriterError> {
+        if PAGE == 0 {
+            return self.inner.cfu_storage(mem_offset, data).await;
+        }
+        if self.start != self.end && mem_offset != self.page_base + self.end {
+            self.flush().await?;
+        }
+        let mut offset = mem_offset;
+        while !data.is_empty() {
+            if self.start == self.end {
+                self.page_base = offset - offset % PAGE;
+                self.start = offset % PAGE;
+                self.end = self.start;
+            }
+            let len = data.len().min(PAGE - self.end);
+            let (head, rest) = data.split_at(len);
+            self.page
+                .get_mut(self.end..self.end + len)
+                .ok_or(CfuWriterError::Other)?
+                .copy_from_slice(head);
+            self.end += len;
+            offset += len;
+            data = rest;
+            if self.end == PAGE {
+                self.flush().await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    /// Writer that records every storage write
+    #[derive(Default)]
+    struct StorageRecorder {
+        writes: Vec<(usize, Vec<u8>)>,
+    }
+
+    impl CfuWriterAsync for StorageRecorder {
+        async fn cfu_write_read(
+            &mut self,
+            _mem_offset: Option<usize>,
+            _data: &[u8],
+            _read: &mut [u8],
+        ) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        async fn cfu_read(&mut self, _mem_offset: Option<usize>, _read: &mut [u8]) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        async fn cfu_write(&mut self, _mem_offset: Option<usize>, _data: &[u8]) -> Result<(), CfuWriterError> {
+            Ok(())
+        }
+
+        async fn cfu_storage(&mut self, mem_offset: usize, data: &[u8]) -> Result<(), CfuWriterError> {
+            self.writes.push((mem_offset, data.to_vec()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_paged_storage_coalesces_blocks() {
+        let mut storage = PagedStorage::<_, 256>::new(StorageRecorder::default());
+        let image: Vec<u8> = (0..520).map(|i| i as u8).collect();
+        for (i, block) in image.chunks(52).enumerate() {
+            block_on(storage.cfu_storage(i * 52, block)).unwrap();
+        }
+        assert_eq!(storage.inner.writes.len(), 2);
+        let recorder = block_on(storage.finish()).unwrap();
+
+        let layout: Vec<(usize, usize)> = recorder.writes.iter().map(|(o, d)| (*o, d.len())).collect();
+        assert_eq!(layout, std::vec![(0, 256), (256, 256), (512, 8)]);
+        let written: Vec<u8> = recorder.writes.into_iter().flat_map(|(_, d)| d).collect();
+        assert_eq!(written, image);
+    }
+
+    #[test]
+    fn test_paged_storage_unaligned_and_gaps() {
+        let mut storage = PagedStorage::<_, 256>::new(StorageRecorder::default());
+        // starts mid page, so the first program only covers the rest of that page
+        block_on(storage.cfu_storage(0x1000 + 200, &[1; 100])).unwrap();
+        // a gap flushes what was buffered
+        block_on(storage.cfu_storage(0x2000, &[2; 52])).unwrap();
+        let recorder = block_on(storage.finish()).unwrap();
+        let layout: Vec<(usize, usize)> = recorder.writes.iter().map(|(o, d)| (*o, d.len())).collect();
+        assert_eq!(layout, std::vec![(0x1000 + 200, 56), (0x1100, 44), (0x2000, 52)]);
+    }
+
+    /// Writer that fails every transaction with a fixed error and fills reads with a marker
+    #[cfg(feature = "defmt")]
+    struct FailingWriter(CfuWriterError);
+
+    #[cfg(feature = "defmt")]
+    impl CfuWriterAsync for FailingWriter {
+        async fn cfu_write_read(
+            &mut self,
+            _mem_offset: Option<usize>,
+            _data: &[u8],
+            read: &mut [u8],
+        ) -> Result<(), CfuWriterError> {
+            read.fill(0xA5);
+            Err(self.0)
+        }
+
+        async fn cfu_read(&mut self, _mem_offset: Option<usize>, read: &mut [u8]) -> Result<(), CfuWriterError> {
+            read.fill(0xA5);
+            Err(self.0)
+        }
+
+        async fn cfu_write(&mut self, _mem_offset: Option<usize>, _data: &[u8]) -> Result<(), CfuWriterError> {
+            Err(self.0)
+        }
+
+        async fn cfu_storage(&mut self, _mem_offset: usize, _data: &[u8]) -> Result<(), CfuWriterError> {
+            Err(self.0)
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "defmt")]
+    fn test_logging_writer_forwards_results() {
+        let mut read = [0u8; 16];
+        let mut writer = LoggingWriter::new(CfuWriterNop);
+        assert_eq!(block_on(writer.cfu_write_read(Some(4), &[1, 2, 3], &mut read)), Ok(()));
+        assert_eq!(block_on(writer.cfu_read(None, &mut read)), Ok(()));
+        assert_eq!(block_on(writer.cfu_write(None, &[0; 60])), Ok(()));
+        assert_eq!(block_on(writer.cfu_storage(0x1000, &[0; 52])), Ok(()));
+
+        let mut writer = LoggingWriter::new(FailingWriter(CfuWriterError::Timeout));
+        assert_eq!(
+            block_on(writer.cfu_write_read(None, &[1, 2, 3], &mut read)),
+            Err(CfuWriterError::Timeout)
+        );
+        assert_eq!(read, [0xA5; 16]);
+        assert_eq!(block_on(writer.cfu_read(None, &mut [])), Err(CfuWriterError::Timeout));
+        assert_eq!(block_on(writer.cfu_write(None, &[])), Err(CfuWriterError::Timeout));
+        assert_eq!(block_on(writer.cfu_storage(0, &[])), Err(CfuWriterError::Timeout));
+        assert_eq!(writer.into_inner().0, CfuWriterError::Timeout);
+    }
+
+    /// I2C bus that records the address and bytes written by each transaction, and answers reads with 0xA5
+    #[cfg(feature = "embedded-hal-async")]
+    #[derive(Default)]
+    struct MockI2c {
+        transactions: Vec<(u8, Vec<u8>)>,
+        fail: bool,
+    }
+
+    #[cfg(feature = "embedded-hal-async")]
+    impl embedded_hal_async::i2c::ErrorType for MockI2c {
+        type Error = embedded_hal_async::i2c::ErrorKind;
+    }
+
+    #[cfg(feature = "embedded-hal-async")]
+    impl I2c<u8> for MockI2c {
+        async fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), Self::Error> {
+            if self.fail {
+                return Err(embedded_hal_async::i2c::ErrorKind::Bus);
+            }
+            let mut written = Vec::new();
+            for operation in operations {
+                match operation {
+                    Operation::Write(data) => written.extend_from_slice(data),
+                    Operation::Read(read) => read.fill(0xA5),
+                }
+            }
+            self.transactions.push((address, written));
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "embedded-hal-async")]
+    #[test]
+    fn test_i2c_writer() {
+        let mut writer = I2cWriter::new(MockI2c::default(), 0x50);
+        let mut read = [0u8; 4];
+        block_on(writer.cfu_write_read(None, &[1, 2], &mut read)).unwrap();
+        assert_eq!(read, [0xA5; 4]);
+        // the offset is sent as a register address ahead of the data
+        block_on(writer.cfu_write(Some(0x12), &[3, 4])).unwrap();
+        block_on(writer.cfu_storage(0x34, &[5])).unwrap();
+        assert_eq!(
+            block_on(writer.cfu_write(Some(0x100), &[6])),
+            Err(CfuWriterError::ByteConversionError)
+        );
+        assert_eq!(
+            writer.into_inner().transactions,
+            std::vec![
+                (0x50, std::vec![1, 2]),
+                (0x50, std::vec![0x12, 3, 4]),
+                (0x50, std::vec![0x34, 5])
+            ]
+        );
+
+        let mut writer = I2cWriter::with_register_width(MockI2c::default(), 0x51, I2cRegisterWidth::TwoBytes);
+        block_on(writer.cfu_read(Some(0x1234), &mut read)).unwrap();
+        assert_eq!(
+            writer.into_inner().transactions,
+            std::vec![(0x51, std::vec![0x12, 0x34])]
+        );
+
+        let mut writer = I2cWriter::new(
+            MockI2c {
+                fail: true,
+                ..Default::default()
+            },
+            0x50,
+        );
+        assert_eq!(
+            block_on(writer.cfu_write_read(Some(0), &[1], &mut read)),
+            Err(CfuWriterError::TransportError)
+        );
+    }
+
+    /// SPI device that records the bytes written by each transaction, and answers reads with a canned response
+    #[cfg(feature = "embedded-hal-async")]
+    #[derive(Default)]
+    struct MockSpi {
+        transactions: Vec<Vec<u8>>,
+        response: Vec<u8>,
+        fail: bool,
+    }
+
+    #[cfg(feature = "embedded-hal-async")]
+    impl embedded_hal_async::spi::ErrorType for MockSpi {
+        type Error = embedded_hal_async::spi::ErrorKind;
+    }
+
+    #[cfg(feature = "embedded-hal-async")]
+    impl SpiDevice for MockSpi {
+        async fn transaction(&mut self, operations: &mut [SpiOperation<'_, u8>]) -> Result<(), Self::Error> {
+            if self.fail {
+                return Err(embedded_hal_async::spi::ErrorKind::Other);
+            }
+            let mut written = Vec::new();
+            for operation in operations {
+                match operation {
+                    SpiOperation::Write(data) => written.extend_from_slice(data),
+                    SpiOperation::Transfer(read, data) => {
+                        written.extend_from_slice(data);
+                        read.copy_from_slice(&self.response[..read.len()]);
+                    }
+                    SpiOperation::Read(read) => read.copy_from_slice(&self.response[..read.len()]),
+                    SpiOperation::TransferInPlace(_) | SpiOperation::DelayNs(_) => {}
+                }
+            }
+            self.transactions.push(written);
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "embedded-hal-async")]
+    fn spi_address(offset: usize) -> Result<heapless::Vec<u8, SPI_ADDRESS_MAX_LEN>, CfuWriterError> {
+        // write command followed by a 24-bit address
+        let offset = u32::try_from(offset)
+            .ok()
+            .filter(|offset| *offset <= 0xFF_FFFF)
+            .ok_or(CfuWriterError::ByteConversionError)?;
+        let bytes = offset.to_be_bytes();
+        Ok(heapless::Vec::from_slice(&[0x02, bytes[1], bytes[2], bytes[3]]).unwrap())
+    }
+
+    #[cfg(feature = "embedded-hal-async")]
+    #[test]
+    fn test_spi_writer() {
+        let spi = MockSpi {
+            response: std::vec![0xC0, 0xFF, 0xEE, 0x00],
+            ..Default::default()
+        };
+        let mut writer = SpiWriter::new(spi, spi_address);
+        let mut read = [0u8; 4];
+        block_on(writer.cfu_write_read(None, &[1, 2], &mut read)).unwrap();
+        assert_eq!(read, [0xC0, 0xFF, 0xEE, 0x00]);
+
+        let mut read = [0u8; 2];
+        block_on(writer.cfu_read(Some(0x10), &mut read)).unwrap();
+        assert_eq!(read, [0xC0, 0xFF]);
+        block_on(writer.cfu_storage(0x012345, &[3, 4])).unwrap();
+        assert_eq!(
+            block_on(writer.cfu_write(Some(0x0100_0000), &[5])),
+            Err(CfuWriterError::ByteConversionError)
+        );
+        assert_eq!(
+            writer.into_inner().transactions,
+            std::vec![
+                std::vec![1, 2],
+                std::vec![0x02, 0x00, 0x00, 0x10],
+                std::vec![0x02, 0x01, 0x23, 0x45, 3, 4]
+            ]
+        );
+
+        let mut writer = SpiWriter::new(
+            MockSpi {
+                fail: true,
+                ..Default::default()
+            },
+            spi_address,
+        );
+        assert_eq!(
+            block_on(writer.cfu_write(None, &[1])),
+            Err(CfuWriterError::TransportError)
+        );
+    }
+}